@@ -16,7 +16,7 @@ use esp_hal::{
     spi::{master::Spi, SpiMode},
     timer::timg::TimerGroup,
 };
-use esp_hal_mfrc522::{consts::UidSize, debug::MFRC522Debug};
+use esp_hal_mfrc522::debug::MFRC522Debug;
 use log::{debug, error, info};
 
 #[main]
@@ -130,9 +130,9 @@ async fn rfid_task(
 
     loop {
         if mfrc522.picc_is_new_card_present().await.is_ok() {
-            let card = mfrc522.get_card(UidSize::Four).await;
+            let card = mfrc522.get_card().await;
             if let Ok(card) = card {
-                info!("Card UID: {}", card.get_number());
+                info!("Card UID: {card}");
 
                 let mut buff = [0; 18];
                 let mut byte_count = 18;
@@ -0,0 +1,482 @@
+use core::fmt;
+
+use crate::{
+    consts::PCDErrorCode,
+    geometry::BlockAddr,
+    mifare::{KeyType, MifareKey},
+};
+use Access::{KeyA as A, KeyAOrB as AB, KeyB as B, Never as N};
+
+/// Which key allows an operation. Key B readable from trailer can't be used
+/// for authentication, so `KeyAOrB` then means Key A only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Access {
+    Never,
+    KeyA,
+    KeyB,
+    KeyAOrB,
+}
+
+impl Access {
+    pub fn allows(&self, key_type: KeyType) -> bool {
+        matches!(
+            (self, key_type),
+            (Access::KeyAOrB, _) | (Access::KeyA, KeyType::A) | (Access::KeyB, KeyType::B)
+        )
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Access::Never => "never",
+            Access::KeyA => "A",
+            Access::KeyB => "B",
+            Access::KeyAOrB => "A|B",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataOperation {
+    Read,
+    Write,
+    Increment,
+
+    /// Decrement, transfer and restore
+    Decrement,
+}
+
+/// Permissions of data (or value) block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataPermissions {
+    pub read: Access,
+    pub write: Access,
+    pub increment: Access,
+
+    /// Decrement, transfer and restore
+    pub decrement: Access,
+}
+
+impl DataPermissions {
+    pub fn get(&self, operation: DataOperation) -> Access {
+        match operation {
+            DataOperation::Read => self.read,
+            DataOperation::Write => self.write,
+            DataOperation::Increment => self.increment,
+            DataOperation::Decrement => self.decrement,
+        }
+    }
+}
+
+impl fmt::Display for DataPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "read: {}, write: {}, increment: {}, decrement/transfer/restore: {}",
+            self.read, self.write, self.increment, self.decrement
+        )
+    }
+}
+
+/// Permissions of sector trailer (Key A itself is never readable)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrailerPermissions {
+    pub key_a_write: Access,
+    pub access_bits_read: Access,
+    pub access_bits_write: Access,
+    pub key_b_read: Access,
+    pub key_b_write: Access,
+}
+
+impl TrailerPermissions {
+    /// Key B is readable, so it's just data and can't be used to authenticate
+    pub fn key_b_readable(&self) -> bool {
+        self.key_b_read != Access::Never
+    }
+}
+
+impl fmt::Display for TrailerPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key A write: {}, access bits read: {}, access bits write: {}, key B read: {}, key B write: {}",
+            self.key_a_write,
+            self.access_bits_read,
+            self.access_bits_write,
+            self.key_b_read,
+            self.key_b_write
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockPermissions {
+    Data(DataPermissions),
+    Trailer(TrailerPermissions),
+}
+
+const fn data(
+    read: Access,
+    write: Access,
+    increment: Access,
+    decrement: Access,
+) -> DataPermissions {
+    DataPermissions {
+        read,
+        write,
+        increment,
+        decrement,
+    }
+}
+
+const fn trailer(
+    key_a_write: Access,
+    access_bits_read: Access,
+    access_bits_write: Access,
+    key_b_read: Access,
+    key_b_write: Access,
+) -> TrailerPermissions {
+    TrailerPermissions {
+        key_a_write,
+        access_bits_read,
+        access_bits_write,
+        key_b_read,
+        key_b_write,
+    }
+}
+
+/// Data block permissions indexed by C1C2C3 bits
+const DATA_TABLE: [DataPermissions; 8] = [
+    data(AB, AB, AB, AB), // 000 transport configuration
+    data(AB, N, N, AB),   // 001 value block
+    data(AB, N, N, N),    // 010 read only
+    data(B, B, N, N),     // 011
+    data(AB, B, N, N),    // 100
+    data(B, N, N, N),     // 101
+    data(AB, B, B, AB),   // 110 value block
+    data(N, N, N, N),     // 111
+];
+
+/// Trailer permissions indexed by C1C2C3 bits
+const TRAILER_TABLE: [TrailerPermissions; 8] = [
+    trailer(A, A, N, A, A),  // 000
+    trailer(A, A, A, A, A),  // 001 transport configuration
+    trailer(N, A, N, A, N),  // 010
+    trailer(B, AB, B, N, B), // 011
+    trailer(B, AB, N, N, B), // 100
+    trailer(N, AB, B, N, N), // 101
+    trailer(N, AB, N, N, N), // 110
+    trailer(N, AB, N, N, N), // 111
+];
+
+/// MIFARE Classic access conditions (trailer bytes 6..9). Each of 4 groups
+/// (data blocks 0..=2, trailer 3) holds C1C2C3 bits as `0bC1C2C3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccessConditions {
+    groups: [u8; 4],
+}
+
+impl AccessConditions {
+    /// Transport configuration (FF 07 80)
+    pub const TRANSPORT: Self = Self {
+        groups: [0b000, 0b000, 0b000, 0b001],
+    };
+
+    /// Groups are masked to 3 bits
+    pub fn new(groups: [u8; 4]) -> Self {
+        Self {
+            groups: groups.map(|g| g & 0x07),
+        }
+    }
+
+    /// Decodes access bytes (trailer bytes 6..9) and checks inverted copies
+    pub fn parse(bytes: &[u8]) -> Result<Self, PCDErrorCode> {
+        let bytes: &[u8; 3] = bytes
+            .get(..3)
+            .and_then(|b| b.try_into().ok())
+            .ok_or(PCDErrorCode::Invalid)?;
+
+        let conditions = Self::from_bytes_unchecked(bytes);
+        if conditions.to_bytes() != *bytes {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        Ok(conditions)
+    }
+
+    /// Decodes only non-inverted copies of access bits
+    pub fn from_bytes_unchecked(bytes: &[u8; 3]) -> Self {
+        let c1 = bytes[1] >> 4;
+        let c2 = bytes[2] & 0x0F;
+        let c3 = bytes[2] >> 4;
+
+        let mut groups = [0; 4];
+        for (i, group) in groups.iter_mut().enumerate() {
+            *group = (((c1 >> i) & 1) << 2) | (((c2 >> i) & 1) << 1) | ((c3 >> i) & 1);
+        }
+
+        Self { groups }
+    }
+
+    /// Access bytes with inverted copies (without GPB byte 9)
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let (mut c1, mut c2, mut c3) = (0u8, 0u8, 0u8);
+        for (i, group) in self.groups.iter().enumerate() {
+            c1 |= ((group >> 2) & 1) << i;
+            c2 |= ((group >> 1) & 1) << i;
+            c3 |= (group & 1) << i;
+        }
+
+        [
+            ((!c2 & 0x0F) << 4) | (!c1 & 0x0F),
+            (c1 << 4) | (!c3 & 0x0F),
+            (c3 << 4) | c2,
+        ]
+    }
+
+    /// C1C2C3 bits of group (0..=3) as `0bC1C2C3`
+    pub fn group_bits(&self, group: u8) -> u8 {
+        self.groups[group as usize & 0x03] & 0x07
+    }
+
+    /// Permissions of data block group (0..=2)
+    pub fn data(&self, group: u8) -> DataPermissions {
+        DATA_TABLE[self.group_bits(group) as usize]
+    }
+
+    pub fn trailer(&self) -> TrailerPermissions {
+        TRAILER_TABLE[self.group_bits(3) as usize]
+    }
+
+    pub fn block(&self, block: BlockAddr) -> BlockPermissions {
+        match block.access_group() {
+            3 => BlockPermissions::Trailer(self.trailer()),
+            group => BlockPermissions::Data(self.data(group)),
+        }
+    }
+
+    /// Data block group is configured as value block
+    pub fn is_value_block(&self, group: u8) -> bool {
+        group < 3 && matches!(self.group_bits(group), 0b001 | 0b110)
+    }
+
+    /// Encodes permissions, fails if some of them can't be expressed by
+    /// access bits
+    pub fn from_permissions(
+        data: [DataPermissions; 3],
+        trailer: TrailerPermissions,
+    ) -> Result<Self, PCDErrorCode> {
+        let mut groups = [0; 4];
+        for (group, permissions) in groups.iter_mut().zip(data) {
+            *group = DATA_TABLE
+                .iter()
+                .position(|p| *p == permissions)
+                .ok_or(PCDErrorCode::Invalid)? as u8;
+        }
+
+        // 110 and 111 are equal for trailer, first match (110) is used
+        groups[3] = TRAILER_TABLE
+            .iter()
+            .position(|p| *p == trailer)
+            .ok_or(PCDErrorCode::Invalid)? as u8;
+
+        Ok(Self { groups })
+    }
+}
+
+impl Default for AccessConditions {
+    fn default() -> Self {
+        Self::TRANSPORT
+    }
+}
+
+/// Transport value of general purpose byte (trailer byte 9)
+pub const DEFAULT_GPB: u8 = 0x69;
+
+/// Contents of sector trailer block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SectorTrailer {
+    pub key_a: MifareKey,
+    pub access: AccessConditions,
+
+    /// General purpose byte (byte 9), free for user data
+    pub gpb: u8,
+    pub key_b: MifareKey,
+}
+
+impl SectorTrailer {
+    pub fn new(key_a: MifareKey, access: AccessConditions, key_b: MifareKey) -> Self {
+        Self {
+            key_a,
+            access,
+            gpb: DEFAULT_GPB,
+            key_b,
+        }
+    }
+
+    /// Parses trailer block, note that Key A (and not readable Key B) is
+    /// read back as zeros
+    pub fn parse(block: &[u8]) -> Result<Self, PCDErrorCode> {
+        if block.len() < 16 {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let mut key_a = [0; 6];
+        let mut key_b = [0; 6];
+        key_a.copy_from_slice(&block[..6]);
+        key_b.copy_from_slice(&block[10..16]);
+
+        Ok(Self {
+            key_a: MifareKey(key_a),
+            access: AccessConditions::parse(&block[6..9])?,
+            gpb: block[9],
+            key_b: MifareKey(key_b),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut block = [0; 16];
+        block[..6].copy_from_slice(self.key_a.as_bytes());
+        block[6..9].copy_from_slice(&self.access.to_bytes());
+        block[9] = self.gpb;
+        block[10..].copy_from_slice(self.key_b.as_bytes());
+        block
+    }
+
+    pub fn key(&self, key_type: KeyType) -> &MifareKey {
+        match key_type {
+            KeyType::A => &self.key_a,
+            KeyType::B => &self.key_b,
+        }
+    }
+}
+
+impl Default for SectorTrailer {
+    fn default() -> Self {
+        Self::new(
+            MifareKey::DEFAULT,
+            AccessConditions::TRANSPORT,
+            MifareKey::DEFAULT,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_encoding() {
+        assert_eq!(AccessConditions::TRANSPORT.to_bytes(), [0xFF, 0x07, 0x80]);
+        assert_eq!(
+            AccessConditions::parse(&[0xFF, 0x07, 0x80]),
+            Ok(AccessConditions::TRANSPORT)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for bits in 0..8 {
+            for group in 0..4 {
+                let mut groups = [0b000, 0b000, 0b000, 0b001];
+                groups[group] = bits;
+
+                let access = AccessConditions::new(groups);
+                let parsed = AccessConditions::parse(&access.to_bytes()).unwrap();
+                assert_eq!(parsed, access);
+                assert_eq!(parsed.group_bits(group as u8), bits);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_inverted_copy() {
+        for byte in 0..3 {
+            for bit in 0..8 {
+                let mut bytes = AccessConditions::TRANSPORT.to_bytes();
+                bytes[byte] ^= 1 << bit;
+                assert_eq!(AccessConditions::parse(&bytes), Err(PCDErrorCode::Invalid));
+            }
+        }
+
+        assert_eq!(
+            AccessConditions::parse(&[0xFF, 0x07]),
+            Err(PCDErrorCode::Invalid)
+        );
+    }
+
+    // MF1S50yyX datasheet, tables 7 and 8
+    #[test]
+    fn data_permissions() {
+        let expected = [
+            (0b000, [AB, AB, AB, AB]),
+            (0b010, [AB, N, N, N]),
+            (0b100, [AB, B, N, N]),
+            (0b110, [AB, B, B, AB]),
+            (0b001, [AB, N, N, AB]),
+            (0b011, [B, B, N, N]),
+            (0b101, [B, N, N, N]),
+            (0b111, [N, N, N, N]),
+        ];
+
+        for (bits, [read, write, increment, decrement]) in expected {
+            let access = AccessConditions::new([bits, 0, 0, 0b001]);
+            assert_eq!(
+                access.data(0),
+                DataPermissions {
+                    read,
+                    write,
+                    increment,
+                    decrement
+                },
+                "C1C2C3 = {bits:03b}"
+            );
+        }
+    }
+
+    #[test]
+    fn trailer_permissions() {
+        let expected = [
+            (0b000, [A, A, N, A, A]),
+            (0b010, [N, A, N, A, N]),
+            (0b100, [B, AB, N, N, B]),
+            (0b110, [N, AB, N, N, N]),
+            (0b001, [A, A, A, A, A]),
+            (0b011, [B, AB, B, N, B]),
+            (0b101, [N, AB, B, N, N]),
+            (0b111, [N, AB, N, N, N]),
+        ];
+
+        for (bits, [key_a_write, access_bits_read, access_bits_write, key_b_read, key_b_write]) in
+            expected
+        {
+            let access = AccessConditions::new([0, 0, 0, bits]);
+            assert_eq!(
+                access.trailer(),
+                TrailerPermissions {
+                    key_a_write,
+                    access_bits_read,
+                    access_bits_write,
+                    key_b_read,
+                    key_b_write
+                },
+                "C1C2C3 = {bits:03b}"
+            );
+        }
+    }
+
+    #[test]
+    fn value_block_groups() {
+        let access = AccessConditions::new([0b001, 0b110, 0b000, 0b001]);
+        assert!(access.is_value_block(0));
+        assert!(access.is_value_block(1));
+        assert!(!access.is_value_block(2));
+        assert!(!access.is_value_block(3));
+    }
+}
@@ -0,0 +1,446 @@
+use embedded_hal::digital::OutputPin;
+
+use crate::{consts::PCDErrorCode, iso_dep::IsoDep, MFRC522};
+
+const INS_GET_RESPONSE: u8 = 0xC0;
+
+/// Longest command sent by [`MFRC522::transmit_apdu`] (short APDU with 255
+/// data bytes and Le). Extended commands with more data have to be encoded
+/// into caller's buffer and sent by [`MFRC522::transmit_apdu_raw`].
+pub const MAX_COMMAND_LEN: usize = 261;
+
+/// Max number of GET RESPONSE rounds for one command
+const MAX_GET_RESPONSE: u8 = 16;
+
+/// Command APDU (ISO 7816-4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command<'a> {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: &'a [u8],
+
+    /// Expected response length, `Some(0)` means maximum (256 or 65536)
+    pub le: Option<u32>,
+}
+
+impl<'a> Command<'a> {
+    pub fn new(cla: u8, ins: u8, p1: u8, p2: u8) -> Self {
+        Self {
+            cla,
+            ins,
+            p1,
+            p2,
+            data: &[],
+            le: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: &'a [u8]) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn with_le(mut self, le: u32) -> Self {
+        self.le = Some(le);
+        self
+    }
+
+    /// Extended length is used only if data or Le don't fit into short APDU
+    pub fn is_extended(&self) -> bool {
+        self.data.len() > 255 || self.le.is_some_and(|le| le > 256)
+    }
+
+    /// Length of encoded command
+    pub fn encoded_len(&self) -> usize {
+        let extended = self.is_extended();
+        4 + match (self.data.len(), extended) {
+            (0, _) => 0,
+            (n, false) => 1 + n,
+            (n, true) => 3 + n,
+        } + match (self.le, extended, self.data.is_empty()) {
+            (None, _, _) => 0,
+            (Some(_), false, _) => 1,
+            (Some(_), true, true) => 3,
+            (Some(_), true, false) => 2,
+        }
+    }
+
+    /// Encodes command into `out`, returns encoded length
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, PCDErrorCode> {
+        if self.data.len() > 65535 || self.le.is_some_and(|le| le > 65536) {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let extended = self.is_extended();
+        if out.len() < self.encoded_len() {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        out[..4].copy_from_slice(&[self.cla, self.ins, self.p1, self.p2]);
+        let mut index = 4;
+
+        if !self.data.is_empty() {
+            if extended {
+                out[index] = 0;
+                out[index + 1..index + 3].copy_from_slice(&(self.data.len() as u16).to_be_bytes());
+                index += 3;
+            } else {
+                out[index] = self.data.len() as u8;
+                index += 1;
+            }
+
+            out[index..index + self.data.len()].copy_from_slice(self.data);
+            index += self.data.len();
+        }
+
+        if let Some(le) = self.le {
+            if extended {
+                if self.data.is_empty() {
+                    out[index] = 0;
+                    index += 1;
+                }
+
+                // 65536 is encoded as 0x0000
+                out[index..index + 2].copy_from_slice(&(le as u16).to_be_bytes());
+                index += 2;
+            } else {
+                // 256 is encoded as 0x00
+                out[index] = le as u8;
+                index += 1;
+            }
+        }
+
+        Ok(index)
+    }
+}
+
+/// Decoded SW1/SW2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// 9000
+    Success,
+
+    /// 61XX - XX more bytes available (GET RESPONSE)
+    BytesAvailable(u8),
+
+    /// 62XX / 63XX - warning, state of non-volatile memory unchanged/changed
+    Warning(u16),
+
+    /// 63CX - verification failed, X retries left
+    VerificationFailed(u8),
+
+    /// 6700
+    WrongLength,
+
+    /// 6982
+    SecurityNotSatisfied,
+
+    /// 6983
+    AuthMethodBlocked,
+
+    /// 6985
+    ConditionsNotSatisfied,
+
+    /// 6A80
+    IncorrectData,
+
+    /// 6A82
+    FileNotFound,
+
+    /// 6A86
+    IncorrectP1P2,
+
+    /// 6CXX - wrong Le, XX is the exact length
+    WrongLe(u8),
+
+    /// 6D00
+    InsNotSupported,
+
+    /// 6E00
+    ClaNotSupported,
+
+    /// Other error/proprietary status
+    Other(u16),
+}
+
+impl Status {
+    pub fn from_sw(sw1: u8, sw2: u8) -> Self {
+        match (sw1, sw2) {
+            (0x90, 0x00) => Self::Success,
+            (0x61, n) => Self::BytesAvailable(n),
+            (0x63, n) if n & 0xF0 == 0xC0 => Self::VerificationFailed(n & 0x0F),
+            (0x62 | 0x63, _) => Self::Warning(u16::from_be_bytes([sw1, sw2])),
+            (0x67, 0x00) => Self::WrongLength,
+            (0x69, 0x82) => Self::SecurityNotSatisfied,
+            (0x69, 0x83) => Self::AuthMethodBlocked,
+            (0x69, 0x85) => Self::ConditionsNotSatisfied,
+            (0x6A, 0x80) => Self::IncorrectData,
+            (0x6A, 0x82) => Self::FileNotFound,
+            (0x6A, 0x86) => Self::IncorrectP1P2,
+            (0x6C, n) => Self::WrongLe(n),
+            (0x6D, 0x00) => Self::InsNotSupported,
+            (0x6E, 0x00) => Self::ClaNotSupported,
+            _ => Self::Other(u16::from_be_bytes([sw1, sw2])),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Success)
+    }
+}
+
+/// Response APDU, `data` borrows buffer passed to [`MFRC522::transmit_apdu`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response<'a> {
+    pub data: &'a [u8],
+    pub sw1: u8,
+    pub sw2: u8,
+}
+
+impl<'a> Response<'a> {
+    pub fn sw(&self) -> u16 {
+        u16::from_be_bytes([self.sw1, self.sw2])
+    }
+
+    pub fn status(&self) -> Status {
+        Status::from_sw(self.sw1, self.sw2)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status().is_success()
+    }
+
+    /// Response data, or [`PCDErrorCode::ApduStatus`] if status isn't 9000
+    pub fn into_result(self) -> Result<&'a [u8], PCDErrorCode> {
+        match self.is_success() {
+            true => Ok(self.data),
+            false => Err(PCDErrorCode::ApduStatus(self.sw())),
+        }
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Sends command APDU over ISO-DEP session and returns response.
+    ///
+    /// 61XX is handled by sending GET RESPONSE (data is concatenated) and
+    /// 6CXX by repeating command with Le = XX.
+    /// Commands longer than [`MAX_COMMAND_LEN`] fail with `NoRoom`.
+    pub async fn transmit_apdu<'b>(
+        &mut self,
+        iso: &mut IsoDep,
+        cmd: &Command<'_>,
+        back_data: &'b mut [u8],
+    ) -> Result<Response<'b>, PCDErrorCode> {
+        if cmd.encoded_len() > MAX_COMMAND_LEN {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        let mut apdu = [0; MAX_COMMAND_LEN];
+        let len = cmd.encode(&mut apdu)?;
+
+        let mut received = self.transmit_apdu_raw(iso, &apdu[..len], back_data).await?;
+
+        if let Status::WrongLe(le) = status_of(&back_data[..received]) {
+            let cmd = Command {
+                le: Some(le as u32),
+                ..*cmd
+            };
+            let len = cmd.encode(&mut apdu)?;
+            received = self.transmit_apdu_raw(iso, &apdu[..len], back_data).await?;
+        }
+
+        let mut rounds = 0;
+        while let Status::BytesAvailable(le) = status_of(&back_data[..received]) {
+            rounds += 1;
+            if rounds > MAX_GET_RESPONSE {
+                return Err(PCDErrorCode::Protocol);
+            }
+
+            // drop SW of previous response, append next part of data
+            received -= 2;
+            let get_response = Command::new(cmd.cla, INS_GET_RESPONSE, 0, 0).with_le(le as u32);
+            let len = get_response.encode(&mut apdu)?;
+
+            received += self
+                .transmit_apdu_raw(iso, &apdu[..len], &mut back_data[received..])
+                .await?;
+        }
+
+        let back_data: &'b [u8] = back_data;
+        let (data, sw) = back_data[..received].split_at(received - 2);
+        Ok(Response {
+            data,
+            sw1: sw[0],
+            sw2: sw[1],
+        })
+    }
+
+    /// Sends already encoded APDU, returns response length (including SW)
+    pub async fn transmit_apdu_raw(
+        &mut self,
+        iso: &mut IsoDep,
+        apdu: &[u8],
+        back_data: &mut [u8],
+    ) -> Result<usize, PCDErrorCode> {
+        let len = self.iso_dep_transceive(iso, apdu, back_data).await?;
+        if len < 2 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        Ok(len)
+    }
+}
+
+fn status_of(response: &[u8]) -> Status {
+    let len = response.len();
+    Status::from_sw(response[len - 2], response[len - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<'a>(cmd: &Command, out: &'a mut [u8]) -> &'a [u8] {
+        let len = cmd.encode(out).unwrap();
+        assert_eq!(len, cmd.encoded_len());
+        &out[..len]
+    }
+
+    #[test]
+    fn encode_short() {
+        let mut out = [0; MAX_COMMAND_LEN];
+        let cmd = Command::new(0x00, 0xA4, 0x04, 0x00);
+
+        // case 1
+        assert_eq!(encode(&cmd, &mut out), &[0x00, 0xA4, 0x04, 0x00]);
+
+        // case 2, 256 (or 0 = max) is encoded as 0x00
+        assert_eq!(
+            encode(&cmd.with_le(16), &mut out),
+            &[0x00, 0xA4, 0x04, 0x00, 0x10]
+        );
+        assert_eq!(
+            encode(&cmd.with_le(256), &mut out),
+            &[0x00, 0xA4, 0x04, 0x00, 0x00]
+        );
+        assert_eq!(
+            encode(&cmd.with_le(0), &mut out),
+            &[0x00, 0xA4, 0x04, 0x00, 0x00]
+        );
+
+        // case 3
+        let cmd = cmd.with_data(&[0xA0, 0x00, 0x01]);
+        assert_eq!(
+            encode(&cmd, &mut out),
+            &[0x00, 0xA4, 0x04, 0x00, 0x03, 0xA0, 0x00, 0x01]
+        );
+
+        // case 4
+        assert_eq!(
+            encode(&cmd.with_le(0), &mut out),
+            &[0x00, 0xA4, 0x04, 0x00, 0x03, 0xA0, 0x00, 0x01, 0x00]
+        );
+
+        // the longest short command
+        let data = [0x55; 255];
+        let cmd = Command::new(0x00, 0xD6, 0x00, 0x00)
+            .with_data(&data)
+            .with_le(256);
+        assert!(!cmd.is_extended());
+        assert_eq!(encode(&cmd, &mut out).len(), MAX_COMMAND_LEN);
+    }
+
+    #[test]
+    fn encode_extended() {
+        let mut out = [0; 512];
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00);
+
+        // case 2, 65536 is encoded as 0x0000
+        assert_eq!(
+            encode(&cmd.with_le(300), &mut out),
+            &[0x00, 0xB0, 0x00, 0x00, 0x00, 0x01, 0x2C]
+        );
+        assert_eq!(
+            encode(&cmd.with_le(65536), &mut out),
+            &[0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+
+        // case 3
+        let data = [0x55; 300];
+        let cmd = cmd.with_data(&data);
+        let encoded = encode(&cmd, &mut out);
+        assert_eq!(encoded.len(), 7 + 300);
+        assert_eq!(&encoded[4..7], &[0x00, 0x01, 0x2C]);
+
+        // case 4, Le without leading zero
+        let encoded = encode(&cmd.with_le(65536), &mut out);
+        assert_eq!(encoded.len(), 7 + 300 + 2);
+        assert_eq!(&encoded[307..], &[0x00, 0x00]);
+
+        // short data, extended Le
+        let encoded = encode(&cmd.with_data(&[0x01]).with_le(1000), &mut out);
+        assert_eq!(
+            encoded,
+            &[0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x03, 0xE8]
+        );
+    }
+
+    #[test]
+    fn encode_errors() {
+        let cmd = Command::new(0x00, 0xB0, 0x00, 0x00);
+        assert_eq!(
+            cmd.with_le(65537).encode(&mut [0; 16]),
+            Err(PCDErrorCode::Invalid)
+        );
+        assert_eq!(
+            cmd.with_le(0).encode(&mut [0; 4]),
+            Err(PCDErrorCode::NoRoom)
+        );
+
+        // doesn't fit into buffer of `transmit_apdu`
+        let data = [0x55; 300];
+        assert!(cmd.with_data(&data).encoded_len() > MAX_COMMAND_LEN);
+    }
+
+    #[test]
+    fn status_words() {
+        let cases = [
+            ((0x90, 0x00), Status::Success),
+            ((0x61, 0x10), Status::BytesAvailable(0x10)),
+            ((0x62, 0x83), Status::Warning(0x6283)),
+            ((0x63, 0x00), Status::Warning(0x6300)),
+            ((0x63, 0xC2), Status::VerificationFailed(2)),
+            ((0x67, 0x00), Status::WrongLength),
+            ((0x69, 0x82), Status::SecurityNotSatisfied),
+            ((0x69, 0x83), Status::AuthMethodBlocked),
+            ((0x69, 0x85), Status::ConditionsNotSatisfied),
+            ((0x6A, 0x80), Status::IncorrectData),
+            ((0x6A, 0x82), Status::FileNotFound),
+            ((0x6A, 0x86), Status::IncorrectP1P2),
+            ((0x6C, 0x20), Status::WrongLe(0x20)),
+            ((0x6D, 0x00), Status::InsNotSupported),
+            ((0x6E, 0x00), Status::ClaNotSupported),
+            ((0x91, 0xAE), Status::Other(0x91AE)),
+        ];
+
+        for ((sw1, sw2), status) in cases {
+            assert_eq!(Status::from_sw(sw1, sw2), status);
+        }
+
+        let response = Response {
+            data: &[0x01],
+            sw1: 0x6A,
+            sw2: 0x82,
+        };
+        assert_eq!(
+            response.into_result(),
+            Err(PCDErrorCode::ApduStatus(0x6A82))
+        );
+    }
+}
@@ -1,31 +1,49 @@
 // FROM: https://github.com/OSSLibraries/Arduino_MFRC522v2/blob/master/src/MFRC522Constants.h
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Uid {
     pub size: u8,
     pub uid_bytes: [u8; 10],
     pub sak: u8,
+    pub atqa: Atqa,
 }
 
-impl Uid {
-    pub fn get_number(&self) -> u128 {
-        match self.size {
-            4 => u32::from_le_bytes(self.uid_bytes[..4].try_into().unwrap_or([0, 0, 0, 0])) as u128,
-            7 => {
-                let mut bytes = [0; 8];
-                bytes[..7].copy_from_slice(&self.uid_bytes[..7]);
-                u64::from_le_bytes(bytes) as u128
-            }
-            10 => {
-                let mut bytes = [0; 16];
-                bytes[..10].copy_from_slice(&self.uid_bytes[..10]);
-                u128::from_le_bytes(bytes)
-            }
-            _ => {
-                log::error!("Wrong bytes count!");
-                unreachable!() // i dont think that this case is reachable
-            }
-        }
+/// Answer To Request (type A), bytes in the order they were received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Atqa {
+    pub bytes: [u8; 2],
+}
+
+impl Atqa {
+    pub fn from_bytes(bytes: [u8; 2]) -> Self {
+        Self { bytes }
+    }
+
+    /// ATQA as it's usually written in datasheets (e.g. 0x0044)
+    pub fn as_u16(&self) -> u16 {
+        u16::from_le_bytes(self.bytes)
+    }
+
+    /// UID size announced by the PICC (b8..b7), `None` if RFU
+    pub fn uid_size(&self) -> Option<UidSize> {
+        UidSize::from_atqa(self.bytes[0])
+    }
+
+    /// Bit frame anticollision bits (b5..b1), exactly one bit should be set
+    pub fn bit_frame_anticollision(&self) -> u8 {
+        self.bytes[0] & 0x1F
+    }
+
+    /// ISO 14443-3 requires exactly one bit of bit frame anticollision to be set
+    pub fn is_bit_frame_anticollision_valid(&self) -> bool {
+        self.bit_frame_anticollision().count_ones() == 1
+    }
+
+    /// Proprietary coding (b12..b9)
+    pub fn proprietary_coding(&self) -> u8 {
+        self.bytes[1] & 0x0F
     }
 }
 
@@ -146,6 +164,8 @@ impl PICCCommand {
                                                // The commands used for MIFARE Ultralight (from http://www.nxp.com/documents/data_sheet/MF0ICU1.pdf, Section 8.6)
                                                // The PICC_CMD_MF_READ and PICC_CMD_MF_WRITE can also be used for MIFARE Ultralight.
     pub const PICC_CMD_UL_WRITE: u8 = 0xA2; // Writes one 4 byte page to the PICC.
+    pub const PICC_CMD_UL_GET_VERSION: u8 = 0x60; // Returns product version (Ultralight EV1, NTAG21x).
+    pub const PICC_CMD_UL_AUTHENTICATE: u8 = 0x1A; // 3DES authentication (Ultralight C only).
 }
 
 #[derive(Debug, PartialEq)]
@@ -177,7 +197,7 @@ impl PCDVersion {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum PICCType {
     PiccTypeUnknown = 0xff,
@@ -212,7 +232,7 @@ impl PICCType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PCDErrorCode {
     /// Error in communication
     Error,
@@ -240,8 +260,28 @@ pub enum PCDErrorCode {
 
     /// MIFARE PICC responded with NAK
     MifareNack,
+
+    /// Unexpected block received from PICC (ISO 14443-4)
+    Protocol,
+
+    /// Authentication with the PICC failed
+    AuthenticationFailed,
+
+    /// MAC or CRC of secured message doesn't match
+    IntegrityError,
+
+    /// DESFire PICC returned error status (see `desfire::DesfireStatus`)
+    Desfire(u8),
+
+    /// MIFARE Plus PICC returned error status (see `plus::PlusStatus`)
+    MifarePlus(u8),
+
+    /// PICC returned error status word (ISO 7816-4 SW1/SW2)
+    ApduStatus(u16),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UidSize {
     Four,
     Seven,
@@ -256,4 +296,24 @@ impl UidSize {
             Self::Ten => 10,
         }
     }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            4 => Some(Self::Four),
+            7 => Some(Self::Seven),
+            10 => Some(Self::Ten),
+            _ => None,
+        }
+    }
+
+    /// Decodes uid size bits (b8..b7) of the first ATQA byte.
+    /// `None` for the RFU value (0b11).
+    pub fn from_atqa(atqa_lsb: u8) -> Option<Self> {
+        match atqa_lsb >> 6 {
+            0b00 => Some(Self::Four),
+            0b01 => Some(Self::Seven),
+            0b10 => Some(Self::Ten),
+            _ => None,
+        }
+    }
 }
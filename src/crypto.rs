@@ -0,0 +1,361 @@
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+#[cfg(feature = "desfire")]
+use des::{Des, TdesEde2, TdesEde3};
+
+pub(crate) const MAX_BLOCK_SIZE: usize = 16;
+
+// no allocator to box key schedules, size is fine for stack
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Cipher {
+    #[cfg(feature = "desfire")]
+    Des(Des),
+    #[cfg(feature = "desfire")]
+    TdesEde2(TdesEde2),
+    #[cfg(feature = "desfire")]
+    TdesEde3(TdesEde3),
+    Aes(Aes128),
+}
+
+impl core::fmt::Debug for Cipher {
+    // never print key material
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "desfire")]
+            Cipher::Des(_) => f.write_str("Des"),
+            #[cfg(feature = "desfire")]
+            Cipher::TdesEde2(_) => f.write_str("TdesEde2"),
+            #[cfg(feature = "desfire")]
+            Cipher::TdesEde3(_) => f.write_str("TdesEde3"),
+            Cipher::Aes(_) => f.write_str("Aes"),
+        }
+    }
+}
+
+impl Cipher {
+    #[cfg(feature = "desfire")]
+    pub fn des(key: &[u8; 8]) -> Self {
+        Cipher::Des(Des::new(GenericArray::from_slice(key)))
+    }
+
+    #[cfg(feature = "desfire")]
+    pub fn tdes_ede2(key: &[u8; 16]) -> Self {
+        Cipher::TdesEde2(TdesEde2::new(GenericArray::from_slice(key)))
+    }
+
+    #[cfg(feature = "desfire")]
+    pub fn tdes_ede3(key: &[u8; 24]) -> Self {
+        Cipher::TdesEde3(TdesEde3::new(GenericArray::from_slice(key)))
+    }
+
+    pub fn aes(key: &[u8; 16]) -> Self {
+        Cipher::Aes(Aes128::new(GenericArray::from_slice(key)))
+    }
+
+    pub fn block_size(&self) -> usize {
+        match self {
+            Cipher::Aes(_) => 16,
+            #[cfg(feature = "desfire")]
+            _ => 8,
+        }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8]) {
+        match self {
+            #[cfg(feature = "desfire")]
+            Cipher::Des(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            #[cfg(feature = "desfire")]
+            Cipher::TdesEde2(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            #[cfg(feature = "desfire")]
+            Cipher::TdesEde3(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+            Cipher::Aes(c) => c.encrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8]) {
+        match self {
+            #[cfg(feature = "desfire")]
+            Cipher::Des(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            #[cfg(feature = "desfire")]
+            Cipher::TdesEde2(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            #[cfg(feature = "desfire")]
+            Cipher::TdesEde3(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+            Cipher::Aes(c) => c.decrypt_block(GenericArray::from_mut_slice(block)),
+        }
+    }
+
+    /// CBC encryption in place, `iv` is updated to last ciphertext block.
+    /// `data` length has to be multiple of block size.
+    pub fn cbc_encrypt(&self, iv: &mut [u8], data: &mut [u8]) {
+        let bs = self.block_size();
+        for block in data.chunks_exact_mut(bs) {
+            xor(block, &iv[..bs]);
+            self.encrypt_block(block);
+            iv[..bs].copy_from_slice(block);
+        }
+    }
+
+    /// CBC decryption in place, `iv` is updated to last ciphertext block
+    pub fn cbc_decrypt(&self, iv: &mut [u8], data: &mut [u8]) {
+        let bs = self.block_size();
+        let mut next_iv = [0; MAX_BLOCK_SIZE];
+        for block in data.chunks_exact_mut(bs) {
+            next_iv[..bs].copy_from_slice(block);
+            self.decrypt_block(block);
+            xor(block, &iv[..bs]);
+            iv[..bs].copy_from_slice(&next_iv[..bs]);
+        }
+    }
+
+    /// DESFire (D40) "send mode": CBC where blocks are deciphered instead
+    /// of enciphered, IV is always zero
+    #[cfg(feature = "desfire")]
+    pub fn legacy_send(&self, data: &mut [u8]) {
+        let bs = self.block_size();
+        let mut prev = [0; MAX_BLOCK_SIZE];
+        for block in data.chunks_exact_mut(bs) {
+            xor(block, &prev[..bs]);
+            self.decrypt_block(block);
+            prev[..bs].copy_from_slice(block);
+        }
+    }
+
+    /// CMAC (NIST SP 800-38B) starting from `iv` (zero for plain CMAC),
+    /// full MAC is left in `iv`
+    pub fn cmac(&self, iv: &mut [u8], data: &[u8]) {
+        let bs = self.block_size();
+        let (k1, k2) = self.cmac_subkeys();
+
+        let full_last = !data.is_empty() && data.len() % bs == 0;
+        let last_start = if data.is_empty() {
+            0
+        } else {
+            (data.len() - 1) / bs * bs
+        };
+
+        for block in data[..last_start].chunks_exact(bs) {
+            xor(&mut iv[..bs], block);
+            self.encrypt_block(&mut iv[..bs]);
+        }
+
+        let mut last = [0; MAX_BLOCK_SIZE];
+        let rest = &data[last_start..];
+        last[..rest.len()].copy_from_slice(rest);
+        if full_last {
+            xor(&mut last[..bs], &k1[..bs]);
+        } else {
+            last[rest.len()] = 0x80;
+            xor(&mut last[..bs], &k2[..bs]);
+        }
+
+        xor(&mut iv[..bs], &last[..bs]);
+        self.encrypt_block(&mut iv[..bs]);
+    }
+
+    fn cmac_subkeys(&self) -> ([u8; MAX_BLOCK_SIZE], [u8; MAX_BLOCK_SIZE]) {
+        let bs = self.block_size();
+        let rb = if bs == 16 { 0x87 } else { 0x1B };
+
+        let mut l = [0; MAX_BLOCK_SIZE];
+        self.encrypt_block(&mut l[..bs]);
+
+        let k1 = shift_left(&l[..bs], rb);
+        let k2 = shift_left(&k1[..bs], rb);
+        (k1, k2)
+    }
+}
+
+fn shift_left(input: &[u8], rb: u8) -> [u8; MAX_BLOCK_SIZE] {
+    let mut out = [0; MAX_BLOCK_SIZE];
+    let len = input.len();
+
+    let mut carry = 0;
+    for (out, byte) in out[..len].iter_mut().zip(input).rev() {
+        *out = (byte << 1) | carry;
+        carry = byte >> 7;
+    }
+
+    if carry != 0 {
+        out[len - 1] ^= rb;
+    }
+
+    out
+}
+
+pub(crate) fn xor(data: &mut [u8], other: &[u8]) {
+    data.iter_mut().zip(other).for_each(|(a, b)| *a ^= b);
+}
+
+/// CRC32 used by DESFire EV1 (IEEE 802.3 without final XOR)
+#[cfg(feature = "desfire")]
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |mut crc, &b| {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+
+        crc
+    })
+}
+
+#[cfg(feature = "desfire")]
+pub(crate) const CRC32_INIT: u32 = 0xFFFF_FFFF;
+
+/// CRC_A (ISO 14443-3) calculated in software
+#[cfg(feature = "desfire")]
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0x6363, |mut crc: u16, &b| {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NIST SP 800-38B examples
+    const AES_KEY: [u8; 16] = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+    const MESSAGE: [u8; 64] = [
+        0x6B, 0xC1, 0xBE, 0xE2, 0x2E, 0x40, 0x9F, 0x96, 0xE9, 0x3D, 0x7E, 0x11, 0x73, 0x93, 0x17,
+        0x2A, 0xAE, 0x2D, 0x8A, 0x57, 0x1E, 0x03, 0xAC, 0x9C, 0x9E, 0xB7, 0x6F, 0xAC, 0x45, 0xAF,
+        0x8E, 0x51, 0x30, 0xC8, 0x1C, 0x46, 0xA3, 0x5C, 0xE4, 0x11, 0xE5, 0xFB, 0xC1, 0x19, 0x1A,
+        0x0A, 0x52, 0xEF, 0xF6, 0x9F, 0x24, 0x45, 0xDF, 0x4F, 0x9B, 0x17, 0xAD, 0x2B, 0x41, 0x7B,
+        0xE6, 0x6C, 0x37, 0x10,
+    ];
+
+    fn cmac(cipher: &Cipher, data: &[u8]) -> [u8; MAX_BLOCK_SIZE] {
+        let mut mac = [0; MAX_BLOCK_SIZE];
+        cipher.cmac(&mut mac, data);
+        mac
+    }
+
+    #[test]
+    fn aes_cmac_subkeys() {
+        let (k1, k2) = Cipher::aes(&AES_KEY).cmac_subkeys();
+        assert_eq!(
+            k1,
+            [
+                0xFB, 0xEE, 0xD6, 0x18, 0x35, 0x71, 0x33, 0x66, 0x7C, 0x85, 0xE0, 0x8F, 0x72, 0x36,
+                0xA8, 0xDE,
+            ]
+        );
+        assert_eq!(
+            k2,
+            [
+                0xF7, 0xDD, 0xAC, 0x30, 0x6A, 0xE2, 0x66, 0xCC, 0xF9, 0x0B, 0xC1, 0x1E, 0xE4, 0x6D,
+                0x51, 0x3B,
+            ]
+        );
+    }
+
+    #[test]
+    fn aes_cmac() {
+        let cipher = Cipher::aes(&AES_KEY);
+        let cases: [(usize, [u8; 16]); 4] = [
+            (
+                0,
+                [
+                    0xBB, 0x1D, 0x69, 0x29, 0xE9, 0x59, 0x37, 0x28, 0x7F, 0xA3, 0x7D, 0x12, 0x9B,
+                    0x75, 0x67, 0x46,
+                ],
+            ),
+            (
+                16,
+                [
+                    0x07, 0x0A, 0x16, 0xB4, 0x6B, 0x4D, 0x41, 0x44, 0xF7, 0x9B, 0xDD, 0x9D, 0xD0,
+                    0x4A, 0x28, 0x7C,
+                ],
+            ),
+            (
+                40,
+                [
+                    0xDF, 0xA6, 0x67, 0x47, 0xDE, 0x9A, 0xE6, 0x30, 0x30, 0xCA, 0x32, 0x61, 0x14,
+                    0x97, 0xC8, 0x27,
+                ],
+            ),
+            (
+                64,
+                [
+                    0x51, 0xF0, 0xBE, 0xBF, 0x7E, 0x3B, 0x9D, 0x92, 0xFC, 0x49, 0x74, 0x17, 0x79,
+                    0x36, 0x3C, 0xFE,
+                ],
+            ),
+        ];
+
+        for (len, mac) in cases {
+            assert_eq!(cmac(&cipher, &MESSAGE[..len]), mac, "length {len}");
+        }
+    }
+
+    #[cfg(feature = "desfire")]
+    #[test]
+    fn tdes_cmac() {
+        let cipher = Cipher::tdes_ede3(&[
+            0x8A, 0xA8, 0x3B, 0xF8, 0xCB, 0xDA, 0x10, 0x62, 0x0B, 0xC1, 0xBF, 0x19, 0xFB, 0xB6,
+            0xCD, 0x58, 0xBC, 0x31, 0x3D, 0x4A, 0x37, 0x1C, 0xA8, 0xB5,
+        ]);
+        let cases: [(usize, [u8; 8]); 4] = [
+            (0, [0xB7, 0xA6, 0x88, 0xE1, 0x22, 0xFF, 0xAF, 0x95]),
+            (16, [0x28, 0x6D, 0x39, 0x46, 0x73, 0x44, 0x81, 0x97]),
+            (20, [0x74, 0x3D, 0xDB, 0xE0, 0xCE, 0x2D, 0xC2, 0xED]),
+            (32, [0x33, 0xE6, 0xB1, 0x09, 0x24, 0x00, 0xEA, 0xE5]),
+        ];
+
+        for (len, mac) in cases {
+            assert_eq!(cmac(&cipher, &MESSAGE[..len])[..8], mac, "length {len}");
+        }
+    }
+
+    #[test]
+    fn cbc_round_trip() {
+        let cipher = Cipher::aes(&AES_KEY);
+        let mut data = MESSAGE;
+
+        let mut iv = [0; MAX_BLOCK_SIZE];
+        cipher.cbc_encrypt(&mut iv, &mut data);
+        assert_eq!(iv, data[48..]);
+
+        let mut iv = [0; MAX_BLOCK_SIZE];
+        cipher.cbc_decrypt(&mut iv, &mut data);
+        assert_eq!(data, MESSAGE);
+    }
+
+    #[cfg(feature = "desfire")]
+    #[test]
+    fn crc16_vectors() {
+        // ISO 14443-3 Annex B
+        assert_eq!(crc16(&[0x00, 0x00]).to_le_bytes(), [0xA0, 0x1E]);
+        assert_eq!(crc16(&[0x12, 0x34]).to_le_bytes(), [0x26, 0xCF]);
+    }
+
+    #[cfg(feature = "desfire")]
+    #[test]
+    fn crc32_vectors() {
+        // standard CRC32 check value 0xCBF43926 without final XOR
+        assert_eq!(crc32(CRC32_INIT, b"123456789"), 0x340B_C6D9);
+        assert_eq!(crc32(CRC32_INIT, b""), CRC32_INIT);
+
+        // can be computed in parts
+        let crc = crc32(CRC32_INIT, b"1234");
+        assert_eq!(crc32(crc, b"56789"), 0x340B_C6D9);
+    }
+}
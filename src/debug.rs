@@ -1,7 +1,14 @@
 use core::fmt::Write;
 
+#[cfg(feature = "desfire")]
+use crate::desfire::{CommMode, Desfire, FileType};
 use crate::{
-    consts::{PCDErrorCode, PICCCommand, PICCType, Uid},
+    access::AccessConditions,
+    consts::{PCDErrorCode, PICCType, Uid},
+    dictionary::KeyMap,
+    dump::{CardDump, CardMemory, ClassicDump, ReadStatus, UltralightDump},
+    geometry::{BlockAddr, ClassicSize},
+    mifare::MifareKey,
     MFRC522,
 };
 use embedded_hal::digital::OutputPin;
@@ -43,18 +50,24 @@ where
             PICCType::PiccTypeMifare1K
             | PICCType::PiccTypeMifare4K
             | PICCType::PiccTypeMifareMini => {
-                let mifare_key = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
-                let res = dump_mifare_classic(self, uid, &mifare_key, picc_type).await;
+                let res = dump_memory(self, uid, picc_type).await;
                 if let Err(e) = res {
                     log::error!("Dump mifare classic failed: {e:?}");
                 }
             }
             PICCType::PiccTypeMifareUL => {
-                let res = dump_mifare_ultralight(self).await;
+                let res = dump_memory(self, uid, picc_type).await;
                 if let Err(e) = res {
                     log::error!("Dump mifare ultralight failed: {e:?}");
                 }
             }
+            #[cfg(feature = "desfire")]
+            PICCType::PiccTypeIso14443_4 => {
+                let res = dump_desfire(self).await;
+                if let Err(e) = res {
+                    log::error!("Dump DESFire failed: {e:?}");
+                }
+            }
             PICCType::PiccTypeUnknown | PICCType::PiccTypeNotComplete => {
                 return Ok(());
             }
@@ -74,6 +87,7 @@ where
         }
 
         log::debug!("Card UID:{dbg_line_buff}");
+        log::debug!("Card ATQA: 0x{:04X}", uid.atqa.as_u16());
         log::debug!("Card SAK: 0x{:02X}", uid.sak);
         log::debug!("PICC Type: {:?}", PICCType::from_sak(uid.sak));
 
@@ -81,168 +95,210 @@ where
     }
 }
 
-async fn dump_mifare_classic<S: SpiDevice, C: OutputPin>(
+/// Logs dump of card memory, MIFARE Classic sectors are read only with factory
+/// default key (use `mifare_check_keys` with dictionary and `dump_card` for more)
+async fn dump_memory<S: SpiDevice, C: OutputPin>(
     mfrc522: &mut MFRC522<S, C>,
     uid: &Uid,
-    key: &[u8],
     picc_type: PICCType,
 ) -> Result<(), PCDErrorCode> {
-    let sectors_count = match picc_type {
-        PICCType::PiccTypeMifareMini => 5,
-        PICCType::PiccTypeMifare1K => 16,
-        PICCType::PiccTypeMifare4K => 40,
-        _ => unreachable!(),
-    };
+    let mut key_map = KeyMap::default();
+    if let Some(size) = ClassicSize::from_picc_type(&picc_type) {
+        mfrc522
+            .mifare_check_keys(uid, size, &[MifareKey::DEFAULT], &mut key_map, |_| {})
+            .await?;
+    }
 
-    log::debug!("Sector Block   0  1  2  3   4  5  6  7   8  9 10 11  12 13 14 15  AccessBits");
-    for i in (0..sectors_count).rev() {
-        let res = dump_mifare_classic_sector(mfrc522, uid, key, i).await;
-        if let Err(e) = res {
-            log::error!("Sector dump error ({i}): {e:?}");
-        }
+    let mut dump = CardDump::new(uid.clone());
+    mfrc522.dump_card(uid, &key_map, &mut dump).await?;
+    match &dump.memory {
+        CardMemory::Classic(classic) => log_classic_dump(classic),
+        CardMemory::Ultralight(ultralight) => log_ultralight_dump(ultralight),
+        CardMemory::Unsupported => {}
     }
 
-    mfrc522.picc_halta().await?;
-    mfrc522.pcd_stop_crypto1().await?;
     Ok(())
 }
 
-async fn dump_mifare_classic_sector<S: SpiDevice, C: OutputPin>(
-    mfrc522: &mut MFRC522<S, C>,
-    uid: &Uid,
-    key: &[u8],
-    sector: u8,
-) -> Result<(), PCDErrorCode> {
-    let mut groups = [0; 4];
-    let mut inverted_error = false;
-
-    let first_block;
-    let no_of_blocks;
-    if sector < 32 {
-        no_of_blocks = 4;
-        first_block = sector * no_of_blocks;
-    } else if sector < 40 {
-        no_of_blocks = 16;
-        first_block = 128 + (sector - 32) * no_of_blocks;
-    } else {
-        return Err(PCDErrorCode::Invalid);
-    }
-
-    let mut is_sector_trailer = true;
-    let mut buff = [0; 18];
+fn log_classic_dump(dump: &ClassicDump) {
+    log::debug!("Sector Block   0  1  2  3   4  5  6  7   8  9 10 11  12 13 14 15  AccessBits");
 
     let mut dbg_line_buff: String<128> = String::new();
-    for block_offset in (0..no_of_blocks).rev() {
-        dbg_line_buff.clear();
+    for sector in dump.size.sectors().rev() {
+        let trailer = dump.block(sector.trailer());
+        if trailer.status == ReadStatus::NoKey {
+            log::debug!("  {: >2}    No known key", sector.index());
+            continue;
+        }
 
-        let block_addr = first_block + block_offset;
-        if is_sector_trailer {
-            mfrc522
-                .pcd_authenticate(PICCCommand::PICC_CMD_MF_AUTH_KEY_A, first_block, &key, &uid)
-                .await?;
+        let bytes = [trailer.data[6], trailer.data[7], trailer.data[8]];
+        let inverted_error = AccessConditions::parse(&bytes).is_err();
+        let access = AccessConditions::from_bytes_unchecked(&bytes);
 
-            _ = dbg_line_buff.write_fmt(format_args!("  {sector: >2}    "));
-        } else {
-            _ = dbg_line_buff.push_str("        ");
-        }
+        for block in sector.blocks().rev() {
+            dbg_line_buff.clear();
 
-        _ = dbg_line_buff.write_fmt(format_args!("{block_addr: >3}   "));
+            if block.is_trailer() {
+                _ = dbg_line_buff.write_fmt(format_args!("  {: >2}    ", sector.index()));
+            } else {
+                _ = dbg_line_buff.push_str("        ");
+            }
 
-        let mut byte_count = 18;
-        mfrc522
-            .mifare_read(block_addr, &mut buff, &mut byte_count)
-            .await?;
+            _ = dbg_line_buff.write_fmt(format_args!("{: >3}   ", block.0));
 
-        for i in 0..16 {
-            _ = dbg_line_buff.write_fmt(format_args!("{:02X} ", buff[i]));
-            if i % 4 == 3 {
-                _ = dbg_line_buff.push(' ');
+            let block_dump = dump.block(block);
+            if !block_dump.is_read() {
+                _ = dbg_line_buff.write_fmt(format_args!("{:?}", block_dump.status));
+                log::debug!("{}", dbg_line_buff);
+                continue;
             }
-        }
 
-        if is_sector_trailer {
-            let c1 = buff[7] >> 4;
-            let c2 = buff[8] & 0xF;
-            let c3 = buff[8] >> 4;
-            let c1_ = buff[6] & 0xF;
-            let c2_ = buff[6] >> 4;
-            let c3_ = buff[7] & 0xF;
-
-            inverted_error = (c1 != (!c1_ & 0xF)) || (c2 != (!c2_ & 0xF)) || (c3 != (!c3_ & 0xF));
-            groups[0] = ((c1 & 1) << 2) | ((c2 & 1) << 1) | ((c3 & 1) << 0);
-            groups[1] = ((c1 & 2) << 1) | ((c2 & 2) << 0) | ((c3 & 2) >> 1);
-            groups[2] = ((c1 & 4) << 0) | ((c2 & 4) >> 1) | ((c3 & 4) >> 2);
-            groups[3] = ((c1 & 8) >> 1) | ((c2 & 8) >> 2) | ((c3 & 8) >> 3);
-
-            is_sector_trailer = false;
-        }
+            for (i, byte) in block_dump.data.iter().enumerate() {
+                _ = dbg_line_buff.write_fmt(format_args!("{byte:02X} "));
+                if i % 4 == 3 {
+                    _ = dbg_line_buff.push(' ');
+                }
+            }
 
-        let first_in_group;
-        let group;
+            // blocks are printed backwards, so group starts at its last block
+            let group = block.access_group();
+            let first_in_group =
+                block.is_trailer() || BlockAddr(block.0 + 1).access_group() != group;
 
-        if no_of_blocks == 4 {
-            group = block_offset;
-            first_in_group = true;
-        } else {
-            group = block_offset / 5;
-            first_in_group = (group == 3) || (group != (block_offset + 1) / 5);
-        }
+            if first_in_group && trailer.is_read() {
+                let bits = access.group_bits(group);
+                let (g1, g2, g3) = ((bits >> 2) & 1, (bits >> 1) & 1, bits & 1);
 
-        if first_in_group {
-            let (g1, g2, g3) = (
-                (groups[group as usize] >> 2) & 1,
-                (groups[group as usize] >> 1) & 1,
-                (groups[group as usize] >> 0) & 1,
-            );
+                _ = core::fmt::write(&mut dbg_line_buff, format_args!("[ {g1} {g2} {g3} ]"));
+                if inverted_error {
+                    _ = dbg_line_buff.push_str(" Inverted access bits did not match! ");
+                }
+            }
 
-            _ = core::fmt::write(&mut dbg_line_buff, format_args!("[ {g1} {g2} {g3} ]"));
-            if inverted_error {
-                _ = dbg_line_buff.push_str(" Inverted access bits did not match! ");
+            if let Some(value) = dump.value(block) {
+                _ = dbg_line_buff.write_fmt(format_args!(
+                    " Value=0x{:X} Adr=0x{:X}",
+                    value.value, value.addr
+                ));
             }
+
+            log::debug!("{}", dbg_line_buff);
         }
+    }
+}
+
+fn log_ultralight_dump(dump: &UltralightDump) {
+    log::debug!("Page   0  1  2  3");
 
-        if group != 3 && (groups[group as usize] == 1 || groups[group as usize] == 6) {
-            let val = ((buff[3] as i32) << 24)
-                | ((buff[2] as i32) << 16)
-                | ((buff[1] as i32) << 8)
-                | (buff[0] as i32);
+    let mut dbg_line_buff: String<32> = String::new();
+    for (page, page_dump) in dump.pages().iter().enumerate() {
+        dbg_line_buff.clear();
+        _ = dbg_line_buff.write_fmt(format_args!("{page: >3}  "));
 
-            _ = dbg_line_buff.write_fmt(format_args!(" Value=0x{val:X} Adr=0x{:X}", buff[12]));
+        if page_dump.is_read() {
+            for byte in page_dump.data {
+                _ = dbg_line_buff.write_fmt(format_args!(" {byte:02X}"));
+            }
+        } else {
+            _ = dbg_line_buff.write_fmt(format_args!(" {:?}", page_dump.status));
         }
 
         log::debug!("{}", dbg_line_buff);
     }
-
-    Ok(())
 }
 
-async fn dump_mifare_ultralight<S: SpiDevice, C: OutputPin>(
+#[cfg(feature = "desfire")]
+async fn dump_desfire<S: SpiDevice, C: OutputPin>(
     mfrc522: &mut MFRC522<S, C>,
 ) -> Result<(), PCDErrorCode> {
-    let mut buff = [0; 18];
-    let mut i;
+    let iso = mfrc522.iso_dep_activate(None).await?;
+    let mut desfire = Desfire::new(iso);
+
+    let res = dump_desfire_applications(mfrc522, &mut desfire).await;
+    _ = mfrc522.iso_dep_deselect(&mut desfire.iso).await;
+    res
+}
 
-    log::debug!("Page  0  1  2  3");
+/// Lists applications and files, content is dumped only for files with free
+/// read access in plain communication mode
+#[cfg(feature = "desfire")]
+async fn dump_desfire_applications<S: SpiDevice, C: OutputPin>(
+    mfrc522: &mut MFRC522<S, C>,
+    desfire: &mut Desfire,
+) -> Result<(), PCDErrorCode> {
+    let version = mfrc522.desfire_get_version(desfire).await?;
+    log::debug!(
+        "DESFire HW: {}.{}, SW: {}.{}, storage: {} bytes",
+        version.hardware.major,
+        version.hardware.minor,
+        version.software.major,
+        version.software.minor,
+        version.hardware.storage_bytes()
+    );
+
+    let aids = mfrc522.desfire_get_application_ids(desfire).await?;
     let mut dbg_line_buff: String<128> = String::new();
-    for page in (0..16).step_by(4) {
-        dbg_line_buff.clear();
+    for aid in aids {
+        log::debug!("Application {aid:06X}");
+        mfrc522.desfire_select_application(desfire, aid).await?;
+
+        let files = match mfrc522.desfire_get_file_ids(desfire).await {
+            Ok(files) => files,
+            Err(e) => {
+                log::debug!("  Files: {e:?}");
+                continue;
+            }
+        };
+
+        for file_no in files {
+            let settings = match mfrc522.desfire_get_file_settings(desfire, file_no).await {
+                Ok(settings) => settings,
+                Err(e) => {
+                    log::debug!("  File {file_no:02X}: {e:?}");
+                    continue;
+                }
+            };
 
-        let mut bytes_count = 18;
-        mfrc522
-            .mifare_read(page, &mut buff, &mut bytes_count)
-            .await?;
+            log::debug!(
+                "  File {file_no:02X}: {:?} {:?} {:?}",
+                settings.file_type,
+                settings.comm_mode,
+                settings.access_rights
+            );
 
-        for offset in 0..4 {
-            i = page + offset;
-            _ = dbg_line_buff.write_fmt(format_args!(" {i: >2}  "));
+            if settings.comm_mode != CommMode::Plain || !settings.access_rights.is_free_read() {
+                continue;
+            }
 
-            for index in 0..4 {
-                i = 4 * offset + index;
-                _ = dbg_line_buff.write_fmt(format_args!(" {i:02X}  "));
+            match settings.file_type {
+                FileType::Standard { size } | FileType::Backup { size } => {
+                    let mut buff = [0; 32];
+                    let len = (size as usize).min(buff.len());
+                    let res = mfrc522
+                        .desfire_read_data(desfire, file_no, 0, &mut buff[..len], CommMode::Plain)
+                        .await;
+
+                    dbg_line_buff.clear();
+                    match res {
+                        Ok(_) => {
+                            for byte in &buff[..len] {
+                                _ = dbg_line_buff.write_fmt(format_args!(" {byte:02X}"));
+                            }
+                        }
+                        Err(e) => _ = dbg_line_buff.write_fmt(format_args!(" {e:?}")),
+                    }
+
+                    log::debug!("    Data:{dbg_line_buff}");
+                }
+                FileType::Value(_) => {
+                    let res = mfrc522
+                        .desfire_get_value(desfire, file_no, CommMode::Plain)
+                        .await;
+                    log::debug!("    Value: {res:?}");
+                }
+                _ => {}
             }
         }
-
-        log::debug!("{}", dbg_line_buff);
     }
 
     Ok(())
@@ -0,0 +1,1564 @@
+use embedded_hal::digital::OutputPin;
+use heapless::Vec;
+use rand_core::RngCore;
+
+use crate::{
+    consts::PCDErrorCode,
+    crypto::{self, Cipher, CRC32_INIT, MAX_BLOCK_SIZE},
+    identify::VersionInfo,
+    iso_dep::IsoDep,
+    MFRC522,
+};
+
+const CMD_AUTHENTICATE_LEGACY: u8 = 0x0A;
+const CMD_AUTHENTICATE_ISO: u8 = 0x1A;
+const CMD_AUTHENTICATE_AES: u8 = 0xAA;
+const CMD_AUTHENTICATE_EV2_FIRST: u8 = 0x71;
+const CMD_GET_VERSION: u8 = 0x60;
+const CMD_GET_APPLICATION_IDS: u8 = 0x6A;
+const CMD_SELECT_APPLICATION: u8 = 0x5A;
+const CMD_GET_FILE_IDS: u8 = 0x6F;
+const CMD_GET_FILE_SETTINGS: u8 = 0xF5;
+const CMD_READ_DATA: u8 = 0xBD;
+const CMD_WRITE_DATA: u8 = 0x3D;
+const CMD_GET_VALUE: u8 = 0x6C;
+const CMD_CREDIT: u8 = 0x0C;
+const CMD_DEBIT: u8 = 0xDC;
+const CMD_READ_RECORDS: u8 = 0xBB;
+const CMD_COMMIT_TRANSACTION: u8 = 0xC7;
+const CMD_ABORT_TRANSACTION: u8 = 0xA7;
+
+/// Max length of single native frame (command/status byte + data)
+const MAX_FRAME_LEN: usize = 59;
+
+/// Max response frame (ISO-DEP FSD)
+const RESPONSE_FRAME_LEN: usize = 64;
+
+/// Longer reads/writes are split into several commands
+const MAX_DATA_CHUNK: usize = 192;
+
+/// Chunk + command header + CRC/MAC + padding
+const MESSAGE_BUFFER_LEN: usize = MAX_DATA_CHUNK + 48;
+
+/// Commands accessing files, EV2 secures them by communication mode of file
+/// (other commands are always MACed)
+const FILE_COMMANDS: [u8; 6] = [
+    CMD_READ_DATA,
+    CMD_WRITE_DATA,
+    CMD_GET_VALUE,
+    CMD_CREDIT,
+    CMD_DEBIT,
+    CMD_READ_RECORDS,
+];
+
+pub const MAX_APPLICATIONS: usize = 28;
+pub const MAX_FILES: usize = 32;
+
+/// DESFire status codes (first byte of every response)
+pub struct DesfireStatus;
+
+impl DesfireStatus {
+    pub const OPERATION_OK: u8 = 0x00;
+    pub const NO_CHANGES: u8 = 0x0C;
+    pub const OUT_OF_EEPROM: u8 = 0x0E;
+    pub const ILLEGAL_COMMAND: u8 = 0x1C;
+    pub const INTEGRITY_ERROR: u8 = 0x1E;
+    pub const NO_SUCH_KEY: u8 = 0x40;
+    pub const LENGTH_ERROR: u8 = 0x7E;
+    pub const PERMISSION_DENIED: u8 = 0x9D;
+    pub const PARAMETER_ERROR: u8 = 0x9E;
+    pub const APPLICATION_NOT_FOUND: u8 = 0xA0;
+    pub const AUTHENTICATION_ERROR: u8 = 0xAE;
+    pub const ADDITIONAL_FRAME: u8 = 0xAF;
+    pub const BOUNDARY_ERROR: u8 = 0xBE;
+    pub const COMMAND_ABORTED: u8 = 0xCA;
+    pub const DUPLICATE_ERROR: u8 = 0xDE;
+    pub const FILE_NOT_FOUND: u8 = 0xF0;
+}
+
+/// Communication mode of a file (or command)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommMode {
+    Plain,
+    Mac,
+    Encrypted,
+}
+
+impl CommMode {
+    /// Decodes communication settings byte of file settings
+    pub fn from_byte(byte: u8) -> Self {
+        match byte & 0x03 {
+            0x01 => CommMode::Mac,
+            0x03 => CommMode::Encrypted,
+            _ => CommMode::Plain,
+        }
+    }
+}
+
+/// DESFire key, DES key is the same as 2K3DES key with equal halves
+#[derive(Clone, PartialEq, Eq)]
+pub enum DesfireKey {
+    Des([u8; 8]),
+    TwoK3Des([u8; 16]),
+    ThreeK3Des([u8; 24]),
+    Aes([u8; 16]),
+}
+
+impl core::fmt::Debug for DesfireKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DesfireKey::Des(_) => f.write_str("Des(..)"),
+            DesfireKey::TwoK3Des(_) => f.write_str("TwoK3Des(..)"),
+            DesfireKey::ThreeK3Des(_) => f.write_str("ThreeK3Des(..)"),
+            DesfireKey::Aes(_) => f.write_str("Aes(..)"),
+        }
+    }
+}
+
+impl DesfireKey {
+    fn cipher(&self) -> Cipher {
+        match self {
+            DesfireKey::Des(key) => Cipher::des(key),
+            DesfireKey::TwoK3Des(key) => Cipher::tdes_ede2(key),
+            DesfireKey::ThreeK3Des(key) => Cipher::tdes_ede3(key),
+            DesfireKey::Aes(key) => Cipher::aes(key),
+        }
+    }
+
+    /// Length of RndA/RndB used in authentication
+    fn random_len(&self) -> usize {
+        match self {
+            DesfireKey::Des(_) | DesfireKey::TwoK3Des(_) => 8,
+            DesfireKey::ThreeK3Des(_) | DesfireKey::Aes(_) => 16,
+        }
+    }
+
+    fn session_cipher(&self, rnd_a: &[u8], rnd_b: &[u8]) -> Cipher {
+        let mut key = [0; 24];
+        key[..4].copy_from_slice(&rnd_a[..4]);
+        key[4..8].copy_from_slice(&rnd_b[..4]);
+
+        match self {
+            DesfireKey::Des(_) => Cipher::des(key[..8].try_into().unwrap()),
+            DesfireKey::TwoK3Des(_) => {
+                key[8..12].copy_from_slice(&rnd_a[4..8]);
+                key[12..16].copy_from_slice(&rnd_b[4..8]);
+                Cipher::tdes_ede2(key[..16].try_into().unwrap())
+            }
+            DesfireKey::ThreeK3Des(_) => {
+                key[8..12].copy_from_slice(&rnd_a[6..10]);
+                key[12..16].copy_from_slice(&rnd_b[6..10]);
+                key[16..20].copy_from_slice(&rnd_a[12..16]);
+                key[20..24].copy_from_slice(&rnd_b[12..16]);
+                Cipher::tdes_ede3(&key)
+            }
+            DesfireKey::Aes(_) => {
+                key[8..12].copy_from_slice(&rnd_a[12..16]);
+                key[12..16].copy_from_slice(&rnd_b[12..16]);
+                Cipher::aes(key[..16].try_into().unwrap())
+            }
+        }
+    }
+}
+
+/// GetVersion response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DesfireVersion {
+    pub hardware: VersionInfo,
+    pub software: VersionInfo,
+    pub uid: [u8; 7],
+    pub batch: [u8; 5],
+    pub production_week: u8,
+    pub production_year: u8,
+}
+
+/// Access rights of a file, key numbers (0x0E - free access, 0x0F - denied)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessRights {
+    pub read: u8,
+    pub write: u8,
+    pub read_write: u8,
+    pub change: u8,
+}
+
+impl AccessRights {
+    pub const FREE: u8 = 0x0E;
+    pub const DENY: u8 = 0x0F;
+
+    pub fn from_u16(value: u16) -> Self {
+        Self {
+            read: ((value >> 12) & 0x0F) as u8,
+            write: ((value >> 8) & 0x0F) as u8,
+            read_write: ((value >> 4) & 0x0F) as u8,
+            change: (value & 0x0F) as u8,
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        ((self.read as u16) << 12)
+            | ((self.write as u16) << 8)
+            | ((self.read_write as u16) << 4)
+            | self.change as u16
+    }
+
+    /// File can be read without authentication
+    pub fn is_free_read(&self) -> bool {
+        self.read == Self::FREE || self.read_write == Self::FREE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueSettings {
+    pub lower_limit: i32,
+    pub upper_limit: i32,
+    pub limited_credit_value: i32,
+    pub limited_credit_enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordSettings {
+    pub record_size: u32,
+    pub max_records: u32,
+    pub current_records: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Standard { size: u32 },
+    Backup { size: u32 },
+    Value(ValueSettings),
+    LinearRecord(RecordSettings),
+    CyclicRecord(RecordSettings),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSettings {
+    pub file_type: FileType,
+    pub comm_mode: CommMode,
+    pub access_rights: AccessRights,
+}
+
+impl FileSettings {
+    pub fn parse(bytes: &[u8]) -> Result<Self, PCDErrorCode> {
+        if bytes.len() < 7 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        let comm_mode = CommMode::from_byte(bytes[1]);
+        let access_rights = AccessRights::from_u16(u16::from_le_bytes([bytes[2], bytes[3]]));
+        let rest = &bytes[4..];
+
+        let record = |rest: &[u8]| -> Result<RecordSettings, PCDErrorCode> {
+            if rest.len() < 9 {
+                return Err(PCDErrorCode::Protocol);
+            }
+
+            Ok(RecordSettings {
+                record_size: u24_from_le(&rest[0..3]),
+                max_records: u24_from_le(&rest[3..6]),
+                current_records: u24_from_le(&rest[6..9]),
+            })
+        };
+
+        let file_type = match bytes[0] {
+            0x00 => FileType::Standard {
+                size: u24_from_le(&rest[0..3]),
+            },
+            0x01 => FileType::Backup {
+                size: u24_from_le(&rest[0..3]),
+            },
+            0x02 => {
+                if rest.len() < 13 {
+                    return Err(PCDErrorCode::Protocol);
+                }
+
+                FileType::Value(ValueSettings {
+                    lower_limit: i32::from_le_bytes(rest[0..4].try_into().unwrap()),
+                    upper_limit: i32::from_le_bytes(rest[4..8].try_into().unwrap()),
+                    limited_credit_value: i32::from_le_bytes(rest[8..12].try_into().unwrap()),
+                    limited_credit_enabled: rest[12] & 0x01 != 0,
+                })
+            }
+            0x03 => FileType::LinearRecord(record(rest)?),
+            0x04 => FileType::CyclicRecord(record(rest)?),
+            _ => return Err(PCDErrorCode::Protocol),
+        };
+
+        Ok(Self {
+            file_type,
+            comm_mode,
+            access_rights,
+        })
+    }
+}
+
+/// Secure messaging of authenticated session
+#[derive(Debug)]
+enum Messaging {
+    /// DESFire D40 (legacy authentication)
+    Legacy,
+
+    /// EV1 (ISO and AES authentication)
+    Ev1,
+    Ev2(Ev2Session),
+}
+
+/// Authenticated session, every command after authentication is
+/// secured with session key
+#[derive(Debug)]
+struct Session {
+    key_no: u8,
+
+    /// Session key (encryption key of EV2)
+    cipher: Cipher,
+    iv: [u8; MAX_BLOCK_SIZE],
+    messaging: Messaging,
+}
+
+impl Session {
+    /// Applies secure messaging to `message[..len]` (command, header and
+    /// data starting at `data_start`), returns new message length
+    fn wrap(&mut self, message: &mut [u8], data_start: usize, len: usize, mode: CommMode) -> usize {
+        let bs = self.cipher.block_size();
+        let legacy = match &self.messaging {
+            Messaging::Legacy => true,
+            Messaging::Ev1 => false,
+            Messaging::Ev2(ev2) => return ev2.wrap(&self.cipher, message, data_start, len, mode),
+        };
+
+        match (mode, legacy) {
+            (CommMode::Plain, true) => len,
+            (CommMode::Plain, false) => {
+                // response MAC is chained with command MAC
+                self.cipher.cmac(&mut self.iv, &message[..len]);
+                len
+            }
+            (CommMode::Mac, true) => {
+                let mac = self.legacy_mac(&message[data_start..len]);
+                message[len..len + 4].copy_from_slice(&mac);
+                len + 4
+            }
+            (CommMode::Mac, false) => {
+                self.cipher.cmac(&mut self.iv, &message[..len]);
+                message[len..len + 8].copy_from_slice(&self.iv[..8]);
+                len + 8
+            }
+            (CommMode::Encrypted, true) => {
+                let crc = crypto::crc16(&message[data_start..len]);
+                message[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+                let end = data_start + (len + 2 - data_start).next_multiple_of(bs);
+                message[len + 2..end].fill(0);
+                self.cipher.legacy_send(&mut message[data_start..end]);
+                end
+            }
+            (CommMode::Encrypted, false) => {
+                let crc = crypto::crc32(CRC32_INIT, &message[..len]);
+                message[len..len + 4].copy_from_slice(&crc.to_le_bytes());
+
+                let end = data_start + (len + 4 - data_start).next_multiple_of(bs);
+                message[len + 4..end].fill(0);
+                self.cipher
+                    .cbc_encrypt(&mut self.iv, &mut message[data_start..end]);
+                end
+            }
+        }
+    }
+
+    /// Verifies/decrypts response data in place, returns length of plain data
+    fn unwrap(&mut self, data: &mut [u8], mode: CommMode) -> Result<usize, PCDErrorCode> {
+        let bs = self.cipher.block_size();
+        let len = data.len();
+        let legacy = match &mut self.messaging {
+            Messaging::Legacy => true,
+            Messaging::Ev1 => false,
+            Messaging::Ev2(ev2) => return ev2.unwrap(&self.cipher, data, mode),
+        };
+
+        match (mode, legacy) {
+            (CommMode::Plain, true) => Ok(len),
+            (CommMode::Plain | CommMode::Mac, false) => {
+                // CMAC over data + status
+                let Some(n) = len.checked_sub(8) else {
+                    return Err(PCDErrorCode::IntegrityError);
+                };
+
+                let mut mac = [0; 8];
+                mac.copy_from_slice(&data[n..]);
+                data[n] = DesfireStatus::OPERATION_OK;
+
+                self.cipher.cmac(&mut self.iv, &data[..n + 1]);
+                if self.iv[..8] != mac {
+                    return Err(PCDErrorCode::IntegrityError);
+                }
+
+                Ok(n)
+            }
+            (CommMode::Mac, true) => {
+                let Some(n) = len.checked_sub(4) else {
+                    return Err(PCDErrorCode::IntegrityError);
+                };
+
+                if self.legacy_mac(&data[..n]) != data[n..] {
+                    return Err(PCDErrorCode::IntegrityError);
+                }
+
+                Ok(n)
+            }
+            (CommMode::Encrypted, legacy) => {
+                if len == 0 || len % bs != 0 {
+                    return Err(PCDErrorCode::IntegrityError);
+                }
+
+                if legacy {
+                    let mut iv = [0; MAX_BLOCK_SIZE];
+                    self.cipher.cbc_decrypt(&mut iv, data);
+
+                    find_data_len(data, bs, 2, |data, crc| {
+                        crypto::crc16(data).to_le_bytes() == crc
+                    })
+                } else {
+                    self.cipher.cbc_decrypt(&mut self.iv, data);
+
+                    find_data_len(data, bs, 4, |data, crc| {
+                        let res = crypto::crc32(CRC32_INIT, data);
+                        let res = crypto::crc32(res, &[DesfireStatus::OPERATION_OK]);
+                        res.to_le_bytes() == crc
+                    })
+                }
+            }
+        }
+    }
+
+    /// 4 byte CBC-MAC (D40 secure messaging)
+    fn legacy_mac(&self, data: &[u8]) -> [u8; 4] {
+        let bs = self.cipher.block_size();
+        let mut iv = [0; MAX_BLOCK_SIZE];
+
+        for chunk in data.chunks(bs) {
+            crypto::xor(&mut iv[..chunk.len()], chunk);
+            self.cipher.encrypt_block(&mut iv[..bs]);
+        }
+
+        [iv[0], iv[1], iv[2], iv[3]]
+    }
+}
+
+/// EV2 secure messaging state, every command and response is bound to
+/// transaction identifier and command counter
+struct Ev2Session {
+    mac_key: [u8; 16],
+
+    /// Transaction identifier
+    ti: [u8; 4],
+    cmd_ctr: u16,
+}
+
+impl core::fmt::Debug for Ev2Session {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Ev2Session")
+            .field("ti", &self.ti)
+            .field("cmd_ctr", &self.cmd_ctr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Ev2Session {
+    /// Derives session keys (SV1/SV2 CMAC), returns encryption key and
+    /// session
+    fn new(key: &Cipher, rnd_a: &[u8; 16], rnd_b: &[u8; 16], ti: [u8; 4]) -> (Cipher, Self) {
+        let mut sv = [0; 32];
+        sv[..6].copy_from_slice(&[0xA5, 0x5A, 0x00, 0x01, 0x00, 0x80]);
+        sv[6..8].copy_from_slice(&rnd_a[..2]);
+        sv[8..14].copy_from_slice(&rnd_a[2..8]);
+        crypto::xor(&mut sv[8..14], &rnd_b[..6]);
+        sv[14..24].copy_from_slice(&rnd_b[6..]);
+        sv[24..].copy_from_slice(&rnd_a[8..]);
+
+        let mut enc_key = [0; 16];
+        key.cmac(&mut enc_key, &sv);
+
+        sv[..2].copy_from_slice(&[0x5A, 0xA5]);
+        let mut mac_key = [0; 16];
+        key.cmac(&mut mac_key, &sv);
+
+        let session = Self {
+            mac_key,
+            ti,
+            cmd_ctr: 0,
+        };
+
+        (Cipher::aes(&enc_key), session)
+    }
+
+    /// Command: `Cmd || MACt(Cmd || CmdCtr || TI || header || data)`, data
+    /// of encrypted command is encrypted (ISO 9797-1 M2 padding) first
+    fn wrap(
+        &self,
+        cipher: &Cipher,
+        message: &mut [u8],
+        data_start: usize,
+        len: usize,
+        mode: CommMode,
+    ) -> usize {
+        if mode == CommMode::Plain {
+            return len;
+        }
+
+        let mut len = len;
+        if mode == CommMode::Encrypted && len > data_start {
+            let end = data_start + (len + 1 - data_start).next_multiple_of(16);
+            message[len] = 0x80;
+            message[len + 1..end].fill(0);
+
+            let mut iv = self.iv(cipher, [0xA5, 0x5A], self.cmd_ctr);
+            cipher.cbc_encrypt(&mut iv, &mut message[data_start..end]);
+            len = end;
+        }
+
+        let mac = self.mac(message[0], self.cmd_ctr, &message[1..len]);
+        message[len..len + 8].copy_from_slice(&mac);
+        len + 8
+    }
+
+    /// Response: `data || MACt(status || CmdCtr || TI || data)` with
+    /// incremented CmdCtr, data of encrypted response is decrypted in place
+    fn unwrap(
+        &mut self,
+        cipher: &Cipher,
+        data: &mut [u8],
+        mode: CommMode,
+    ) -> Result<usize, PCDErrorCode> {
+        // counter is incremented by every command, card refuses overflow
+        self.cmd_ctr = self
+            .cmd_ctr
+            .checked_add(1)
+            .ok_or(PCDErrorCode::IntegrityError)?;
+
+        if mode == CommMode::Plain {
+            return Ok(data.len());
+        }
+
+        let Some(n) = data.len().checked_sub(8) else {
+            return Err(PCDErrorCode::IntegrityError);
+        };
+
+        if self.mac(DesfireStatus::OPERATION_OK, self.cmd_ctr, &data[..n]) != data[n..] {
+            return Err(PCDErrorCode::IntegrityError);
+        }
+
+        if mode == CommMode::Mac || n == 0 {
+            return Ok(n);
+        }
+
+        if n % 16 != 0 {
+            return Err(PCDErrorCode::IntegrityError);
+        }
+
+        let mut iv = self.iv(cipher, [0x5A, 0xA5], self.cmd_ctr);
+        cipher.cbc_decrypt(&mut iv, &mut data[..n]);
+
+        match data[..n].iter().rposition(|&b| b != 0) {
+            Some(end) if data[end] == 0x80 => Ok(end),
+            _ => Err(PCDErrorCode::IntegrityError),
+        }
+    }
+
+    /// `E(label || TI || CmdCtr || 0..0)`
+    fn iv(&self, cipher: &Cipher, label: [u8; 2], cmd_ctr: u16) -> [u8; MAX_BLOCK_SIZE] {
+        let mut iv = [0; MAX_BLOCK_SIZE];
+        iv[..2].copy_from_slice(&label);
+        iv[2..6].copy_from_slice(&self.ti);
+        iv[6..8].copy_from_slice(&cmd_ctr.to_le_bytes());
+        cipher.encrypt_block(&mut iv);
+        iv
+    }
+
+    /// CMAC of `first || CmdCtr || TI || data` truncated to odd bytes
+    fn mac(&self, first: u8, cmd_ctr: u16, data: &[u8]) -> [u8; 8] {
+        let mut input = [0; 7 + MESSAGE_BUFFER_LEN];
+        input[0] = first;
+        input[1..3].copy_from_slice(&cmd_ctr.to_le_bytes());
+        input[3..7].copy_from_slice(&self.ti);
+        input[7..7 + data.len()].copy_from_slice(data);
+
+        let mut mac = [0; MAX_BLOCK_SIZE];
+        Cipher::aes(&self.mac_key).cmac(&mut mac, &input[..7 + data.len()]);
+        core::array::from_fn(|i| mac[2 * i + 1])
+    }
+}
+
+/// DESFire application layer on top of ISO-DEP session
+#[derive(Debug)]
+pub struct Desfire {
+    pub iso: IsoDep,
+    session: Option<Session>,
+}
+
+impl Desfire {
+    pub fn new(iso: IsoDep) -> Self {
+        Self { iso, session: None }
+    }
+
+    /// Key number used in last successful authentication (session is
+    /// dropped by application selection or any error)
+    pub fn authenticated_key(&self) -> Option<u8> {
+        self.session.as_ref().map(|s| s.key_no)
+    }
+
+    pub fn into_iso_dep(self) -> IsoDep {
+        self.iso
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    pub async fn desfire_get_version(
+        &mut self,
+        desfire: &mut Desfire,
+    ) -> Result<DesfireVersion, PCDErrorCode> {
+        let mut resp = [0; 32];
+        let len = self
+            .desfire_command(
+                desfire,
+                CMD_GET_VERSION,
+                &[],
+                &[],
+                (CommMode::Plain, CommMode::Plain),
+                &mut resp,
+            )
+            .await?;
+
+        // EV2 and newer can send few more bytes
+        if len < 28 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        Ok(DesfireVersion {
+            hardware: VersionInfo::from_bytes(&resp[0..7]),
+            software: VersionInfo::from_bytes(&resp[7..14]),
+            uid: resp[14..21].try_into().unwrap(),
+            batch: resp[21..26].try_into().unwrap(),
+            production_week: resp[26],
+            production_year: resp[27],
+        })
+    }
+
+    /// Lists applications (AIDs) on the card, `0x000000` (PICC level) has to
+    /// be selected
+    pub async fn desfire_get_application_ids(
+        &mut self,
+        desfire: &mut Desfire,
+    ) -> Result<Vec<u32, MAX_APPLICATIONS>, PCDErrorCode> {
+        let mut resp = [0; MAX_APPLICATIONS * 3];
+        let len = self
+            .desfire_command(
+                desfire,
+                CMD_GET_APPLICATION_IDS,
+                &[],
+                &[],
+                (CommMode::Plain, CommMode::Plain),
+                &mut resp,
+            )
+            .await?;
+
+        if len % 3 != 0 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        Ok(resp[..len].chunks_exact(3).map(u24_from_le).collect())
+    }
+
+    /// Selects application (`0x000000` for PICC level), current
+    /// authentication is lost
+    pub async fn desfire_select_application(
+        &mut self,
+        desfire: &mut Desfire,
+        aid: u32,
+    ) -> Result<(), PCDErrorCode> {
+        desfire.session = None;
+        self.desfire_command(
+            desfire,
+            CMD_SELECT_APPLICATION,
+            &u24_to_le(aid)?,
+            &[],
+            (CommMode::Plain, CommMode::Plain),
+            &mut [],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn desfire_get_file_ids(
+        &mut self,
+        desfire: &mut Desfire,
+    ) -> Result<Vec<u8, MAX_FILES>, PCDErrorCode> {
+        let mut resp = [0; MAX_FILES];
+        let len = self
+            .desfire_command(
+                desfire,
+                CMD_GET_FILE_IDS,
+                &[],
+                &[],
+                (CommMode::Plain, CommMode::Plain),
+                &mut resp,
+            )
+            .await?;
+
+        Ok(Vec::from_slice(&resp[..len]).unwrap())
+    }
+
+    pub async fn desfire_get_file_settings(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+    ) -> Result<FileSettings, PCDErrorCode> {
+        let mut resp = [0; 32];
+        let len = self
+            .desfire_command(
+                desfire,
+                CMD_GET_FILE_SETTINGS,
+                &[file_no],
+                &[],
+                (CommMode::Plain, CommMode::Plain),
+                &mut resp,
+            )
+            .await?;
+
+        FileSettings::parse(&resp[..len])
+    }
+
+    /// Reads `buff.len()` bytes of standard/backup file starting at `offset`
+    pub async fn desfire_read_data(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+        offset: u32,
+        buff: &mut [u8],
+        mode: CommMode,
+    ) -> Result<(), PCDErrorCode> {
+        let mut done = 0;
+        while done < buff.len() {
+            let chunk = (buff.len() - done).min(MAX_DATA_CHUNK);
+
+            let mut header = [0; 7];
+            header[0] = file_no;
+            header[1..4].copy_from_slice(&u24_to_le(offset + done as u32)?);
+            header[4..7].copy_from_slice(&u24_to_le(chunk as u32)?);
+
+            let len = self
+                .desfire_command(
+                    desfire,
+                    CMD_READ_DATA,
+                    &header,
+                    &[],
+                    (CommMode::Plain, mode),
+                    &mut buff[done..done + chunk],
+                )
+                .await?;
+
+            if len != chunk {
+                return Err(PCDErrorCode::Protocol);
+            }
+
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` into standard/backup file starting at `offset`
+    /// (backup file has to be committed with [`MFRC522::desfire_commit_transaction`])
+    pub async fn desfire_write_data(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+        offset: u32,
+        data: &[u8],
+        mode: CommMode,
+    ) -> Result<(), PCDErrorCode> {
+        for (i, chunk) in data.chunks(MAX_DATA_CHUNK).enumerate() {
+            let mut header = [0; 7];
+            header[0] = file_no;
+            header[1..4].copy_from_slice(&u24_to_le(offset + (i * MAX_DATA_CHUNK) as u32)?);
+            header[4..7].copy_from_slice(&u24_to_le(chunk.len() as u32)?);
+
+            self.desfire_command(
+                desfire,
+                CMD_WRITE_DATA,
+                &header,
+                chunk,
+                (mode, CommMode::Plain),
+                &mut [],
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn desfire_get_value(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+        mode: CommMode,
+    ) -> Result<i32, PCDErrorCode> {
+        let mut resp = [0; 4];
+        let len = self
+            .desfire_command(
+                desfire,
+                CMD_GET_VALUE,
+                &[file_no],
+                &[],
+                (CommMode::Plain, mode),
+                &mut resp,
+            )
+            .await?;
+
+        if len != 4 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        Ok(i32::from_le_bytes(resp))
+    }
+
+    /// Increases value file by `value`, needs to be committed
+    pub async fn desfire_credit(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+        value: i32,
+        mode: CommMode,
+    ) -> Result<(), PCDErrorCode> {
+        self.desfire_value_operation(desfire, CMD_CREDIT, file_no, value, mode)
+            .await
+    }
+
+    /// Decreases value file by `value`, needs to be committed
+    pub async fn desfire_debit(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+        value: i32,
+        mode: CommMode,
+    ) -> Result<(), PCDErrorCode> {
+        self.desfire_value_operation(desfire, CMD_DEBIT, file_no, value, mode)
+            .await
+    }
+
+    /// Reads `count` records (0 - all) of record file starting from
+    /// `offset` (0 - newest record). Returns length of data written into `buff`.
+    pub async fn desfire_read_records(
+        &mut self,
+        desfire: &mut Desfire,
+        file_no: u8,
+        offset: u32,
+        count: u32,
+        buff: &mut [u8],
+        mode: CommMode,
+    ) -> Result<usize, PCDErrorCode> {
+        let mut header = [0; 7];
+        header[0] = file_no;
+        header[1..4].copy_from_slice(&u24_to_le(offset)?);
+        header[4..7].copy_from_slice(&u24_to_le(count)?);
+
+        self.desfire_command(
+            desfire,
+            CMD_READ_RECORDS,
+            &header,
+            &[],
+            (CommMode::Plain, mode),
+            buff,
+        )
+        .await
+    }
+
+    /// Validates all previous writes to backup, value and record files
+    pub async fn desfire_commit_transaction(
+        &mut self,
+        desfire: &mut Desfire,
+    ) -> Result<(), PCDErrorCode> {
+        self.desfire_command(
+            desfire,
+            CMD_COMMIT_TRANSACTION,
+            &[],
+            &[],
+            (CommMode::Plain, CommMode::Plain),
+            &mut [],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Invalidates all previous writes to backup, value and record files
+    pub async fn desfire_abort_transaction(
+        &mut self,
+        desfire: &mut Desfire,
+    ) -> Result<(), PCDErrorCode> {
+        self.desfire_command(
+            desfire,
+            CMD_ABORT_TRANSACTION,
+            &[],
+            &[],
+            (CommMode::Plain, CommMode::Plain),
+            &mut [],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Legacy (DESFire D40) authentication with DES/2K3DES key.
+    /// `rng` is used to generate RndA, it should be cryptographically secure.
+    pub async fn desfire_authenticate_legacy(
+        &mut self,
+        desfire: &mut Desfire,
+        key_no: u8,
+        key: &DesfireKey,
+        rng: &mut impl RngCore,
+    ) -> Result<(), PCDErrorCode> {
+        if !matches!(key, DesfireKey::Des(_) | DesfireKey::TwoK3Des(_)) {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        self.desfire_authenticate(desfire, CMD_AUTHENTICATE_LEGACY, key_no, key, rng)
+            .await
+    }
+
+    /// EV1 ISO authentication with DES/2K3DES/3K3DES key
+    pub async fn desfire_authenticate_iso(
+        &mut self,
+        desfire: &mut Desfire,
+        key_no: u8,
+        key: &DesfireKey,
+        rng: &mut impl RngCore,
+    ) -> Result<(), PCDErrorCode> {
+        if matches!(key, DesfireKey::Aes(_)) {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        self.desfire_authenticate(desfire, CMD_AUTHENTICATE_ISO, key_no, key, rng)
+            .await
+    }
+
+    /// EV1 AES authentication
+    pub async fn desfire_authenticate_aes(
+        &mut self,
+        desfire: &mut Desfire,
+        key_no: u8,
+        key: &[u8; 16],
+        rng: &mut impl RngCore,
+    ) -> Result<(), PCDErrorCode> {
+        self.desfire_authenticate(
+            desfire,
+            CMD_AUTHENTICATE_AES,
+            key_no,
+            &DesfireKey::Aes(*key),
+            rng,
+        )
+        .await
+    }
+
+    /// EV2 AES authentication (AuthenticateEV2First). Following commands use
+    /// EV2 secure messaging (MACs bound to transaction identifier and command
+    /// counter), commands not accessing files are always MACed.
+    pub async fn desfire_authenticate_ev2_first(
+        &mut self,
+        desfire: &mut Desfire,
+        key_no: u8,
+        key: &[u8; 16],
+        rng: &mut impl RngCore,
+    ) -> Result<(), PCDErrorCode> {
+        desfire.session = None;
+        let cipher = Cipher::aes(key);
+
+        // ek(RndB), no PCD capabilities are sent
+        let mut resp = [0; RESPONSE_FRAME_LEN];
+        let len = self
+            .desfire_frame(
+                desfire,
+                &[CMD_AUTHENTICATE_EV2_FIRST, key_no, 0x00],
+                &mut resp,
+            )
+            .await?;
+        check_status(resp[0], DesfireStatus::ADDITIONAL_FRAME)?;
+        if len != 1 + 16 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        // every message is encrypted with zero IV
+        let mut rnd_b = [0; 16];
+        rnd_b.copy_from_slice(&resp[1..len]);
+        cipher.cbc_decrypt(&mut [0; MAX_BLOCK_SIZE], &mut rnd_b);
+
+        let mut rnd_a = [0; 16];
+        rng.fill_bytes(&mut rnd_a);
+
+        // ek(RndA + RndB')
+        let mut token = [0; 1 + 32];
+        token[0] = DesfireStatus::ADDITIONAL_FRAME;
+        token[1..17].copy_from_slice(&rnd_a);
+        token[17..].copy_from_slice(&rnd_b);
+        token[17..].rotate_left(1);
+        cipher.cbc_encrypt(&mut [0; MAX_BLOCK_SIZE], &mut token[1..]);
+
+        // ek(TI + RndA' + PDcap2 + PCDcap2)
+        let len = self.desfire_frame(desfire, &token, &mut resp).await?;
+        check_status(resp[0], DesfireStatus::OPERATION_OK)?;
+        if len != 1 + 32 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        let plain = &mut resp[1..len];
+        cipher.cbc_decrypt(&mut [0; MAX_BLOCK_SIZE], plain);
+        plain[4..20].rotate_right(1);
+        if plain[4..20] != rnd_a {
+            return Err(PCDErrorCode::AuthenticationFailed);
+        }
+
+        let ti = [plain[0], plain[1], plain[2], plain[3]];
+        let (cipher, ev2) = Ev2Session::new(&cipher, &rnd_a, &rnd_b, ti);
+        desfire.session = Some(Session {
+            key_no,
+            cipher,
+            iv: [0; MAX_BLOCK_SIZE],
+            messaging: Messaging::Ev2(ev2),
+        });
+
+        Ok(())
+    }
+
+    async fn desfire_authenticate(
+        &mut self,
+        desfire: &mut Desfire,
+        cmd: u8,
+        key_no: u8,
+        key: &DesfireKey,
+        rng: &mut impl RngCore,
+    ) -> Result<(), PCDErrorCode> {
+        desfire.session = None;
+
+        let legacy = cmd == CMD_AUTHENTICATE_LEGACY;
+        let cipher = key.cipher();
+        let rnd_len = key.random_len();
+
+        // ek(RndB)
+        let mut resp = [0; RESPONSE_FRAME_LEN];
+        let len = self
+            .desfire_frame(desfire, &[cmd, key_no], &mut resp)
+            .await?;
+        check_status(resp[0], DesfireStatus::ADDITIONAL_FRAME)?;
+        if len != 1 + rnd_len {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        // legacy mode always uses zero IV, EV1 chains IV through whole exchange
+        let mut iv = [0; MAX_BLOCK_SIZE];
+        let mut rnd_b = [0; 16];
+        rnd_b[..rnd_len].copy_from_slice(&resp[1..len]);
+        cipher.cbc_decrypt(&mut iv, &mut rnd_b[..rnd_len]);
+
+        let mut rnd_a = [0; 16];
+        rng.fill_bytes(&mut rnd_a[..rnd_len]);
+
+        // ek(RndA + RndB')
+        let mut token = [0; 1 + 32];
+        token[0] = DesfireStatus::ADDITIONAL_FRAME;
+        token[1..1 + rnd_len].copy_from_slice(&rnd_a[..rnd_len]);
+        token[1 + rnd_len..1 + 2 * rnd_len].copy_from_slice(&rnd_b[..rnd_len]);
+        token[1 + rnd_len..1 + 2 * rnd_len].rotate_left(1);
+
+        if legacy {
+            cipher.legacy_send(&mut token[1..1 + 2 * rnd_len]);
+        } else {
+            cipher.cbc_encrypt(&mut iv, &mut token[1..1 + 2 * rnd_len]);
+        }
+
+        // ek(RndA')
+        let len = self
+            .desfire_frame(desfire, &token[..1 + 2 * rnd_len], &mut resp)
+            .await?;
+        check_status(resp[0], DesfireStatus::OPERATION_OK)?;
+        if len != 1 + rnd_len {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        if legacy {
+            iv = [0; MAX_BLOCK_SIZE];
+        }
+
+        let rnd_a_rot = &mut resp[1..len];
+        cipher.cbc_decrypt(&mut iv, rnd_a_rot);
+        rnd_a_rot.rotate_right(1);
+        if *rnd_a_rot != rnd_a[..rnd_len] {
+            return Err(PCDErrorCode::AuthenticationFailed);
+        }
+
+        desfire.session = Some(Session {
+            key_no,
+            cipher: key.session_cipher(&rnd_a, &rnd_b),
+            iv: [0; MAX_BLOCK_SIZE],
+            messaging: if legacy {
+                Messaging::Legacy
+            } else {
+                Messaging::Ev1
+            },
+        });
+
+        Ok(())
+    }
+
+    async fn desfire_value_operation(
+        &mut self,
+        desfire: &mut Desfire,
+        cmd: u8,
+        file_no: u8,
+        value: i32,
+        mode: CommMode,
+    ) -> Result<(), PCDErrorCode> {
+        self.desfire_command(
+            desfire,
+            cmd,
+            &[file_no],
+            &value.to_le_bytes(),
+            (mode, CommMode::Plain),
+            &mut [],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sends command with `header` (always plain) and `data` secured according
+    /// to `modes` (command, response). Returns length of response data.
+    async fn desfire_command(
+        &mut self,
+        desfire: &mut Desfire,
+        cmd: u8,
+        header: &[u8],
+        data: &[u8],
+        modes: (CommMode, CommMode),
+        back_data: &mut [u8],
+    ) -> Result<usize, PCDErrorCode> {
+        let data_start = 1 + header.len();
+        let mut len = data_start + data.len();
+        if len + 24 > MESSAGE_BUFFER_LEN {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        message[0] = cmd;
+        message[1..data_start].copy_from_slice(header);
+        message[data_start..len].copy_from_slice(data);
+
+        // EV2 secures command and response in the same way
+        let modes = match &desfire.session {
+            Some(Session {
+                messaging: Messaging::Ev2(_),
+                ..
+            }) => {
+                let mode = ev2_mode(cmd, modes);
+                (mode, mode)
+            }
+            _ => modes,
+        };
+
+        if let Some(session) = &mut desfire.session {
+            len = session.wrap(&mut message, data_start, len, modes.0);
+        }
+
+        let mut resp = [0; MESSAGE_BUFFER_LEN];
+        let resp_len = self
+            .desfire_transceive(desfire, &message[..len], &mut resp)
+            .await?;
+
+        let resp_len = match &mut desfire.session {
+            Some(session) => match session.unwrap(&mut resp[..resp_len], modes.1) {
+                Ok(len) => len,
+                Err(e) => {
+                    desfire.session = None;
+                    return Err(e);
+                }
+            },
+            None => resp_len,
+        };
+
+        if resp_len > back_data.len() {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        back_data[..resp_len].copy_from_slice(&resp[..resp_len]);
+        Ok(resp_len)
+    }
+
+    /// Sends whole message split into frames and receives all response
+    /// frames (0xAF - additional frame). Returns length of response data.
+    async fn desfire_transceive(
+        &mut self,
+        desfire: &mut Desfire,
+        message: &[u8],
+        back_data: &mut [u8],
+    ) -> Result<usize, PCDErrorCode> {
+        let mut frame = [0; MAX_FRAME_LEN];
+        let mut resp = [0; RESPONSE_FRAME_LEN];
+
+        frame[0] = message[0];
+        let mut sent = 1;
+        let mut resp_len;
+        loop {
+            let chunk = (message.len() - sent).min(MAX_FRAME_LEN - 1);
+            frame[1..1 + chunk].copy_from_slice(&message[sent..sent + chunk]);
+            sent += chunk;
+
+            resp_len = self
+                .desfire_frame(desfire, &frame[..1 + chunk], &mut resp)
+                .await?;
+
+            if resp[0] != DesfireStatus::ADDITIONAL_FRAME || sent == message.len() {
+                break;
+            }
+
+            frame[0] = DesfireStatus::ADDITIONAL_FRAME;
+        }
+
+        if sent < message.len() && resp[0] == DesfireStatus::OPERATION_OK {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        let mut received = 0;
+        loop {
+            let status = resp[0];
+            if status != DesfireStatus::OPERATION_OK && status != DesfireStatus::ADDITIONAL_FRAME {
+                desfire.session = None;
+                return Err(PCDErrorCode::Desfire(status));
+            }
+
+            let data = &resp[1..resp_len];
+            if received + data.len() > back_data.len() {
+                return Err(PCDErrorCode::NoRoom);
+            }
+
+            back_data[received..received + data.len()].copy_from_slice(data);
+            received += data.len();
+
+            if status == DesfireStatus::OPERATION_OK {
+                return Ok(received);
+            }
+
+            resp_len = self
+                .desfire_frame(desfire, &[DesfireStatus::ADDITIONAL_FRAME], &mut resp)
+                .await?;
+        }
+    }
+
+    async fn desfire_frame(
+        &mut self,
+        desfire: &mut Desfire,
+        frame: &[u8],
+        resp: &mut [u8],
+    ) -> Result<usize, PCDErrorCode> {
+        let len = self
+            .iso_dep_transceive(&mut desfire.iso, frame, resp)
+            .await?;
+
+        if len == 0 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        Ok(len)
+    }
+}
+
+/// Stronger of command and response mode, commands not accessing files are
+/// MACed
+fn ev2_mode(cmd: u8, modes: (CommMode, CommMode)) -> CommMode {
+    match modes {
+        (CommMode::Encrypted, _) | (_, CommMode::Encrypted) => CommMode::Encrypted,
+        (CommMode::Mac, _) | (_, CommMode::Mac) => CommMode::Mac,
+        _ if FILE_COMMANDS.contains(&cmd) => CommMode::Plain,
+        _ => CommMode::Mac,
+    }
+}
+
+fn check_status(status: u8, expected: u8) -> Result<(), PCDErrorCode> {
+    match status {
+        s if s == expected => Ok(()),
+        DesfireStatus::OPERATION_OK | DesfireStatus::ADDITIONAL_FRAME => {
+            Err(PCDErrorCode::Protocol)
+        }
+        s => Err(PCDErrorCode::Desfire(s)),
+    }
+}
+
+/// Finds length of decrypted data followed by CRC and zero (or 0x80 0x00..)
+/// padding. Shortest data is tried first, CRC of data and its CRC is zero,
+/// so zero padding would pass as CRC of longer data.
+fn find_data_len(
+    plain: &[u8],
+    block_size: usize,
+    crc_len: usize,
+    check_crc: impl Fn(&[u8], &[u8]) -> bool,
+) -> Result<usize, PCDErrorCode> {
+    for padding in (0..block_size).rev() {
+        let Some(n) = plain.len().checked_sub(crc_len + padding) else {
+            continue;
+        };
+
+        let pad = &plain[n + crc_len..];
+        let pad_valid = match pad.split_first() {
+            None => true,
+            Some((&first, rest)) => {
+                (first == 0x00 || first == 0x80) && rest.iter().all(|&b| b == 0)
+            }
+        };
+
+        if pad_valid && check_crc(&plain[..n], &plain[n..n + crc_len]) {
+            return Ok(n);
+        }
+    }
+
+    Err(PCDErrorCode::IntegrityError)
+}
+
+fn u24_from_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+fn u24_to_le(value: u32) -> Result<[u8; 3], PCDErrorCode> {
+    if value > 0xFF_FFFF {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    let bytes = value.to_le_bytes();
+    Ok([bytes[0], bytes[1], bytes[2]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // AuthenticateEV2First example of NXP AN12196 (zero key)
+    const RND_A: [u8; 16] = [
+        0x13, 0xC5, 0xDB, 0x8A, 0x59, 0x30, 0x43, 0x9F, 0xC3, 0xDE, 0xF9, 0xA4, 0xC6, 0x75, 0x36,
+        0x0F,
+    ];
+    const RND_B: [u8; 16] = [
+        0xB9, 0xE2, 0xFC, 0x78, 0x9B, 0x64, 0xBF, 0x23, 0x7C, 0xCC, 0xAA, 0x20, 0xEC, 0x7E, 0x6E,
+        0x48,
+    ];
+    const TI: [u8; 4] = [0x9D, 0x00, 0xC4, 0xDF];
+
+    fn ev2_session() -> Session {
+        let (cipher, ev2) = Ev2Session::new(&Cipher::aes(&[0; 16]), &RND_A, &RND_B, TI);
+        Session {
+            key_no: 0,
+            cipher,
+            iv: [0; MAX_BLOCK_SIZE],
+            messaging: Messaging::Ev2(ev2),
+        }
+    }
+
+    #[test]
+    fn ev2_session_keys() {
+        let session = ev2_session();
+        let Messaging::Ev2(ev2) = &session.messaging else {
+            unreachable!();
+        };
+
+        // SesAuthENCKey 1309C877509E5A215007FF0ED19CA564 (compared by
+        // encrypting zero block)
+        let mut block = [0; 16];
+        session.cipher.encrypt_block(&mut block);
+        assert_eq!(
+            block,
+            [
+                0xB4, 0x6A, 0xAB, 0x47, 0xD6, 0x30, 0x4B, 0x44, 0x70, 0x4E, 0x61, 0xF5, 0xFF, 0xE9,
+                0xAE, 0x43,
+            ]
+        );
+
+        // SesAuthMACKey
+        assert_eq!(
+            ev2.mac_key,
+            [
+                0x4C, 0x66, 0x26, 0xF5, 0xE7, 0x2E, 0xA6, 0x94, 0x20, 0x21, 0x39, 0x29, 0x5C, 0x7A,
+                0x7F, 0xC7,
+            ]
+        );
+    }
+
+    #[test]
+    fn ev2_encrypted_command() {
+        let mut session = ev2_session();
+
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        // WriteData of file 1, offset 0, 5 bytes
+        message[0] = CMD_WRITE_DATA;
+        message[1..8].copy_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00]);
+        message[8..13].copy_from_slice(&[0x01, 0x02, 0x03, 0x04, 0x05]);
+
+        let len = session.wrap(&mut message, 8, 13, CommMode::Encrypted);
+        assert_eq!(
+            message[..len],
+            [
+                0x3D, 0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x44, 0x8B, 0x44, 0xEA, 0x4D, 0xFF,
+                0x49, 0x3C, 0x28, 0x18, 0x39, 0xAD, 0xB4, 0x7D, 0x88, 0x28, 0x03, 0xFD, 0x06, 0x69,
+                0x50, 0xC2, 0x40, 0x9F,
+            ]
+        );
+    }
+
+    #[test]
+    fn ev2_encrypted_response() {
+        let mut resp = [
+            0x14, 0xA8, 0x0C, 0x39, 0x4B, 0xFE, 0x18, 0xD0, 0x6B, 0xBF, 0x87, 0xE7, 0xBE, 0xDB,
+            0x26, 0x5F, 0x50, 0xE4, 0xAB, 0x9D, 0xA4, 0x19, 0xBB, 0x2A,
+        ];
+
+        let mut session = ev2_session();
+        assert_eq!(session.unwrap(&mut resp, CommMode::Encrypted), Ok(5));
+        assert_eq!(&resp[..5], b"Hello");
+
+        // same response doesn't verify with next command counter
+        let mut session = ev2_session();
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        session.wrap(&mut message, 1, 1, CommMode::Plain);
+        assert_eq!(session.unwrap(&mut [0; 8], CommMode::Plain), Ok(8));
+        assert_eq!(
+            session.unwrap(&mut resp, CommMode::Encrypted),
+            Err(PCDErrorCode::IntegrityError)
+        );
+    }
+
+    fn session(key: &DesfireKey, messaging: Messaging) -> Session {
+        Session {
+            key_no: 0,
+            cipher: key.cipher(),
+            iv: [0; MAX_BLOCK_SIZE],
+            messaging,
+        }
+    }
+
+    // WriteData of file 1, offset 0, 5 bytes
+    fn write_command(message: &mut [u8]) -> usize {
+        message[0] = CMD_WRITE_DATA;
+        message[1..8].copy_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00]);
+        message[8..13].copy_from_slice(b"Hello");
+        13
+    }
+
+    #[test]
+    fn legacy_round_trip() {
+        let key = DesfireKey::TwoK3Des([
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ]);
+        let mut pcd = session(&key, Messaging::Legacy);
+        let card = key.cipher();
+
+        // MAC over data only
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        let len = write_command(&mut message);
+        let len = pcd.wrap(&mut message, 8, len, CommMode::Mac);
+        assert_eq!(len, 13 + 4);
+        assert_eq!(pcd.unwrap(&mut message[8..len], CommMode::Mac), Ok(5));
+
+        message[9] ^= 0x01;
+        assert_eq!(
+            pcd.unwrap(&mut message[8..len], CommMode::Mac),
+            Err(PCDErrorCode::IntegrityError)
+        );
+
+        // card enciphers data received in "send mode"
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        let len = write_command(&mut message);
+        let len = pcd.wrap(&mut message, 8, len, CommMode::Encrypted);
+        assert_eq!(len, 8 + 8);
+
+        let mut prev = [0; 8];
+        for block in message[8..len].chunks_exact_mut(8) {
+            let cipher_block: [u8; 8] = block.try_into().unwrap();
+            card.encrypt_block(block);
+            crypto::xor(block, &prev);
+            prev = cipher_block;
+        }
+        assert_eq!(&message[8..13], b"Hello");
+        assert_eq!(message[13..15], crypto::crc16(b"Hello").to_le_bytes());
+
+        // response is plain CBC with zero IV, padding longer than CRC
+        let mut resp = [0; 8];
+        resp[..3].copy_from_slice(b"Hey");
+        resp[3..5].copy_from_slice(&crypto::crc16(b"Hey").to_le_bytes());
+        card.cbc_encrypt(&mut [0; MAX_BLOCK_SIZE], &mut resp);
+        assert_eq!(pcd.unwrap(&mut resp, CommMode::Encrypted), Ok(3));
+        assert_eq!(&resp[..3], b"Hey");
+    }
+
+    #[test]
+    fn ev1_round_trip() {
+        let key = DesfireKey::Aes([0x42; 16]);
+        let mut pcd = session(&key, Messaging::Ev1);
+        let mut card = session(&key, Messaging::Ev1);
+
+        // encrypted command, CRC32 over whole command
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        let len = write_command(&mut message);
+        let len = pcd.wrap(&mut message, 8, len, CommMode::Encrypted);
+        assert_eq!(len, 8 + 16);
+
+        card.cipher.cbc_decrypt(&mut card.iv, &mut message[8..len]);
+        assert_eq!(&message[8..13], b"Hello");
+        let mut command = [0; 13];
+        write_command(&mut command);
+        assert_eq!(
+            message[13..17],
+            crypto::crc32(CRC32_INIT, &command).to_le_bytes()
+        );
+
+        // encrypted response, CRC32 over data and status, IV chained
+        let mut resp = [0; 16];
+        resp[..5].copy_from_slice(b"World");
+        let crc = crypto::crc32(CRC32_INIT, b"World\x00");
+        resp[5..9].copy_from_slice(&crc.to_le_bytes());
+        card.cipher.cbc_encrypt(&mut card.iv, &mut resp);
+        assert_eq!(pcd.unwrap(&mut resp, CommMode::Encrypted), Ok(5));
+        assert_eq!(&resp[..5], b"World");
+        assert_eq!(pcd.iv, card.iv);
+
+        // MACed command and response, CMAC chained through IV
+        let mut message = [0; MESSAGE_BUFFER_LEN];
+        let len = write_command(&mut message);
+        let len = pcd.wrap(&mut message, 8, len, CommMode::Mac);
+        assert_eq!(len, 13 + 8);
+        card.cipher.cmac(&mut card.iv, &message[..13]);
+        assert_eq!(message[13..len], card.iv[..8]);
+
+        let mut resp = [0; 5 + 8];
+        resp[..5].copy_from_slice(b"World");
+        card.cipher.cmac(&mut card.iv, b"World\x00");
+        resp[5..].copy_from_slice(&card.iv[..8]);
+
+        let mut replay = resp;
+        assert_eq!(pcd.unwrap(&mut resp, CommMode::Mac), Ok(5));
+        assert_eq!(
+            pcd.unwrap(&mut replay, CommMode::Mac),
+            Err(PCDErrorCode::IntegrityError)
+        );
+    }
+
+    #[test]
+    fn ev2_modes() {
+        let plain = (CommMode::Plain, CommMode::Plain);
+        assert_eq!(ev2_mode(CMD_GET_FILE_IDS, plain), CommMode::Mac);
+        assert_eq!(ev2_mode(CMD_READ_DATA, plain), CommMode::Plain);
+        assert_eq!(
+            ev2_mode(CMD_READ_DATA, (CommMode::Plain, CommMode::Encrypted)),
+            CommMode::Encrypted
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use embedded_hal::digital::OutputPin;
+use heapless::Vec;
+
+use crate::{
+    consts::{PCDErrorCode, Uid},
+    geometry::{ClassicSize, Sector, MAX_SECTORS},
+    mifare::{KeyType, MifareKey},
+    MFRC522,
+};
+
+/// Publicly known default keys (factory, MAD, NDEF and common vendor keys)
+pub const DEFAULT_KEYS: [MifareKey; 15] = [
+    MifareKey::DEFAULT,
+    MifareKey([0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    MifareKey::MAD,
+    MifareKey([0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5]),
+    MifareKey([0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5]),
+    MifareKey([0xD0, 0xD1, 0xD2, 0xD3, 0xD4, 0xD5]),
+    MifareKey([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]),
+    MifareKey::NDEF,
+    MifareKey([0x4D, 0x3A, 0x99, 0xC3, 0x51, 0xDD]),
+    MifareKey([0x1A, 0x98, 0x2C, 0x7E, 0x45, 0x9A]),
+    MifareKey([0x71, 0x4C, 0x5C, 0x88, 0x6E, 0x97]),
+    MifareKey([0x58, 0x7E, 0xE5, 0xF9, 0x35, 0x0F]),
+    MifareKey([0xA0, 0x47, 0x8C, 0xC3, 0x90, 0x91]),
+    MifareKey([0x53, 0x3C, 0xB6, 0xC7, 0x23, 0xF6]),
+    MifareKey([0x8F, 0xD0, 0xA4, 0xF2, 0x56, 0xE9]),
+];
+
+/// Known keys of card sectors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    keys: [[Option<MifareKey>; 2]; MAX_SECTORS as usize],
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            keys: [[None; 2]; MAX_SECTORS as usize],
+        }
+    }
+}
+
+impl KeyMap {
+    pub fn get(&self, sector: Sector, key_type: KeyType) -> Option<&MifareKey> {
+        self.keys[sector.index() as usize][key_type as usize].as_ref()
+    }
+
+    pub fn set(&mut self, sector: Sector, key_type: KeyType, key: Option<MifareKey>) {
+        self.keys[sector.index() as usize][key_type as usize] = key;
+    }
+
+    /// Known key of sector, Key A is preferred
+    pub fn any(&self, sector: Sector) -> Option<(KeyType, &MifareKey)> {
+        [KeyType::A, KeyType::B]
+            .into_iter()
+            .find_map(|key_type| Some((key_type, self.get(sector, key_type)?)))
+    }
+
+    /// Number of found keys (each sector has 2)
+    pub fn found(&self) -> usize {
+        self.keys.iter().flatten().filter(|k| k.is_some()).count()
+    }
+}
+
+/// Passed to progress callback after every authentication attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCheckProgress {
+    pub sector: Sector,
+    pub key_type: KeyType,
+
+    /// Position in whole check / number of attempts (keys skipped after
+    /// match or already known are counted as done)
+    pub attempt: usize,
+    pub total: usize,
+
+    /// Set if key was found by this attempt
+    pub found: Option<MifareKey>,
+}
+
+/// Key slot still to be checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PendingSlot {
+    sector: Sector,
+    key_type: KeyType,
+
+    /// Attempts before first key of this slot (including skipped slots)
+    start: usize,
+}
+
+/// Slots (sector and key type) not present in `key_map` in check order,
+/// known slots are counted as done
+fn pending_slots(
+    size: ClassicSize,
+    key_count: usize,
+    key_map: &KeyMap,
+) -> Vec<PendingSlot, { MAX_SECTORS as usize * 2 }> {
+    size.sectors()
+        .flat_map(|sector| [(sector, KeyType::A), (sector, KeyType::B)])
+        .enumerate()
+        .filter(|(_, (sector, key_type))| key_map.get(*sector, *key_type).is_none())
+        .map(|(i, (sector, key_type))| PendingSlot {
+            sector,
+            key_type,
+            start: i * key_count,
+        })
+        .collect()
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Tries every key of `keys` as Key A and Key B of every sector. Keys
+    /// already present in `key_map` are not checked again.
+    ///
+    /// Failed authentication makes card go IDLE, it's selected again by
+    /// `pcd_authenticate`. Card is selected again before returning.
+    pub async fn mifare_check_keys(
+        &mut self,
+        uid: &Uid,
+        size: ClassicSize,
+        keys: &[MifareKey],
+        key_map: &mut KeyMap,
+        mut progress: impl FnMut(&KeyCheckProgress),
+    ) -> Result<(), PCDErrorCode> {
+        let total = size.sector_count() as usize * 2 * keys.len();
+
+        for slot in pending_slots(size, keys.len(), key_map) {
+            let PendingSlot {
+                sector,
+                key_type,
+                start,
+            } = slot;
+
+            for (i, key) in keys.iter().enumerate() {
+                let found = match self
+                    .pcd_authenticate(key_type, sector.trailer(), key, uid)
+                    .await
+                {
+                    Ok(()) => Some(*key),
+                    Err(PCDErrorCode::AuthenticationFailed) => None,
+                    Err(e) => return Err(e),
+                };
+
+                progress(&KeyCheckProgress {
+                    sector,
+                    key_type,
+                    attempt: start + i + 1,
+                    total,
+                    found,
+                });
+
+                if found.is_some() {
+                    key_map.set(sector, key_type, found);
+                    break;
+                }
+            }
+        }
+
+        _ = self.picc_halta().await;
+        self.pcd_stop_crypto1().await?;
+
+        let mut uid = uid.clone();
+        self.picc_wakeup_uid(&mut uid).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sector(index: u8) -> Sector {
+        Sector::new(index).unwrap()
+    }
+
+    #[test]
+    fn any_prefers_key_a() {
+        let mut key_map = KeyMap::default();
+        assert_eq!(key_map.any(sector(1)), None);
+
+        key_map.set(sector(1), KeyType::B, Some(MifareKey::MAD));
+        assert_eq!(key_map.any(sector(1)), Some((KeyType::B, &MifareKey::MAD)));
+
+        key_map.set(sector(1), KeyType::A, Some(MifareKey::DEFAULT));
+        assert_eq!(
+            key_map.any(sector(1)),
+            Some((KeyType::A, &MifareKey::DEFAULT))
+        );
+        assert_eq!(key_map.any(sector(0)), None);
+    }
+
+    #[test]
+    fn found_keys() {
+        let mut key_map = KeyMap::default();
+        assert_eq!(key_map.found(), 0);
+
+        key_map.set(sector(0), KeyType::A, Some(MifareKey::DEFAULT));
+        key_map.set(sector(0), KeyType::B, Some(MifareKey::DEFAULT));
+        key_map.set(sector(39), KeyType::B, Some(MifareKey::NDEF));
+        assert_eq!(key_map.found(), 3);
+
+        key_map.set(sector(0), KeyType::A, None);
+        assert_eq!(key_map.found(), 2);
+    }
+
+    #[test]
+    fn pending_slots_all_unknown() {
+        let slots = pending_slots(ClassicSize::Mini, 3, &KeyMap::default());
+        assert_eq!(slots.len(), 10);
+        assert_eq!(
+            slots[1],
+            PendingSlot {
+                sector: sector(0),
+                key_type: KeyType::B,
+                start: 3,
+            }
+        );
+
+        // last attempt of last slot is the total
+        let last = slots.last().unwrap();
+        assert_eq!(last.start + 3, 5 * 2 * 3);
+    }
+
+    #[test]
+    fn pending_slots_skip_known() {
+        let mut key_map = KeyMap::default();
+        key_map.set(sector(0), KeyType::A, Some(MifareKey::DEFAULT));
+        key_map.set(sector(1), KeyType::B, Some(MifareKey::DEFAULT));
+
+        let slots = pending_slots(ClassicSize::Mini, 4, &key_map);
+        let starts: Vec<_, 10> = slots
+            .iter()
+            .map(|slot| (slot.sector.index(), slot.key_type, slot.start))
+            .collect();
+
+        assert_eq!(
+            starts[..3],
+            [(0, KeyType::B, 4), (1, KeyType::A, 8), (2, KeyType::A, 16)]
+        );
+        assert_eq!(slots.len(), 8);
+
+        // everything known
+        let mut key_map = KeyMap::default();
+        for sector in ClassicSize::Mini.sectors() {
+            key_map.set(sector, KeyType::A, Some(MifareKey::DEFAULT));
+            key_map.set(sector, KeyType::B, Some(MifareKey::DEFAULT));
+        }
+        assert!(pending_slots(ClassicSize::Mini, 4, &key_map).is_empty());
+    }
+}
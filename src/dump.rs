@@ -0,0 +1,546 @@
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    access::{AccessConditions, SectorTrailer},
+    consts::{PCDErrorCode, PICCType, Uid},
+    dictionary::KeyMap,
+    geometry::{BlockAddr, ClassicSize, Sector, MAX_SECTORS},
+    identify::{CardInfo, CardModel, VersionInfo},
+    mifare::{KeyType, MifareKey, ValueBlock},
+    MFRC522,
+};
+
+/// Pages of the biggest supported Ultralight/NTAG (NTAG216)
+pub const MAX_PAGES: usize = 231;
+
+/// Pages read if size of Ultralight/NTAG is unknown (plain Ultralight)
+const DEFAULT_PAGES: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadStatus {
+    NotRead,
+    Ok,
+
+    /// No key of sector is known
+    NoKey,
+    Failed(PCDErrorCode),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDump {
+    pub data: [u8; 16],
+    pub status: ReadStatus,
+}
+
+impl BlockDump {
+    const EMPTY: Self = Self {
+        data: [0; 16],
+        status: ReadStatus::NotRead,
+    };
+
+    pub fn is_read(&self) -> bool {
+        self.status == ReadStatus::Ok
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageDump {
+    pub data: [u8; 4],
+    pub status: ReadStatus,
+}
+
+impl PageDump {
+    const EMPTY: Self = Self {
+        data: [0; 4],
+        status: ReadStatus::NotRead,
+    };
+
+    pub fn is_read(&self) -> bool {
+        self.status == ReadStatus::Ok
+    }
+}
+
+/// MIFARE Classic memory. Known keys are filled into read trailers (card
+/// returns Key A and not readable Key B as zeros), see [`ClassicDump::key_known`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassicDump {
+    pub size: ClassicSize,
+    blocks: [BlockDump; 256],
+    known_keys: [[bool; 2]; MAX_SECTORS as usize],
+}
+
+impl ClassicDump {
+    pub fn new(size: ClassicSize) -> Self {
+        Self {
+            size,
+            blocks: [BlockDump::EMPTY; 256],
+            known_keys: [[false; 2]; MAX_SECTORS as usize],
+        }
+    }
+
+    /// Key in trailer of dump is real key (not zeros returned by card in
+    /// place of unreadable key)
+    pub fn key_known(&self, sector: Sector, key_type: KeyType) -> bool {
+        self.known_keys[sector.index() as usize][key_type as usize]
+    }
+
+    pub fn set_key_known(&mut self, sector: Sector, key_type: KeyType, known: bool) {
+        self.known_keys[sector.index() as usize][key_type as usize] = known;
+    }
+
+    /// Marks every block as not read (in place)
+    fn clear(&mut self) {
+        self.blocks.fill(BlockDump::EMPTY);
+        self.known_keys = [[false; 2]; MAX_SECTORS as usize];
+    }
+
+    pub fn block(&self, block: BlockAddr) -> &BlockDump {
+        &self.blocks[block.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, block: BlockAddr) -> &mut BlockDump {
+        &mut self.blocks[block.0 as usize]
+    }
+
+    /// Blocks of card (`size.block_count()` of them)
+    pub fn blocks(&self) -> &[BlockDump] {
+        &self.blocks[..self.size.block_count() as usize]
+    }
+
+    /// Decoded trailer of sector, `None` if it wasn't read or its access
+    /// bits are corrupted
+    pub fn trailer(&self, sector: Sector) -> Option<SectorTrailer> {
+        let trailer = self.block(sector.trailer());
+        if !trailer.is_read() {
+            return None;
+        }
+
+        SectorTrailer::parse(&trailer.data).ok()
+    }
+
+    pub fn access(&self, sector: Sector) -> Option<AccessConditions> {
+        self.trailer(sector).map(|trailer| trailer.access)
+    }
+
+    /// Puts known keys into read trailer, Key B only if it's not readable
+    fn fill_trailer_keys(&mut self, sector: Sector, key_map: &KeyMap) {
+        let data = &mut self.blocks[sector.trailer().0 as usize].data;
+
+        let key_a = key_map.get(sector, KeyType::A);
+        if let Some(key) = key_a {
+            data[..6].copy_from_slice(key.as_bytes());
+        }
+
+        let access = AccessConditions::from_bytes_unchecked(&[data[6], data[7], data[8]]);
+        let key_b_readable = access.trailer().key_b_readable();
+
+        let key_b = key_map.get(sector, KeyType::B);
+        if let (Some(key), false) = (key_b, key_b_readable) {
+            data[10..].copy_from_slice(key.as_bytes());
+        }
+
+        self.set_key_known(sector, KeyType::A, key_a.is_some());
+        self.set_key_known(sector, KeyType::B, key_b.is_some() || key_b_readable);
+    }
+
+    /// Value of block configured as value block (by access bits) with valid
+    /// value block format
+    pub fn value(&self, block: BlockAddr) -> Option<ValueBlock> {
+        let dump = self.block(block);
+        let access = self.access(block.sector())?;
+        if !dump.is_read() || block.is_trailer() || !access.is_value_block(block.access_group()) {
+            return None;
+        }
+
+        ValueBlock::parse(&dump.data).ok()
+    }
+}
+
+/// MIFARE Ultralight / NTAG memory (pages of 4 bytes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UltralightDump {
+    pub model: CardModel,
+
+    /// GET_VERSION response (if card supports it)
+    pub version: Option<VersionInfo>,
+    page_count: u8,
+    pages: [PageDump; MAX_PAGES],
+}
+
+impl UltralightDump {
+    /// `page_count` is limited to [`MAX_PAGES`]
+    pub fn new(model: CardModel, page_count: u8) -> Self {
+        Self {
+            model,
+            version: None,
+            page_count: page_count.min(MAX_PAGES as u8),
+            pages: [PageDump::EMPTY; MAX_PAGES],
+        }
+    }
+
+    pub fn page_count(&self) -> u8 {
+        self.page_count
+    }
+
+    pub fn page(&self, page: u8) -> Option<&PageDump> {
+        self.pages().get(page as usize)
+    }
+
+    pub fn page_mut(&mut self, page: u8) -> Option<&mut PageDump> {
+        self.pages[..self.page_count as usize].get_mut(page as usize)
+    }
+
+    pub fn pages(&self) -> &[PageDump] {
+        &self.pages[..self.page_count as usize]
+    }
+}
+
+// no allocator to box variants, dump is filled in place (see `dump_card`)
+// and reserves memory of the biggest card anyway
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardMemory {
+    Classic(ClassicDump),
+    Ultralight(UltralightDump),
+
+    /// Only card details are available
+    Unsupported,
+}
+
+#[derive(Debug, Clone)]
+pub struct CardDump {
+    /// Uid with ATQA and SAK
+    pub uid: Uid,
+    pub picc_type: PICCType,
+    pub memory: CardMemory,
+}
+
+impl CardDump {
+    /// Dump without memory, filled by `dump_card`
+    pub fn new(uid: Uid) -> Self {
+        Self {
+            picc_type: PICCType::from_sak(uid.sak),
+            uid,
+            memory: CardMemory::Unsupported,
+        }
+    }
+
+    pub fn classic(&self) -> Option<&ClassicDump> {
+        match &self.memory {
+            CardMemory::Classic(dump) => Some(dump),
+            _ => None,
+        }
+    }
+
+    pub fn ultralight(&self) -> Option<&UltralightDump> {
+        match &self.memory {
+            CardMemory::Ultralight(dump) => Some(dump),
+            _ => None,
+        }
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Reads whole memory of selected card into `dump` (filled in place,
+    /// dump of 4K card takes few KB). MIFARE Classic sectors are read
+    /// with keys from `key_map` (see `mifare_check_keys`), Ultralight/NTAG
+    /// size is detected by `picc_identify`.
+    ///
+    /// Card is selected again before returning.
+    pub async fn dump_card(
+        &mut self,
+        uid: &Uid,
+        key_map: &KeyMap,
+        dump: &mut CardDump,
+    ) -> Result<(), PCDErrorCode> {
+        dump.uid = uid.clone();
+        dump.picc_type = PICCType::from_sak(uid.sak);
+
+        if let Some(size) = ClassicSize::from_picc_type(&dump.picc_type) {
+            if !matches!(dump.memory, CardMemory::Classic(_)) {
+                dump.memory = CardMemory::Classic(ClassicDump::new(size));
+            }
+            if let CardMemory::Classic(classic) = &mut dump.memory {
+                classic.size = size;
+                self.dump_classic(uid, key_map, classic).await?;
+            }
+        } else if dump.picc_type == PICCType::PiccTypeMifareUL {
+            let info = self.picc_identify(uid).await?;
+            if !matches!(dump.memory, CardMemory::Ultralight(_)) {
+                dump.memory = CardMemory::Ultralight(UltralightDump::new(info.model, 0));
+            }
+            if let CardMemory::Ultralight(ultralight) = &mut dump.memory {
+                self.dump_ultralight(uid, &info, ultralight).await?;
+            }
+        } else {
+            dump.memory = CardMemory::Unsupported;
+        }
+
+        Ok(())
+    }
+
+    /// Reads every block of MIFARE Classic card into `dump` (of card size).
+    /// Blocks not readable with Key A are tried with Key B, failed reads are
+    /// recorded in block status and dump continues with next block.
+    pub async fn dump_classic(
+        &mut self,
+        uid: &Uid,
+        key_map: &KeyMap,
+        dump: &mut ClassicDump,
+    ) -> Result<(), PCDErrorCode> {
+        dump.clear();
+
+        for sector in dump.size.sectors() {
+            if key_map.any(sector).is_none() {
+                for block in sector.blocks() {
+                    dump.block_mut(block).status = ReadStatus::NoKey;
+                }
+                continue;
+            }
+
+            for key_type in [KeyType::A, KeyType::B] {
+                if let Some(key) = key_map.get(sector, key_type) {
+                    self.dump_classic_sector(uid, dump, sector, key_type, key)
+                        .await?;
+                }
+            }
+
+            if dump.block(sector.trailer()).is_read() {
+                dump.fill_trailer_keys(sector, key_map);
+            }
+        }
+
+        self.picc_reselect(uid).await
+    }
+
+    /// Reads `page_count` (from `info.memory_size`) pages of Ultralight/NTAG
+    /// into `dump`. Pages refused by card (password protected) are recorded
+    /// as failed.
+    pub async fn dump_ultralight(
+        &mut self,
+        uid: &Uid,
+        info: &CardInfo,
+        dump: &mut UltralightDump,
+    ) -> Result<(), PCDErrorCode> {
+        let page_count = info
+            .memory_size
+            .map(|size| (size / 4).min(MAX_PAGES as u32) as u8)
+            .unwrap_or(DEFAULT_PAGES);
+
+        dump.model = info.model;
+        dump.version = info.version;
+        dump.page_count = page_count;
+        dump.pages.fill(PageDump::EMPTY);
+
+        let mut buff = [0; 18];
+        for page in (0..page_count).step_by(4) {
+            let mut byte_count = 18;
+            let res = self.mifare_read(page, &mut buff, &mut byte_count).await;
+
+            // READ returns 4 pages
+            for (offset, data) in buff[..16].chunks_exact(4).enumerate() {
+                let Some(page_dump) = dump.page_mut(page + offset as u8) else {
+                    break;
+                };
+
+                match res {
+                    Ok(()) => {
+                        page_dump.data.copy_from_slice(data);
+                        page_dump.status = ReadStatus::Ok;
+                    }
+                    Err(e) => page_dump.status = ReadStatus::Failed(e),
+                }
+            }
+
+            // NAK puts card into IDLE
+            if res.is_err() {
+                self.picc_reselect(uid).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads blocks of sector not read yet
+    async fn dump_classic_sector(
+        &mut self,
+        uid: &Uid,
+        dump: &mut ClassicDump,
+        sector: Sector,
+        key_type: KeyType,
+        key: &MifareKey,
+    ) -> Result<(), PCDErrorCode> {
+        let mut authenticated = false;
+        let mut buff = [0; 18];
+
+        for block in sector.blocks() {
+            if dump.block(block).is_read() {
+                continue;
+            }
+
+            if !authenticated {
+                match self
+                    .pcd_authenticate(key_type, sector.trailer(), key, uid)
+                    .await
+                {
+                    Ok(()) => authenticated = true,
+                    Err(e @ PCDErrorCode::AuthenticationFailed) => {
+                        for block in sector.blocks() {
+                            let block = dump.block_mut(block);
+                            if !block.is_read() {
+                                block.status = ReadStatus::Failed(e);
+                            }
+                        }
+
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            let mut byte_count = 18;
+            let res = self.mifare_read(block, &mut buff, &mut byte_count).await;
+
+            let block = dump.block_mut(block);
+            match res {
+                Ok(()) => {
+                    block.data.copy_from_slice(&buff[..16]);
+                    block.status = ReadStatus::Ok;
+                }
+                Err(e) => {
+                    // NAK puts card into IDLE, it has to be authenticated again
+                    block.status = ReadStatus::Failed(e);
+                    self.picc_reselect(uid).await?;
+                    authenticated = false;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: MifareKey = MifareKey([0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5]);
+    const KEY_B: MifareKey = MifareKey([0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5]);
+
+    /// Dump with read trailer of sector 1 as returned by card (Key A as zeros)
+    fn dump_with_trailer(access: AccessConditions, key_b: [u8; 6]) -> ClassicDump {
+        let mut dump = ClassicDump::new(ClassicSize::Classic1K);
+        let trailer = dump.block_mut(BlockAddr(7));
+        trailer.data[6..9].copy_from_slice(&access.to_bytes());
+        trailer.data[10..].copy_from_slice(&key_b);
+        trailer.status = ReadStatus::Ok;
+        dump
+    }
+
+    fn key_map() -> KeyMap {
+        let sector = Sector::new(1).unwrap();
+        let mut key_map = KeyMap::default();
+        key_map.set(sector, KeyType::A, Some(KEY_A));
+        key_map.set(sector, KeyType::B, Some(KEY_B));
+        key_map
+    }
+
+    #[test]
+    fn trailer_keys_key_b_readable() {
+        let sector = Sector::new(1).unwrap();
+
+        // transport configuration, Key B is data read from card
+        let mut dump = dump_with_trailer(AccessConditions::TRANSPORT, [0x42; 6]);
+        dump.fill_trailer_keys(sector, &key_map());
+
+        let trailer = dump.trailer(sector).unwrap();
+        assert_eq!(trailer.key(KeyType::A), &KEY_A);
+        assert_eq!(trailer.key(KeyType::B), &MifareKey([0x42; 6]));
+        assert!(dump.key_known(sector, KeyType::A));
+        assert!(dump.key_known(sector, KeyType::B));
+
+        // readable Key B is known even without key map
+        let mut dump = dump_with_trailer(AccessConditions::TRANSPORT, [0x42; 6]);
+        dump.fill_trailer_keys(sector, &KeyMap::default());
+        assert!(!dump.key_known(sector, KeyType::A));
+        assert!(dump.key_known(sector, KeyType::B));
+    }
+
+    #[test]
+    fn trailer_keys_key_b_not_readable() {
+        let sector = Sector::new(1).unwrap();
+        let access = AccessConditions::new([0b000, 0b000, 0b000, 0b011]);
+
+        let mut dump = dump_with_trailer(access, [0; 6]);
+        dump.fill_trailer_keys(sector, &key_map());
+
+        let trailer = dump.trailer(sector).unwrap();
+        assert_eq!(trailer.key(KeyType::A), &KEY_A);
+        assert_eq!(trailer.key(KeyType::B), &KEY_B);
+        assert!(dump.key_known(sector, KeyType::B));
+
+        // zeros in place of unknown Key B
+        let mut key_map = KeyMap::default();
+        key_map.set(sector, KeyType::A, Some(KEY_A));
+        let mut dump = dump_with_trailer(access, [0; 6]);
+        dump.fill_trailer_keys(sector, &key_map);
+
+        let trailer = dump.trailer(sector).unwrap();
+        assert_eq!(trailer.key(KeyType::B), &MifareKey([0; 6]));
+        assert!(dump.key_known(sector, KeyType::A));
+        assert!(!dump.key_known(sector, KeyType::B));
+    }
+
+    #[test]
+    fn value_blocks() {
+        // block 4 configured as value block, block 5 as data
+        let access = AccessConditions::new([0b110, 0b000, 0b000, 0b001]);
+        let mut dump = dump_with_trailer(access, [0; 6]);
+
+        let value = ValueBlock::new(-100, 4);
+        for block in [4, 5] {
+            let block = dump.block_mut(BlockAddr(block));
+            block.data = value.to_bytes();
+            block.status = ReadStatus::Ok;
+        }
+
+        assert_eq!(dump.value(BlockAddr(4)), Some(value));
+        assert_eq!(dump.value(BlockAddr(5)), None);
+        assert_eq!(dump.value(BlockAddr(7)), None);
+
+        // broken copy of value
+        dump.block_mut(BlockAddr(4)).data[8] ^= 0x01;
+        assert_eq!(dump.value(BlockAddr(4)), None);
+
+        // not read
+        let block = dump.block_mut(BlockAddr(4));
+        block.data = value.to_bytes();
+        block.status = ReadStatus::NoKey;
+        assert_eq!(dump.value(BlockAddr(4)), None);
+
+        // access bits unknown
+        dump.block_mut(BlockAddr(4)).status = ReadStatus::Ok;
+        dump.block_mut(BlockAddr(7)).status = ReadStatus::NotRead;
+        assert_eq!(dump.value(BlockAddr(4)), None);
+    }
+
+    #[test]
+    fn ultralight_pages_clamped() {
+        let mut dump = UltralightDump::new(CardModel::Ntag216, 255);
+        assert_eq!(dump.page_count() as usize, MAX_PAGES);
+        assert_eq!(dump.pages().len(), MAX_PAGES);
+        assert!(dump.page(230).is_some());
+        assert!(dump.page(231).is_none());
+        assert!(dump.page_mut(231).is_none());
+
+        let mut dump = UltralightDump::new(CardModel::Ntag213, 45);
+        assert_eq!(dump.pages().len(), 45);
+        assert!(dump.page(45).is_none());
+        assert!(dump.page_mut(45).is_none());
+        assert!(dump.page_mut(44).is_some());
+    }
+}
@@ -0,0 +1,1015 @@
+use core::fmt::{self, Write};
+
+use crate::{
+    consts::{Atqa, PCDErrorCode, PICCType, Uid},
+    dump::{
+        BlockDump, CardDump, CardMemory, ClassicDump, PageDump, ReadStatus, UltralightDump,
+        MAX_PAGES,
+    },
+    geometry::{BlockAddr, ClassicSize},
+    identify::{CardModel, VersionInfo},
+    magic::validate_manufacturer_block,
+    mifare::KeyType,
+};
+
+const CLASSIC_BLOCK_SIZE: usize = 16;
+const ULTRALIGHT_PAGE_SIZE: usize = 4;
+
+/// Trailer bytes as bit masks (bit per byte, as returned by `parse_hex`)
+const TRAILER_KEY_A: u16 = 0x003F;
+const TRAILER_ACCESS: u16 = 0x03C0;
+const TRAILER_KEY_B: u16 = 0xFC00;
+
+/// ATQA of Ultralight/NTAG (0x0044), used when file has no card details
+const ULTRALIGHT_ATQA: [u8; 2] = [0x44, 0x00];
+const ULTRALIGHT_SAK: u8 = 0x00;
+
+const PROXMARK_CLASSIC: &str = "mfcard";
+const PROXMARK_ULTRALIGHT: &str = "mfu";
+
+const FLIPPER_CLASSIC: &str = "Mifare Classic";
+const FLIPPER_ULTRALIGHT: &str = "NTAG/Ultralight";
+
+/// Flipper Ultralight/NTAG type names by model
+const FLIPPER_ULTRALIGHT_TYPES: [(CardModel, &str); 5] = [
+    (CardModel::MifareUltralight, "Mifare Ultralight"),
+    (CardModel::MifareUltralightC, "Mifare Ultralight C"),
+    (CardModel::Ntag213, "NTAG213"),
+    (CardModel::Ntag215, "NTAG215"),
+    (CardModel::Ntag216, "NTAG216"),
+];
+
+/// Blocks of Classic or pages of Ultralight/NTAG dump
+enum Units<'a> {
+    Blocks(&'a [BlockDump]),
+    Pages(&'a [PageDump]),
+}
+
+impl<'a> Units<'a> {
+    fn of(dump: &'a CardDump) -> Result<Self, PCDErrorCode> {
+        match &dump.memory {
+            CardMemory::Classic(classic) => Ok(Units::Blocks(classic.blocks())),
+            CardMemory::Ultralight(ultralight) => Ok(Units::Pages(ultralight.pages())),
+            CardMemory::Unsupported => Err(PCDErrorCode::Invalid),
+        }
+    }
+
+    /// Data and read flag of every unit
+    fn iter(&self) -> impl Iterator<Item = (&'a [u8], bool)> + '_ {
+        let (blocks, pages): (&[BlockDump], &[PageDump]) = match self {
+            Units::Blocks(blocks) => (blocks, &[]),
+            Units::Pages(pages) => (&[], pages),
+        };
+
+        blocks
+            .iter()
+            .map(|block| (&block.data[..], block.is_read()))
+            .chain(pages.iter().map(|page| (&page.data[..], page.is_read())))
+    }
+}
+
+/// Writes raw memory (`.mfd`/`.bin`), unread blocks/pages are written as
+/// zeros (as well as unknown keys, read back as unknown). Returns number of
+/// written bytes.
+pub fn write_binary(dump: &CardDump, out: &mut [u8]) -> Result<usize, PCDErrorCode> {
+    let units = Units::of(dump)?;
+
+    let mut len = 0;
+    for (data, _) in units.iter() {
+        out.get_mut(len..len + data.len())
+            .ok_or(PCDErrorCode::NoRoom)?
+            .copy_from_slice(data);
+        len += data.len();
+    }
+
+    Ok(len)
+}
+
+/// Reads raw memory (`.mfd`/`.bin`). MIFARE Classic is recognized by size
+/// (320, 1024, 2048 or 4096 bytes), anything else is read as Ultralight/NTAG
+/// pages. Card details are taken from block 0 (pages 0..2). Trailer keys of
+/// zeros are marked unknown (see [`ClassicDump::key_known`]).
+pub fn read_binary(data: &[u8]) -> Result<CardDump, PCDErrorCode> {
+    let unit_size = match classic_size(data.len() / CLASSIC_BLOCK_SIZE) {
+        Some(_) if data.len() % CLASSIC_BLOCK_SIZE == 0 => CLASSIC_BLOCK_SIZE,
+        _ if data.len() % ULTRALIGHT_PAGE_SIZE == 0 => ULTRALIGHT_PAGE_SIZE,
+        _ => return Err(PCDErrorCode::Invalid),
+    };
+
+    let mut memory = new_memory(unit_size, data.len() / unit_size)?;
+    for (index, unit) in data.chunks_exact(unit_size).enumerate() {
+        let unknown = zero_keys(&memory, index, unit);
+        set_unit(&mut memory, index, unit, unknown)?;
+    }
+
+    finish(memory, None)
+}
+
+/// Writes Proxmark emulator file (`.eml`), one block/page per line in hex
+pub fn write_eml(dump: &CardDump, out: &mut impl Write) -> Result<(), PCDErrorCode> {
+    let units = Units::of(dump)?;
+    for (data, _) in units.iter() {
+        write_hex(out, data, "").map_err(|_| PCDErrorCode::NoRoom)?;
+        out.write_char('\n').map_err(|_| PCDErrorCode::NoRoom)?;
+    }
+
+    Ok(())
+}
+
+/// Reads Proxmark emulator file (`.eml`). Lines of 32 hex digits are Classic
+/// blocks, lines of 8 hex digits Ultralight/NTAG pages. Trailer keys of
+/// zeros are marked unknown.
+pub fn read_eml(text: &str) -> Result<CardDump, PCDErrorCode> {
+    let lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+
+    let unit_size = lines.clone().next().ok_or(PCDErrorCode::Invalid)?.len() / 2;
+    let mut memory = new_memory(unit_size, lines.clone().count())?;
+
+    let mut buff = [0; CLASSIC_BLOCK_SIZE];
+    for (index, line) in lines.enumerate() {
+        parse_hex(line, &mut buff[..unit_size])?;
+        let unknown = zero_keys(&memory, index, &buff[..unit_size]);
+        set_unit(&mut memory, index, &buff[..unit_size], unknown)?;
+    }
+
+    finish(memory, None)
+}
+
+/// Writes Proxmark JSON dump (`FileType` `mfcard` or `mfu`). Classic dumps
+/// also get `SectorKeys` of sectors with read trailer.
+pub fn write_proxmark_json(dump: &CardDump, out: &mut impl Write) -> Result<(), PCDErrorCode> {
+    let units = Units::of(dump)?;
+    write_proxmark_json_units(dump, &units, out).map_err(|_| PCDErrorCode::NoRoom)
+}
+
+fn write_proxmark_json_units(dump: &CardDump, units: &Units, out: &mut impl Write) -> fmt::Result {
+    let file_type = match units {
+        Units::Blocks(_) => PROXMARK_CLASSIC,
+        Units::Pages(_) => PROXMARK_ULTRALIGHT,
+    };
+
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"Created\": \"esp-hal-mfrc522\",")?;
+    writeln!(out, "  \"FileType\": \"{file_type}\",")?;
+    writeln!(out, "  \"Card\": {{")?;
+    writeln!(out, "    \"UID\": \"{:#}\",", dump.uid)?;
+
+    if let Some(version) = dump.ultralight().and_then(|ultralight| ultralight.version) {
+        write!(out, "    \"Version\": \"00")?;
+        write_hex(out, &version.to_bytes(), "")?;
+        writeln!(out, "\",")?;
+    }
+
+    write!(out, "    \"ATQA\": \"")?;
+    write_hex(out, &dump.uid.atqa.bytes, "")?;
+    writeln!(out, "\",")?;
+    writeln!(out, "    \"SAK\": \"{:02X}\"", dump.uid.sak)?;
+    writeln!(out, "  }},")?;
+
+    writeln!(out, "  \"blocks\": {{")?;
+    let count = units.iter().count();
+    for (index, (data, _)) in units.iter().enumerate() {
+        write!(out, "    \"{index}\": \"")?;
+        write_hex(out, data, "")?;
+        writeln!(out, "\"{}", if index + 1 < count { "," } else { "" })?;
+    }
+
+    let Some(classic) = dump.classic() else {
+        writeln!(out, "  }}")?;
+        return writeln!(out, "}}");
+    };
+
+    writeln!(out, "  }},")?;
+    writeln!(out, "  \"SectorKeys\": {{")?;
+
+    let mut sectors = classic
+        .size
+        .sectors()
+        .filter(|sector| classic.block(sector.trailer()).is_read())
+        .peekable();
+
+    while let Some(sector) = sectors.next() {
+        let data = &classic.block(sector.trailer()).data;
+
+        writeln!(out, "    \"{}\": {{", sector.index())?;
+        write!(out, "      \"KeyA\": \"")?;
+        write_hex(out, &data[..6], "")?;
+        write!(out, "\",\n      \"KeyB\": \"")?;
+        write_hex(out, &data[10..], "")?;
+        write!(out, "\",\n      \"AccessConditions\": \"")?;
+        write_hex(out, &data[6..10], "")?;
+        writeln!(out, "\"")?;
+        writeln!(
+            out,
+            "    }}{}",
+            if sectors.peek().is_some() { "," } else { "" }
+        )?;
+    }
+
+    writeln!(out, "  }}")?;
+    writeln!(out, "}}")
+}
+
+/// Reads Proxmark JSON dump. Only `FileType`, `Card` details and `blocks`
+/// are used, keys are part of trailer blocks anyway (keys of zeros are marked
+/// unknown). `mfu` files get ATQA and SAK of Ultralight/NTAG.
+pub fn read_proxmark_json(text: &str) -> Result<CardDump, PCDErrorCode> {
+    let mut count = 0;
+    let mut hex_len = 0;
+    json_blocks(text, |index, hex| {
+        count = count.max(index + 1);
+        hex_len = hex.len();
+        Ok(())
+    })?;
+
+    let unit_size = match json_string(text, "FileType") {
+        Some(PROXMARK_CLASSIC) => CLASSIC_BLOCK_SIZE,
+        Some(PROXMARK_ULTRALIGHT) => ULTRALIGHT_PAGE_SIZE,
+        _ => hex_len / 2,
+    };
+
+    let mut memory = new_memory(unit_size, count)?;
+    let mut buff = [0; CLASSIC_BLOCK_SIZE];
+    json_blocks(text, |index, hex| {
+        parse_hex(hex, &mut buff[..unit_size])?;
+        let unknown = zero_keys(&memory, index, &buff[..unit_size]);
+        set_unit(&mut memory, index, &buff[..unit_size], unknown)
+    })?;
+
+    if let (CardMemory::Ultralight(ultralight), Some(hex)) =
+        (&mut memory, json_string(text, "Version"))
+    {
+        let mut version = [0; 8];
+        parse_hex(hex, &mut version)?;
+        ultralight.version = Some(VersionInfo::from_bytes(&version[1..]));
+    }
+
+    let uid = match json_string(text, "UID") {
+        Some(hex) => {
+            let mut uid = Uid::from_hex(hex)?;
+            match (json_string(text, "ATQA"), json_string(text, "SAK")) {
+                (Some(atqa), Some(sak)) => {
+                    let mut bytes = [0; 2];
+                    parse_hex(atqa, &mut bytes)?;
+                    parse_hex(sak, core::slice::from_mut(&mut uid.sak))?;
+                    uid.atqa = Atqa::from_bytes(bytes);
+                }
+
+                // mfu files don't contain ATQA and SAK
+                (None, None) if matches!(memory, CardMemory::Ultralight(_)) => {
+                    uid.atqa = Atqa::from_bytes(ULTRALIGHT_ATQA);
+                    uid.sak = ULTRALIGHT_SAK;
+                }
+                _ => return Err(PCDErrorCode::Invalid),
+            }
+
+            Some(uid)
+        }
+        None => None,
+    };
+
+    finish(memory, uid)
+}
+
+/// Writes Flipper Zero `.nfc` file (version 4). Unread Classic blocks and
+/// unknown trailer keys are written as `??`, unread Ultralight/NTAG pages as
+/// zeros.
+pub fn write_flipper_nfc(dump: &CardDump, out: &mut impl Write) -> Result<(), PCDErrorCode> {
+    let units = Units::of(dump)?;
+    write_flipper_nfc_units(dump, &units, out).map_err(|_| PCDErrorCode::NoRoom)
+}
+
+fn write_flipper_nfc_units(dump: &CardDump, units: &Units, out: &mut impl Write) -> fmt::Result {
+    let device_type = match units {
+        Units::Blocks(_) => FLIPPER_CLASSIC,
+        Units::Pages(_) => FLIPPER_ULTRALIGHT,
+    };
+
+    // Flipper writes ATQA most significant byte first
+    let atqa = [dump.uid.atqa.bytes[1], dump.uid.atqa.bytes[0]];
+
+    writeln!(out, "Filetype: Flipper NFC device")?;
+    writeln!(out, "Version: 4")?;
+    writeln!(out, "Device type: {device_type}")?;
+    write!(out, "UID: ")?;
+    write_hex(out, dump.uid.as_bytes(), " ")?;
+    write!(out, "\nATQA: ")?;
+    write_hex(out, &atqa, " ")?;
+    writeln!(out, "\nSAK: {:02X}", dump.uid.sak)?;
+
+    match &dump.memory {
+        CardMemory::Classic(classic) => {
+            writeln!(
+                out,
+                "Mifare Classic type: {}",
+                flipper_classic_type(classic.size)
+            )?;
+            writeln!(out, "Data format version: 2")?;
+
+            for (index, block) in classic.blocks().iter().enumerate() {
+                let unknown = unknown_bytes(classic, BlockAddr(index as u8));
+
+                write!(out, "Block {index}:")?;
+                for (i, byte) in block.data.iter().enumerate() {
+                    if unknown & (1 << i) != 0 {
+                        write!(out, " ??")?;
+                    } else {
+                        write!(out, " {byte:02X}")?;
+                    }
+                }
+                writeln!(out)?;
+            }
+        }
+        CardMemory::Ultralight(ultralight) => {
+            let version = ultralight.version.map(|v| v.to_bytes()).unwrap_or_default();
+            let pages_read = units.iter().take_while(|(_, read)| *read).count();
+
+            writeln!(out, "Data format version: 2")?;
+            writeln!(
+                out,
+                "NTAG/Ultralight type: {}",
+                flipper_ultralight_type(ultralight)
+            )?;
+            write!(out, "Signature: 00")?;
+            for _ in 1..32 {
+                write!(out, " 00")?;
+            }
+            write!(out, "\nMifare version: 00 ")?;
+            write_hex(out, &version, " ")?;
+            writeln!(out)?;
+
+            for counter in 0..3 {
+                writeln!(out, "Counter {counter}: 0")?;
+                writeln!(out, "Tearing {counter}: 00")?;
+            }
+
+            writeln!(out, "Pages total: {}", ultralight.page_count())?;
+            writeln!(out, "Pages read: {pages_read}")?;
+            for (index, (data, _)) in units.iter().enumerate() {
+                write!(out, "Page {index}: ")?;
+                write_hex(out, data, " ")?;
+                writeln!(out)?;
+            }
+
+            writeln!(out, "Failed authentication attempts: 0")?;
+        }
+        CardMemory::Unsupported => {}
+    }
+
+    Ok(())
+}
+
+/// Reads Flipper Zero `.nfc` file of MIFARE Classic or NTAG/Ultralight.
+/// Blocks with any unknown (`??`) byte are marked as not read, except
+/// trailers with known access bits (their `??` keys are marked unknown).
+pub fn read_flipper_nfc(text: &str) -> Result<CardDump, PCDErrorCode> {
+    let mut uid = None;
+    let mut atqa = [0; 2];
+    let mut sak = 0;
+    let mut model = CardModel::Unknown;
+    let mut version = None;
+    let mut pages_read = None;
+    let mut memory = None;
+
+    let mut buff = [0; CLASSIC_BLOCK_SIZE];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line.split_once(':').ok_or(PCDErrorCode::Invalid)?;
+        let value = value.trim();
+
+        match key {
+            "Device type" if value != FLIPPER_CLASSIC && value != FLIPPER_ULTRALIGHT => {
+                return Err(PCDErrorCode::Invalid)
+            }
+            "UID" => uid = Some(Uid::from_hex(value)?),
+            "ATQA" => {
+                parse_hex(value, &mut atqa)?;
+                atqa.reverse();
+            }
+            "SAK" => _ = parse_hex(value, core::slice::from_mut(&mut sak))?,
+            "Mifare Classic type" => {
+                let size = match value {
+                    "MINI" => ClassicSize::Mini,
+                    "1K" => ClassicSize::Classic1K,
+                    "2K" => ClassicSize::Classic2K,
+                    "4K" => ClassicSize::Classic4K,
+                    _ => return Err(PCDErrorCode::Invalid),
+                };
+
+                memory = Some(CardMemory::Classic(ClassicDump::new(size)));
+            }
+            "NTAG/Ultralight type" => model = flipper_ultralight_model(value),
+            "Mifare version" => {
+                let mut bytes = [0; 8];
+                parse_hex(value, &mut bytes)?;
+                version = Some(VersionInfo::from_bytes(&bytes[1..]));
+            }
+            "Pages total" => {
+                let count = value.parse().map_err(|_| PCDErrorCode::Invalid)?;
+                let mut ultralight = UltralightDump::new(model, count);
+                ultralight.version = version.filter(|v| v.to_bytes() != [0; 7]);
+
+                memory = Some(CardMemory::Ultralight(ultralight));
+            }
+            "Pages read" => pages_read = Some(value.parse().map_err(|_| PCDErrorCode::Invalid)?),
+            _ => {
+                let Some(index) = key
+                    .strip_prefix("Block ")
+                    .or_else(|| key.strip_prefix("Page "))
+                else {
+                    continue;
+                };
+
+                let index = index.parse().map_err(|_| PCDErrorCode::Invalid)?;
+                let memory = memory.as_mut().ok_or(PCDErrorCode::Invalid)?;
+                let unit_size = match memory {
+                    CardMemory::Classic(_) => CLASSIC_BLOCK_SIZE,
+                    _ => ULTRALIGHT_PAGE_SIZE,
+                };
+
+                let unknown = parse_hex(value, &mut buff[..unit_size])?;
+                set_unit(memory, index, &buff[..unit_size], unknown)?;
+            }
+        }
+    }
+
+    let mut memory = memory.ok_or(PCDErrorCode::Invalid)?;
+    if let (CardMemory::Ultralight(ultralight), Some(pages_read)) = (&mut memory, pages_read) {
+        for page in pages_read..ultralight.page_count() {
+            if let Some(page) = ultralight.page_mut(page) {
+                page.status = ReadStatus::NotRead;
+            }
+        }
+    }
+
+    let uid = uid
+        .map(|mut uid| {
+            uid.atqa = Atqa::from_bytes(atqa);
+            uid.sak = sak;
+            uid
+        })
+        .ok_or(PCDErrorCode::Invalid)?;
+
+    finish(memory, Some(uid))
+}
+
+fn classic_size(block_count: usize) -> Option<ClassicSize> {
+    [
+        ClassicSize::Mini,
+        ClassicSize::Classic1K,
+        ClassicSize::Classic2K,
+        ClassicSize::Classic4K,
+    ]
+    .into_iter()
+    .find(|size| size.block_count() as usize == block_count)
+}
+
+fn flipper_classic_type(size: ClassicSize) -> &'static str {
+    match size {
+        ClassicSize::Mini => "MINI",
+        ClassicSize::Classic1K => "1K",
+        ClassicSize::Classic2K => "2K",
+        ClassicSize::Classic4K => "4K",
+    }
+}
+
+fn flipper_ultralight_type(dump: &UltralightDump) -> &'static str {
+    match (dump.model, dump.page_count()) {
+        (CardModel::MifareUltralightEv1, 20) => "Mifare Ultralight 11",
+        (CardModel::MifareUltralightEv1, 41) => "Mifare Ultralight 21",
+        (model, _) => FLIPPER_ULTRALIGHT_TYPES
+            .iter()
+            .find(|(m, _)| *m == model)
+            .map(|(_, name)| *name)
+            .unwrap_or("Mifare Ultralight"),
+    }
+}
+
+fn flipper_ultralight_model(name: &str) -> CardModel {
+    match name {
+        "Mifare Ultralight 11" | "Mifare Ultralight 21" => CardModel::MifareUltralightEv1,
+        _ => FLIPPER_ULTRALIGHT_TYPES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(model, _)| *model)
+            .unwrap_or(CardModel::Unknown),
+    }
+}
+
+/// Model guessed from number of pages (files without card details)
+fn ultralight_model(page_count: usize) -> CardModel {
+    match page_count {
+        16 => CardModel::MifareUltralight,
+        48 => CardModel::MifareUltralightC,
+        20 | 41 => CardModel::MifareUltralightEv1,
+        45 => CardModel::Ntag213,
+        135 => CardModel::Ntag215,
+        231 => CardModel::Ntag216,
+        _ => CardModel::Unknown,
+    }
+}
+
+fn new_memory(unit_size: usize, count: usize) -> Result<CardMemory, PCDErrorCode> {
+    match unit_size {
+        CLASSIC_BLOCK_SIZE => {
+            let size = classic_size(count).ok_or(PCDErrorCode::Invalid)?;
+            Ok(CardMemory::Classic(ClassicDump::new(size)))
+        }
+        ULTRALIGHT_PAGE_SIZE if count > 0 && count <= MAX_PAGES => Ok(CardMemory::Ultralight(
+            UltralightDump::new(ultralight_model(count), count as u8),
+        )),
+        _ => Err(PCDErrorCode::Invalid),
+    }
+}
+
+/// Bytes of Classic block that aren't known (bit per byte)
+fn unknown_bytes(classic: &ClassicDump, block: BlockAddr) -> u16 {
+    if !classic.block(block).is_read() {
+        return u16::MAX;
+    }
+
+    let sector = block.sector();
+    let mut unknown = 0;
+    if block.is_trailer() && !classic.key_known(sector, KeyType::A) {
+        unknown |= TRAILER_KEY_A;
+    }
+    if block.is_trailer() && !classic.key_known(sector, KeyType::B) {
+        unknown |= TRAILER_KEY_B;
+    }
+
+    unknown
+}
+
+/// Unknown bytes of block from format without `??` marker. Card returns
+/// unreadable keys as zeros, so trailer keys of zeros are taken as unknown
+/// (real all-zero key is lost, trailer is then skipped by restore).
+fn zero_keys(memory: &CardMemory, index: usize, data: &[u8]) -> u16 {
+    let trailer = index < 256 && BlockAddr(index as u8).is_trailer();
+    if !matches!(memory, CardMemory::Classic(_)) || !trailer {
+        return 0;
+    }
+
+    let mut unknown = 0;
+    if data[..6] == [0; 6] {
+        unknown |= TRAILER_KEY_A;
+    }
+    if data[10..16] == [0; 6] {
+        unknown |= TRAILER_KEY_B;
+    }
+
+    unknown
+}
+
+/// Stores block/page, `unknown` are bytes that aren't known (bit per byte).
+/// Keys of Classic trailers are known unless marked unknown.
+fn set_unit(
+    memory: &mut CardMemory,
+    index: usize,
+    data: &[u8],
+    unknown: u16,
+) -> Result<(), PCDErrorCode> {
+    let status = |unknown| match unknown {
+        0 => ReadStatus::Ok,
+        _ => ReadStatus::NotRead,
+    };
+
+    match memory {
+        CardMemory::Classic(classic) if index < classic.size.block_count() as usize => {
+            let addr = BlockAddr(index as u8);
+            let block = classic.block_mut(addr);
+            block.data.copy_from_slice(data);
+
+            if addr.is_trailer() {
+                block.status = status(unknown & TRAILER_ACCESS);
+
+                let read = block.is_read();
+                let sector = addr.sector();
+                classic.set_key_known(sector, KeyType::A, read && unknown & TRAILER_KEY_A == 0);
+                classic.set_key_known(sector, KeyType::B, read && unknown & TRAILER_KEY_B == 0);
+            } else {
+                block.status = status(unknown);
+            }
+        }
+        CardMemory::Ultralight(ultralight) => {
+            let page = u8::try_from(index)
+                .ok()
+                .and_then(|index| ultralight.page_mut(index))
+                .ok_or(PCDErrorCode::Invalid)?;
+
+            page.data.copy_from_slice(data);
+            page.status = status(unknown);
+        }
+        _ => return Err(PCDErrorCode::Invalid),
+    }
+
+    Ok(())
+}
+
+/// Builds dump, card details are taken from memory if `uid` is not known
+fn finish(memory: CardMemory, uid: Option<Uid>) -> Result<CardDump, PCDErrorCode> {
+    let uid = match uid {
+        Some(uid) => uid,
+        None => uid_from_memory(&memory)?,
+    };
+
+    Ok(CardDump {
+        picc_type: PICCType::from_sak(uid.sak),
+        uid,
+        memory,
+    })
+}
+
+/// Uid, SAK and ATQA from manufacturer block (checked by
+/// [`validate_manufacturer_block`], 4 byte uid is recognized by its BCC) or
+/// Ultralight/NTAG pages 0..2
+fn uid_from_memory(memory: &CardMemory) -> Result<Uid, PCDErrorCode> {
+    match memory {
+        CardMemory::Classic(classic) => {
+            let block = classic.block(BlockAddr(0));
+            if !block.is_read() {
+                return Err(PCDErrorCode::Invalid);
+            }
+
+            validate_manufacturer_block(&block.data, 4)
+                .or_else(|_| validate_manufacturer_block(&block.data, 7))
+                .map(|(uid, _)| uid)
+        }
+        CardMemory::Ultralight(ultralight) => {
+            let (Some(page0), Some(page1)) = (ultralight.page(0), ultralight.page(1)) else {
+                return Err(PCDErrorCode::Invalid);
+            };
+
+            // page 0 holds UID0..2 and BCC0
+            let mut bytes = [0; 7];
+            bytes[..3].copy_from_slice(&page0.data[..3]);
+            bytes[3..].copy_from_slice(&page1.data);
+
+            let mut uid = Uid::from_bytes(&bytes)?;
+            uid.atqa = Atqa::from_bytes(ULTRALIGHT_ATQA);
+            Ok(uid)
+        }
+        CardMemory::Unsupported => Err(PCDErrorCode::Invalid),
+    }
+}
+
+fn write_hex(out: &mut impl Write, bytes: &[u8], separator: &str) -> fmt::Result {
+    for (i, byte) in bytes.iter().enumerate() {
+        if i != 0 {
+            out.write_str(separator)?;
+        }
+
+        write!(out, "{byte:02X}")?;
+    }
+
+    Ok(())
+}
+
+/// Parses hex bytes (whitespace is ignored) into whole `out` (up to 16
+/// bytes), returns unknown (`??`) bytes as bit mask
+fn parse_hex(text: &str, out: &mut [u8]) -> Result<u16, PCDErrorCode> {
+    let mut unknown = 0;
+    let mut len = 0;
+
+    let mut chars = text.chars().filter(|c| !c.is_whitespace());
+    while let Some(high) = chars.next() {
+        let low = chars.next().ok_or(PCDErrorCode::Invalid)?;
+        let byte = out.get_mut(len).ok_or(PCDErrorCode::Invalid)?;
+
+        if high == '?' && low == '?' {
+            *byte = 0;
+            unknown |= 1 << len;
+        } else {
+            let high = high.to_digit(16).ok_or(PCDErrorCode::Invalid)?;
+            let low = low.to_digit(16).ok_or(PCDErrorCode::Invalid)?;
+            *byte = ((high << 4) | low) as u8;
+        }
+
+        len += 1;
+    }
+
+    if len != out.len() {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    Ok(unknown)
+}
+
+/// Value of first `"key": "value"` pair with string value (pairs with other
+/// values are skipped)
+fn json_string<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let mut rest = text;
+    while let Some(start) = rest.find('"') {
+        let (name, after) = json_quoted(&rest[start..])?;
+        rest = after.trim_start();
+
+        if name != key {
+            continue;
+        }
+
+        if let Some((value, _)) = rest
+            .strip_prefix(':')
+            .and_then(|value| json_quoted(value.trim_start()))
+        {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Calls `f` with index and hex of every entry of `blocks` object
+fn json_blocks<'a>(
+    text: &'a str,
+    mut f: impl FnMut(usize, &'a str) -> Result<(), PCDErrorCode>,
+) -> Result<(), PCDErrorCode> {
+    let start = text.find("\"blocks\"").ok_or(PCDErrorCode::Invalid)?;
+    let mut rest = text[start + "\"blocks\"".len()..].trim_start();
+    rest = rest
+        .strip_prefix(':')
+        .ok_or(PCDErrorCode::Invalid)?
+        .trim_start();
+    rest = rest.strip_prefix('{').ok_or(PCDErrorCode::Invalid)?;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.starts_with('}') {
+            return Ok(());
+        }
+
+        let (index, after) = json_quoted(rest).ok_or(PCDErrorCode::Invalid)?;
+        let after = after
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or(PCDErrorCode::Invalid)?;
+        let (hex, after) = json_quoted(after.trim_start()).ok_or(PCDErrorCode::Invalid)?;
+
+        f(index.parse().map_err(|_| PCDErrorCode::Invalid)?, hex)?;
+        rest = after;
+    }
+}
+
+/// Splits `"string"rest` (no escape sequences, dumps don't need them)
+fn json_quoted(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('"')?;
+    let end = text.find('"')?;
+    Some((&text[..end], &text[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+    use crate::geometry::Sector;
+
+    const FLIPPER_SAMPLE: &str = "Filetype: Flipper NFC device
+Version: 4
+# Device type can be ISO14443-3A, ISO14443-3B, ISO14443-4A, ISO14443-4B, ISO15693-3, FeliCa, NTAG/Ultralight, Mifare Classic, Mifare DESFire, SLIX, ST25TB
+Device type: Mifare Classic
+# UID is common for all formats
+UID: 2A 6C 3F 91
+# ISO14443-3A specific data
+ATQA: 00 04
+SAK: 08
+# Mifare Classic specific data
+Mifare Classic type: 1K
+Data format version: 2
+# Mifare Classic blocks, '??' means unknown data
+Block 0: 2A 6C 3F 91 E8 08 04 00 62 63 64 65 66 67 68 69
+Block 1: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+Block 2: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+Block 3: FF FF FF FF FF FF FF 07 80 69 FF FF FF FF FF FF
+Block 4: 48 65 6C 6C 6F 00 00 00 00 00 00 00 00 00 00 00
+Block 5: 0A 00 00 00 F5 FF FF FF 0A 00 00 00 05 FA 05 FA
+Block 6: ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ?? ??
+Block 7: A0 A1 A2 A3 A4 A5 7F 07 88 69 ?? ?? ?? ?? ?? ??
+";
+
+    const PROXMARK_MFU_SAMPLE: &str = r#"{
+  "Created": "proxmark3",
+  "FileType": "mfu",
+  "Card": {
+    "UID": "04A2B3C2D4E580",
+    "Version": "0004040201000F03",
+    "TBO_0": "0000",
+    "TBO_1": "00",
+    "Signature": "0000000000000000000000000000000000000000000000000000000000000000",
+    "Counter0": "000000",
+    "Tearing0": "BD",
+    "Counter1": "000000",
+    "Tearing1": "BD",
+    "Counter2": "000000",
+    "Tearing2": "BD"
+  },
+  "blocks": {
+    "0": "04A2B39D",
+    "1": "C2D4E580",
+    "2": "73480000",
+    "3": "E1101200",
+    "4": "0103A00C",
+    "5": "340300FE"
+  }
+}"#;
+
+    fn classic_dump() -> CardDump {
+        let mut data = [0; 1024];
+        data[..16].copy_from_slice(&[
+            0xDE, 0xAD, 0xBE, 0xEF, 0x22, 0x08, 0x04, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        for sector in 0..16 {
+            let trailer = sector * 64 + 48;
+            data[trailer..trailer + 6].copy_from_slice(&[0xFF; 6]);
+            data[trailer + 6..trailer + 10].copy_from_slice(&[0xFF, 0x07, 0x80, 0x69]);
+            data[trailer + 10..trailer + 16].copy_from_slice(&[0xFF; 6]);
+        }
+        data[16..32].copy_from_slice(&[0x42; 16]);
+
+        read_binary(&data).unwrap()
+    }
+
+    fn ultralight_dump() -> CardDump {
+        let mut data = [0; 45 * 4];
+        data[..8].copy_from_slice(&[0x04, 0xA2, 0xB3, 0x9D, 0xC2, 0xD4, 0xE5, 0x80]);
+        for (i, byte) in data.iter_mut().enumerate().skip(16) {
+            *byte = i as u8;
+        }
+
+        read_binary(&data).unwrap()
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        for dump in [classic_dump(), ultralight_dump()] {
+            let mut out = [0; 1024];
+            let len = write_binary(&dump, &mut out).unwrap();
+
+            let read = read_binary(&out[..len]).unwrap();
+            assert_eq!(read.memory, dump.memory);
+            assert_eq!(read.uid, dump.uid);
+        }
+
+        let dump = classic_dump();
+        assert_eq!(dump.uid.as_bytes(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(dump.uid.sak, 0x08);
+        assert_eq!(dump.classic().unwrap().size, ClassicSize::Classic1K);
+        assert_eq!(
+            ultralight_dump().ultralight().unwrap().model,
+            CardModel::Ntag213
+        );
+    }
+
+    #[test]
+    fn eml_round_trip() {
+        for dump in [classic_dump(), ultralight_dump()] {
+            let mut out: String<4096> = String::new();
+            write_eml(&dump, &mut out).unwrap();
+
+            let read = read_eml(&out).unwrap();
+            assert_eq!(read.memory, dump.memory);
+            assert_eq!(read.uid, dump.uid);
+        }
+    }
+
+    #[test]
+    fn proxmark_json_round_trip() {
+        for dump in [classic_dump(), ultralight_dump()] {
+            let mut out: String<8192> = String::new();
+            write_proxmark_json(&dump, &mut out).unwrap();
+
+            let read = read_proxmark_json(&out).unwrap();
+            assert_eq!(read.memory, dump.memory);
+            assert_eq!(read.uid, dump.uid);
+        }
+    }
+
+    #[test]
+    fn binary_invalid_block0() {
+        let mut data = [0; 1024];
+        write_binary(&classic_dump(), &mut data).unwrap();
+
+        // wrong BCC and no SAK/ATQA for 7 byte uid
+        data[4] ^= 0x01;
+        data[7..10].fill(0);
+        assert_eq!(read_binary(&data).err(), Some(PCDErrorCode::Invalid));
+
+        // blank block 0
+        data[..16].fill(0);
+        assert_eq!(read_binary(&data).err(), Some(PCDErrorCode::Invalid));
+    }
+
+    #[test]
+    fn unknown_keys_round_trip() {
+        let mut dump = classic_dump();
+        let (sector2, sector3) = (Sector::new(2).unwrap(), Sector::new(3).unwrap());
+        if let CardMemory::Classic(memory) = &mut dump.memory {
+            // card returns unreadable keys as zeros
+            memory.block_mut(sector2.trailer()).data[10..].fill(0);
+            memory.set_key_known(sector2, KeyType::B, false);
+            memory.block_mut(sector3.trailer()).data[..6].fill(0);
+            memory.set_key_known(sector3, KeyType::A, false);
+        }
+
+        let mut binary = [0; 1024];
+        let len = write_binary(&dump, &mut binary).unwrap();
+        let mut eml: String<4096> = String::new();
+        write_eml(&dump, &mut eml).unwrap();
+        let mut json: String<8192> = String::new();
+        write_proxmark_json(&dump, &mut json).unwrap();
+
+        for read in [
+            read_binary(&binary[..len]),
+            read_eml(&eml),
+            read_proxmark_json(&json),
+        ] {
+            let read = read.unwrap();
+            assert_eq!(read.memory, dump.memory);
+
+            let classic = read.classic().unwrap();
+            assert!(classic.block(sector2.trailer()).is_read());
+            assert!(classic.key_known(sector2, KeyType::A));
+            assert!(!classic.key_known(sector2, KeyType::B));
+            assert!(!classic.key_known(sector3, KeyType::A));
+            assert!(classic.key_known(sector3, KeyType::B));
+        }
+    }
+
+    #[test]
+    fn flipper_nfc_round_trip() {
+        let mut classic = classic_dump();
+        if let CardMemory::Classic(memory) = &mut classic.memory {
+            memory.block_mut(BlockAddr(8)).status = ReadStatus::NoKey;
+            memory.set_key_known(Sector::new(2).unwrap(), KeyType::B, false);
+        }
+
+        for dump in [classic, ultralight_dump()] {
+            let mut out: String<8192> = String::new();
+            write_flipper_nfc(&dump, &mut out).unwrap();
+
+            let read = read_flipper_nfc(&out).unwrap();
+            assert_eq!(read.uid, dump.uid);
+
+            let mut again: String<8192> = String::new();
+            write_flipper_nfc(&read, &mut again).unwrap();
+            assert_eq!(again, out);
+        }
+    }
+
+    #[test]
+    fn flipper_nfc_sample() {
+        let dump = read_flipper_nfc(FLIPPER_SAMPLE).unwrap();
+        assert_eq!(dump.uid.as_bytes(), &[0x2A, 0x6C, 0x3F, 0x91]);
+        assert_eq!(dump.uid.atqa, Atqa::from_bytes([0x04, 0x00]));
+        assert_eq!(dump.uid.sak, 0x08);
+        assert_eq!(dump.picc_type, PICCType::PiccTypeMifare1K);
+
+        let classic = dump.classic().unwrap();
+        assert_eq!(classic.size, ClassicSize::Classic1K);
+        assert_eq!(&classic.block(BlockAddr(4)).data[..5], b"Hello");
+        assert!(!classic.block(BlockAddr(6)).is_read());
+        assert!(!classic.block(BlockAddr(8)).is_read());
+        assert_eq!(classic.value(BlockAddr(5)), None);
+
+        let sector = Sector::new(1).unwrap();
+        assert!(classic.block(BlockAddr(7)).is_read());
+        assert!(classic.key_known(sector, KeyType::A));
+        assert!(!classic.key_known(sector, KeyType::B));
+        assert!(classic.access(sector).is_some());
+    }
+
+    #[test]
+    fn proxmark_mfu_sample() {
+        let dump = read_proxmark_json(PROXMARK_MFU_SAMPLE).unwrap();
+        assert_eq!(
+            dump.uid.as_bytes(),
+            &[0x04, 0xA2, 0xB3, 0xC2, 0xD4, 0xE5, 0x80]
+        );
+        assert_eq!(dump.uid.atqa, Atqa::from_bytes(ULTRALIGHT_ATQA));
+        assert_eq!(dump.uid.sak, ULTRALIGHT_SAK);
+        assert_eq!(dump.picc_type, PICCType::PiccTypeMifareUL);
+
+        let ultralight = dump.ultralight().unwrap();
+        assert_eq!(ultralight.page_count(), 6);
+        assert_eq!(ultralight.page(3).unwrap().data, [0xE1, 0x10, 0x12, 0x00]);
+        assert_eq!(
+            ultralight.version.unwrap().to_bytes(),
+            [0x04, 0x04, 0x02, 0x01, 0x00, 0x0F, 0x03]
+        );
+    }
+
+    #[test]
+    fn proxmark_json_missing_sak() {
+        let mut out: String<8192> = String::new();
+        write_proxmark_json(&classic_dump(), &mut out).unwrap();
+
+        let start = out.find("    \"SAK\"").unwrap();
+        let end = start + out[start..].find('\n').unwrap();
+        let mut text: String<8192> = String::new();
+        text.push_str(&out[..start]).unwrap();
+        text.push_str(&out[end..]).unwrap();
+
+        assert_eq!(read_proxmark_json(&text).err(), Some(PCDErrorCode::Invalid));
+    }
+}
@@ -0,0 +1,441 @@
+use core::fmt::Display;
+
+use embedded_hal::digital::OutputPin;
+use heapless::{String, Vec};
+
+use crate::{
+    apdu::Command,
+    consts::PCDErrorCode,
+    iso_dep::IsoDep,
+    tlv::{self, Tag, TlvIter},
+    MFRC522,
+};
+
+/// Proximity Payment System Environment
+const PPSE_NAME: &[u8] = b"2PAY.SYS.DDF01";
+
+const INS_SELECT: u8 = 0xA4;
+const INS_READ_RECORD: u8 = 0xB2;
+const INS_GET_PROCESSING_OPTIONS: u8 = 0xA8;
+
+const TAG_AID: Tag = Tag(0x4F);
+const TAG_APPLICATION_LABEL: Tag = Tag(0x50);
+const TAG_TRACK2: Tag = Tag(0x57);
+const TAG_PAN: Tag = Tag(0x5A);
+const TAG_DIRECTORY_ENTRY: Tag = Tag(0x61);
+const TAG_RESPONSE_FORMAT_1: Tag = Tag(0x80);
+const TAG_COMMAND_TEMPLATE: Tag = Tag(0x83);
+const TAG_PRIORITY: Tag = Tag(0x87);
+const TAG_AFL: Tag = Tag(0x94);
+const TAG_EXPIRY: Tag = Tag(0x5F24);
+const TAG_PREFERRED_NAME: Tag = Tag(0x9F12);
+const TAG_PDOL: Tag = Tag(0x9F38);
+
+const MAX_RESPONSE: usize = 256 + 2;
+const MAX_PAN_DIGITS: u8 = 19;
+const MAX_AFL: usize = 64;
+
+/// Terminal data used to fill PDOL of GET PROCESSING OPTIONS. Values are
+/// only used to make card return its records, no transaction is made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalData {
+    /// 9F1A, BCD (ISO 3166 numeric)
+    pub country_code: [u8; 2],
+
+    /// 5F2A, BCD (ISO 4217 numeric)
+    pub currency_code: [u8; 2],
+
+    /// 9A, BCD YYMMDD
+    pub transaction_date: [u8; 3],
+
+    /// 9F66 Terminal Transaction Qualifiers
+    pub ttq: [u8; 4],
+}
+
+impl Default for TerminalData {
+    fn default() -> Self {
+        Self {
+            country_code: [0x08, 0x40],
+            currency_code: [0x08, 0x40],
+            transaction_date: [0x24, 0x01, 0x01],
+            ttq: [0x36, 0x00, 0x40, 0x00],
+        }
+    }
+}
+
+/// PAN with only first 6 (IIN) and last 4 digits kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaskedPan {
+    first: [u8; 6],
+    last: [u8; 4],
+
+    /// Total number of digits
+    pub len: u8,
+}
+
+impl MaskedPan {
+    /// Builds masked PAN from BCD digits (PAN tag or Track 2 data), full
+    /// number is never copied
+    fn from_bcd(bcd: &[u8]) -> Option<Self> {
+        let mut pan = Self {
+            first: [0; 6],
+            last: [0; 4],
+            len: 0,
+        };
+
+        let digits = bcd.iter().flat_map(|b| [b >> 4, b & 0x0F]);
+        for digit in digits.take_while(|&d| d <= 9) {
+            if pan.len == MAX_PAN_DIGITS {
+                return None;
+            }
+
+            if (pan.len as usize) < pan.first.len() {
+                pan.first[pan.len as usize] = digit;
+            }
+
+            pan.last.rotate_left(1);
+            pan.last[3] = digit;
+            pan.len += 1;
+        }
+
+        // more than 10 digits, so at least one is left masked
+        (pan.len > 10).then_some(pan)
+    }
+
+    /// Issuer Identification Number (first 6 digits)
+    pub fn iin(&self) -> u32 {
+        self.first.iter().fold(0, |acc, &d| acc * 10 + d as u32)
+    }
+
+    pub fn last_digits(&self) -> u16 {
+        self.last.iter().fold(0, |acc, &d| acc * 10 + d as u16)
+    }
+}
+
+impl Display for MaskedPan {
+    /// e.g. `411111******1111`
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for d in self.first {
+            write!(f, "{d}")?;
+        }
+
+        for _ in 0..self.len - 10 {
+            f.write_str("*")?;
+        }
+
+        for d in self.last {
+            write!(f, "{d}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiry {
+    pub year: u16,
+    pub month: u8,
+}
+
+/// Public data of EMV contactless card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmvCard {
+    pub aid: Vec<u8, 16>,
+    pub label: String<16>,
+    pub pan: Option<MaskedPan>,
+    pub expiry: Option<Expiry>,
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Reads public data of payment card (SELECT PPSE, SELECT AID,
+    /// GET PROCESSING OPTIONS, READ RECORD). Only masked PAN is returned,
+    /// note that full PAN passes through response buffers on stack (also
+    /// of ISO-DEP layer), which are not cleared.
+    pub async fn emv_read_public_data(
+        &mut self,
+        iso: &mut IsoDep,
+        terminal: &TerminalData,
+    ) -> Result<EmvCard, PCDErrorCode> {
+        let mut resp = [0; MAX_RESPONSE];
+        let mut card = EmvCard {
+            aid: Vec::new(),
+            label: String::new(),
+            pan: None,
+            expiry: None,
+        };
+
+        // directory of payment applications
+        let fci = self.emv_select(iso, PPSE_NAME, &mut resp).await?;
+        let entry = select_directory_entry(fci).ok_or(PCDErrorCode::Protocol)?;
+        card.aid = Vec::from_slice(entry.0).map_err(|_| PCDErrorCode::Protocol)?;
+        set_label(&mut card.label, entry.1);
+
+        // application FCI with PDOL
+        let aid = card.aid.clone();
+        let fci = self.emv_select(iso, &aid, &mut resp).await?;
+        if let Some(label) =
+            tlv::find(fci, TAG_PREFERRED_NAME).or(tlv::find(fci, TAG_APPLICATION_LABEL))
+        {
+            set_label(&mut card.label, label.value);
+        }
+
+        let mut pdol_data = [0; 128];
+        let pdol_len = match tlv::find(fci, TAG_PDOL) {
+            Some(pdol) => {
+                let unpredictable = ((self.get_current_time)() as u32).to_be_bytes();
+                fill_dol(pdol.value, terminal, &unpredictable, &mut pdol_data)?
+            }
+            None => 0,
+        };
+
+        // GET PROCESSING OPTIONS
+        let mut gpo = [0; 131];
+        let mut writer = tlv::TlvWriter::new(&mut gpo);
+        writer.write(TAG_COMMAND_TEMPLATE, &pdol_data[..pdol_len])?;
+        let gpo_len = writer.len();
+
+        let cmd = Command::new(0x80, INS_GET_PROCESSING_OPTIONS, 0x00, 0x00)
+            .with_data(&gpo[..gpo_len])
+            .with_le(0);
+        let data = self
+            .transmit_apdu(iso, &cmd, &mut resp)
+            .await?
+            .into_result()?;
+
+        let mut afl: Vec<u8, MAX_AFL> = Vec::new();
+        match TlvIter::new(data).next() {
+            // format 1: AIP (2 bytes) + AFL
+            Some(Ok(tlv)) if tlv.tag == TAG_RESPONSE_FORMAT_1 && tlv.value.len() >= 2 => {
+                _ = afl.extend_from_slice(&tlv.value[2..]);
+            }
+            Some(Ok(_)) => {
+                if let Some(tlv) = tlv::find(data, TAG_AFL) {
+                    _ = afl.extend_from_slice(tlv.value);
+                }
+
+                // some cards (e.g. Visa qVSDC) return Track 2 directly
+                parse_card_data(data, &mut card);
+            }
+            _ => return Err(PCDErrorCode::Protocol),
+        }
+
+        // READ RECORD of every file in AFL
+        for entry in afl.chunks_exact(4) {
+            if card.pan.is_some() && card.expiry.is_some() {
+                break;
+            }
+
+            let sfi = entry[0] >> 3;
+            for record in entry[1].max(1)..=entry[2] {
+                let cmd = Command::new(0x00, INS_READ_RECORD, record, (sfi << 3) | 0x04).with_le(0);
+                let res = self.transmit_apdu(iso, &cmd, &mut resp).await;
+
+                if let Ok(data) = res.and_then(|r| r.into_result()) {
+                    parse_card_data(data, &mut card);
+                }
+            }
+        }
+
+        Ok(card)
+    }
+
+    /// SELECT by name, returns FCI
+    async fn emv_select<'b>(
+        &mut self,
+        iso: &mut IsoDep,
+        name: &[u8],
+        resp: &'b mut [u8],
+    ) -> Result<&'b [u8], PCDErrorCode> {
+        let cmd = Command::new(0x00, INS_SELECT, 0x04, 0x00)
+            .with_data(name)
+            .with_le(0);
+
+        self.transmit_apdu(iso, &cmd, resp).await?.into_result()
+    }
+}
+
+/// Picks application with the highest priority (lowest 87 value) from PPSE
+/// FCI, returns its AID and label
+fn select_directory_entry(fci: &[u8]) -> Option<(&[u8], &[u8])> {
+    let directory = tlv::find(fci, Tag(0xBF0C))?;
+
+    let mut best: Option<(u8, &[u8], &[u8])> = None;
+    for entry in directory.children().flatten() {
+        if entry.tag != TAG_DIRECTORY_ENTRY {
+            continue;
+        }
+
+        let Some(aid) = entry.find(TAG_AID) else {
+            continue;
+        };
+
+        let label = entry
+            .find(TAG_APPLICATION_LABEL)
+            .map_or(&[][..], |l| l.value);
+
+        // no priority (or 0) means the lowest one
+        let priority = match entry.find(TAG_PRIORITY).and_then(|p| p.value.first()) {
+            Some(&p) if p & 0x0F != 0 => p & 0x0F,
+            _ => 0x10,
+        };
+
+        let higher = match best {
+            Some((best, _, _)) => priority < best,
+            None => true,
+        };
+
+        if higher {
+            best = Some((priority, aid.value, label));
+        }
+    }
+
+    best.map(|(_, aid, label)| (aid, label))
+}
+
+/// Fills Data Object List with terminal data (unknown tags are zeroed)
+fn fill_dol(
+    dol: &[u8],
+    terminal: &TerminalData,
+    unpredictable: &[u8; 4],
+    out: &mut [u8],
+) -> Result<usize, PCDErrorCode> {
+    let mut pos = 0;
+    let mut dol = dol;
+
+    while !dol.is_empty() {
+        let (tag, tag_len) = Tag::parse(dol)?;
+        let (len, len_len) = tlv::parse_length(&dol[tag_len..])?;
+        dol = &dol[tag_len + len_len..];
+
+        if pos + len > out.len() {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        let value: &[u8] = match tag.0 {
+            0x9F66 => &terminal.ttq,
+            0x9F1A => &terminal.country_code,
+            0x5F2A => &terminal.currency_code,
+            0x9A => &terminal.transaction_date,
+            0x9F37 => unpredictable,
+            // transaction type: purchase
+            0x9C => &[0x00],
+            // terminal type: attended, online capable merchant
+            0x9F35 => &[0x22],
+            _ => &[],
+        };
+
+        let field = &mut out[pos..pos + len];
+        field.fill(0);
+        let n = value.len().min(len);
+        field[..n].copy_from_slice(&value[..n]);
+        pos += len;
+    }
+
+    Ok(pos)
+}
+
+fn parse_card_data(data: &[u8], card: &mut EmvCard) {
+    if card.pan.is_none() {
+        card.pan = tlv::find(data, TAG_PAN).and_then(|pan| MaskedPan::from_bcd(pan.value));
+    }
+
+    if card.expiry.is_none() {
+        card.expiry = tlv::find(data, TAG_EXPIRY).and_then(|e| parse_expiry(e.value));
+    }
+
+    // Track 2 Equivalent Data: PAN 'D' YYMM ...
+    if let Some(track2) = tlv::find(data, TAG_TRACK2) {
+        if card.pan.is_none() {
+            card.pan = MaskedPan::from_bcd(track2.value);
+        }
+
+        if card.expiry.is_none() {
+            let mut digits = track2.value.iter().flat_map(|b| [b >> 4, b & 0x0F]);
+            if digits.position(|d| d == 0x0D).is_some() {
+                let mut yymm = [0; 4];
+                for d in yymm.iter_mut() {
+                    *d = digits.next().unwrap_or(0xFF);
+                }
+
+                card.expiry = parse_expiry(&[(yymm[0] << 4) | yymm[1], (yymm[2] << 4) | yymm[3]]);
+            }
+        }
+    }
+}
+
+/// BCD YYMM(DD)
+fn parse_expiry(bcd: &[u8]) -> Option<Expiry> {
+    let decode = |b: u8| -> Option<u8> {
+        let (hi, lo) = (b >> 4, b & 0x0F);
+        (hi <= 9 && lo <= 9).then_some(hi * 10 + lo)
+    };
+
+    let year = decode(*bcd.first()?)?;
+    let month = decode(*bcd.get(1)?)?;
+    if !(1..=12).contains(&month) {
+        return None;
+    }
+
+    Some(Expiry {
+        year: 2000 + year as u16,
+        month,
+    })
+}
+
+fn set_label(label: &mut String<16>, bytes: &[u8]) {
+    label.clear();
+    for &b in bytes.iter().take(16) {
+        _ = label.push(if b.is_ascii_graphic() || b == b' ' {
+            b as char
+        } else {
+            '?'
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use super::*;
+
+    #[test]
+    fn masked_pan() {
+        // 16 digits, Track 2 separator ends the number
+        let pan = MaskedPan::from_bcd(&[0x41, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0xD2]);
+        let pan = pan.unwrap();
+        assert_eq!(pan.len, 16);
+        assert_eq!(pan.iin(), 411111);
+        assert_eq!(pan.last_digits(), 1111);
+
+        let mut text: String<19> = String::new();
+        write!(text, "{pan}").unwrap();
+        assert_eq!(text, "411111******1111");
+    }
+
+    #[test]
+    fn masked_pan_too_short() {
+        // 10 digits would be shown unmasked
+        assert_eq!(MaskedPan::from_bcd(&[0x12, 0x34, 0x56, 0x78, 0x90]), None);
+
+        let pan = MaskedPan::from_bcd(&[0x12, 0x34, 0x56, 0x78, 0x90, 0x1F]).unwrap();
+        assert_eq!(pan.len, 11);
+    }
+
+    #[test]
+    fn masked_pan_too_long() {
+        let mut bcd = [0x11; 10];
+        bcd[9] = 0x1F;
+        assert_eq!(MaskedPan::from_bcd(&bcd).unwrap().len, 19);
+
+        bcd[9] = 0x11;
+        assert_eq!(MaskedPan::from_bcd(&bcd), None);
+
+        // digit count would overflow u8
+        assert_eq!(MaskedPan::from_bcd(&[0x11; 200]), None);
+    }
+}
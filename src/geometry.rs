@@ -0,0 +1,249 @@
+use crate::consts::PICCType;
+
+/// Sectors 0..32 have 4 blocks, sectors 32..40 (4K only) have 16 blocks
+const SMALL_SECTORS: u8 = 32;
+const SMALL_SECTOR_BLOCKS: u8 = 4;
+const LARGE_SECTOR_BLOCKS: u8 = 16;
+
+/// First block of large sectors
+const LARGE_SECTORS_START: u8 = SMALL_SECTORS * SMALL_SECTOR_BLOCKS;
+
+pub const MAX_SECTORS: u8 = 40;
+
+/// MIFARE Classic memory layout (also Plus in SL1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClassicSize {
+    Mini,
+    Classic1K,
+    /// MIFARE Plus 2K in SL1
+    Classic2K,
+    Classic4K,
+}
+
+impl ClassicSize {
+    pub fn from_picc_type(picc_type: &PICCType) -> Option<Self> {
+        match picc_type {
+            PICCType::PiccTypeMifareMini => Some(ClassicSize::Mini),
+            PICCType::PiccTypeMifare1K => Some(ClassicSize::Classic1K),
+            PICCType::PiccTypeMifare4K => Some(ClassicSize::Classic4K),
+            _ => None,
+        }
+    }
+
+    pub fn sector_count(&self) -> u8 {
+        match self {
+            ClassicSize::Mini => 5,
+            ClassicSize::Classic1K => 16,
+            ClassicSize::Classic2K => 32,
+            ClassicSize::Classic4K => 40,
+        }
+    }
+
+    pub fn block_count(&self) -> u16 {
+        match self {
+            ClassicSize::Mini => 20,
+            ClassicSize::Classic1K => 64,
+            ClassicSize::Classic2K => 128,
+            ClassicSize::Classic4K => 256,
+        }
+    }
+
+    pub fn contains(&self, block: BlockAddr) -> bool {
+        (block.0 as u16) < self.block_count()
+    }
+
+    pub fn sectors(&self) -> impl DoubleEndedIterator<Item = Sector> {
+        (0..self.sector_count()).map(Sector)
+    }
+
+    pub fn blocks(&self) -> impl DoubleEndedIterator<Item = BlockAddr> {
+        (0..self.block_count()).map(|block| BlockAddr(block as u8))
+    }
+
+    /// All blocks except manufacturer block and sector trailers
+    pub fn data_blocks(&self) -> impl DoubleEndedIterator<Item = BlockAddr> {
+        self.blocks().filter(BlockAddr::is_data)
+    }
+}
+
+/// MIFARE Classic sector number (0..40)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sector(u8);
+
+impl Sector {
+    pub fn new(sector: u8) -> Option<Self> {
+        (sector < MAX_SECTORS).then_some(Self(sector))
+    }
+
+    pub fn index(&self) -> u8 {
+        self.0
+    }
+
+    pub fn block_count(&self) -> u8 {
+        if self.0 < SMALL_SECTORS {
+            SMALL_SECTOR_BLOCKS
+        } else {
+            LARGE_SECTOR_BLOCKS
+        }
+    }
+
+    pub fn first_block(&self) -> BlockAddr {
+        if self.0 < SMALL_SECTORS {
+            BlockAddr(self.0 * SMALL_SECTOR_BLOCKS)
+        } else {
+            BlockAddr(LARGE_SECTORS_START + (self.0 - SMALL_SECTORS) * LARGE_SECTOR_BLOCKS)
+        }
+    }
+
+    /// Last block of sector (keys and access bits)
+    pub fn trailer(&self) -> BlockAddr {
+        BlockAddr(self.first_block().0 + (self.block_count() - 1))
+    }
+
+    pub fn blocks(&self) -> impl DoubleEndedIterator<Item = BlockAddr> {
+        (self.first_block().0..=self.trailer().0).map(BlockAddr)
+    }
+
+    /// Blocks of sector except manufacturer block and trailer
+    pub fn data_blocks(&self) -> impl DoubleEndedIterator<Item = BlockAddr> {
+        self.blocks().filter(BlockAddr::is_data)
+    }
+}
+
+/// Absolute MIFARE Classic block address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockAddr(pub u8);
+
+impl BlockAddr {
+    pub fn sector(&self) -> Sector {
+        if self.0 < LARGE_SECTORS_START {
+            Sector(self.0 / SMALL_SECTOR_BLOCKS)
+        } else {
+            Sector(SMALL_SECTORS + (self.0 - LARGE_SECTORS_START) / LARGE_SECTOR_BLOCKS)
+        }
+    }
+
+    /// Index of block inside its sector
+    pub fn offset(&self) -> u8 {
+        self.0 - self.sector().first_block().0
+    }
+
+    pub fn is_trailer(&self) -> bool {
+        *self == self.sector().trailer()
+    }
+
+    /// Block 0 (UID and manufacturer data, read-only on genuine cards)
+    pub fn is_manufacturer(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_data(&self) -> bool {
+        !self.is_trailer() && !self.is_manufacturer()
+    }
+
+    /// Trailer of sector this block belongs to
+    pub fn trailer(&self) -> BlockAddr {
+        self.sector().trailer()
+    }
+
+    /// Access bits group of block (0..=2 data, 3 trailer). In 16 block
+    /// sectors groups 0..=2 cover 5 blocks each.
+    pub fn access_group(&self) -> u8 {
+        match self.sector().block_count() {
+            SMALL_SECTOR_BLOCKS => self.offset(),
+            _ => self.offset() / 5,
+        }
+    }
+}
+
+impl From<u8> for BlockAddr {
+    fn from(block: u8) -> Self {
+        Self(block)
+    }
+}
+
+impl From<BlockAddr> for u8 {
+    fn from(block: BlockAddr) -> Self {
+        block.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_sector() {
+        assert_eq!(BlockAddr(0).sector().index(), 0);
+        assert_eq!(BlockAddr(127).sector().index(), 31);
+        assert_eq!(BlockAddr(128).sector().index(), 32);
+        assert_eq!(BlockAddr(143).sector().index(), 32);
+        assert_eq!(BlockAddr(144).sector().index(), 33);
+        assert_eq!(BlockAddr(255).sector().index(), 39);
+    }
+
+    #[test]
+    fn large_sectors() {
+        let sector = Sector::new(32).unwrap();
+        assert_eq!(sector.block_count(), 16);
+        assert_eq!(sector.first_block(), BlockAddr(128));
+        assert_eq!(sector.trailer(), BlockAddr(143));
+        assert_eq!(sector.blocks().count(), 16);
+
+        let last = Sector::new(39).unwrap();
+        assert_eq!(last.first_block(), BlockAddr(240));
+        assert_eq!(last.trailer(), BlockAddr(255));
+        assert_eq!(Sector::new(40), None);
+
+        let small = Sector::new(31).unwrap();
+        assert_eq!(small.block_count(), 4);
+        assert_eq!(small.trailer(), BlockAddr(127));
+    }
+
+    #[test]
+    fn trailers() {
+        assert!(BlockAddr(3).is_trailer());
+        assert!(BlockAddr(127).is_trailer());
+        assert!(!BlockAddr(131).is_trailer());
+        assert!(BlockAddr(143).is_trailer());
+        assert!(BlockAddr(255).is_trailer());
+
+        assert_eq!(BlockAddr(129).trailer(), BlockAddr(143));
+        assert_eq!(BlockAddr(255).trailer(), BlockAddr(255));
+        assert_eq!(BlockAddr(130).offset(), 2);
+    }
+
+    #[test]
+    fn access_groups() {
+        let groups: [u8; 4] = core::array::from_fn(|i| BlockAddr(4 + i as u8).access_group());
+        assert_eq!(groups, [0, 1, 2, 3]);
+
+        // 16 block sector, groups of 5 blocks and trailer
+        let groups: [u8; 16] = core::array::from_fn(|i| BlockAddr(240 + i as u8).access_group());
+        assert_eq!(groups, [0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 3]);
+    }
+
+    #[test]
+    fn sizes() {
+        for size in [
+            ClassicSize::Mini,
+            ClassicSize::Classic1K,
+            ClassicSize::Classic2K,
+            ClassicSize::Classic4K,
+        ] {
+            let blocks: u16 = size.sectors().map(|s| s.block_count() as u16).sum();
+            assert_eq!(blocks, size.block_count());
+            assert_eq!(size.blocks().count(), size.block_count() as usize);
+        }
+
+        let size = ClassicSize::Classic4K;
+        assert!(size.contains(BlockAddr(255)));
+        assert!(!ClassicSize::Classic2K.contains(BlockAddr(128)));
+
+        // without block 0 and 40 trailers
+        assert_eq!(size.data_blocks().count(), 256 - 1 - 40);
+        assert_eq!(size.blocks().last(), Some(BlockAddr(255)));
+    }
+}
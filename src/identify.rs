@@ -0,0 +1,617 @@
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    consts::{Atqa, PCDErrorCode, PICCCommand, Uid},
+    iso_dep::IsoDep,
+    mifare::{KeyType, MifareKey},
+    MFRC522,
+};
+
+/// Key A of MIFARE Classic EV1 originality signature sector (17)
+const CLASSIC_EV1_SIGNATURE_KEY: MifareKey = MifareKey([0x5C, 0x8F, 0xF9, 0x99, 0x0D, 0xA2]);
+const CLASSIC_EV1_SIGNATURE_BLOCK: u8 = 69;
+
+const NXP_VENDOR_ID: u8 = 0x04;
+
+/// MIFARE Plus WritePerso, only available in SL0
+const PLUS_CMD_WRITE_PERSO: u8 = 0xA8;
+
+/// Status of MIFARE Plus command addressing non-existent block
+const PLUS_INVALID_BLOCK_NUMBER: u8 = 0x09;
+
+/// MIFARE Plus First Authenticate (AES), accepted in every security level
+const PLUS_CMD_FIRST_AUTHENTICATE: u8 = 0x70;
+
+/// Security level 3 switch key (0x9003, LSB first), present in SL1 and SL2
+const PLUS_LEVEL3_SWITCH_KEY: [u8; 2] = [0x03, 0x90];
+const PLUS_STATUS_OK: u8 = 0x90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesfireGeneration {
+    /// MF3ICD40
+    Original,
+    Ev1,
+    Ev2,
+    Ev3,
+    Light,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlusVersion {
+    S,
+    X,
+    Ev1,
+    Ev2,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLevel {
+    /// Not personalized yet (same SAK as SL3, told apart by WritePerso probe)
+    Sl0,
+    Sl1,
+    Sl2,
+    Sl3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardModel {
+    Unknown,
+    MifareClassicMini,
+    MifareClassic1K {
+        ev1: bool,
+    },
+    MifareClassic4K {
+        ev1: bool,
+    },
+    MifareUltralight,
+    MifareUltralightC,
+    /// MF0UL11 or MF0UL21 (see `memory_size`)
+    MifareUltralightEv1,
+    Ntag213,
+    Ntag215,
+    Ntag216,
+    /// Other NTAG product, see `version`
+    Ntag,
+    MifarePlus {
+        version: PlusVersion,
+        security_level: SecurityLevel,
+    },
+    MifareDesfire(DesfireGeneration),
+    /// SmartMX / JCOP based card (possibly with MIFARE Classic emulation)
+    SmartMx,
+    /// Unknown ISO/IEC 14443-4 compliant card
+    Iso14443_4,
+    Tnp3xxx,
+}
+
+/// GET_VERSION response (same layout for Ultralight/NTAG and DESFire/Plus)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub vendor: u8,
+    pub product_type: u8,
+    pub product_subtype: u8,
+    pub major: u8,
+    pub minor: u8,
+    pub storage_size: u8,
+    pub protocol: u8,
+}
+
+impl VersionInfo {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            vendor: bytes[0],
+            product_type: bytes[1],
+            product_subtype: bytes[2],
+            major: bytes[3],
+            minor: bytes[4],
+            storage_size: bytes[5],
+            protocol: bytes[6],
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 7] {
+        [
+            self.vendor,
+            self.product_type,
+            self.product_subtype,
+            self.major,
+            self.minor,
+            self.storage_size,
+            self.protocol,
+        ]
+    }
+
+    /// Storage size byte decoded as bytes (lower bound if LSB is set)
+    pub fn storage_bytes(&self) -> u32 {
+        1u32 << (self.storage_size >> 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CardInfo {
+    pub model: CardModel,
+
+    /// Total memory in bytes (if known)
+    pub memory_size: Option<u32>,
+
+    /// GET_VERSION response (if card supports it)
+    pub version: Option<VersionInfo>,
+}
+
+impl CardInfo {
+    fn new(model: CardModel, memory_size: Option<u32>) -> Self {
+        Self {
+            model,
+            memory_size,
+            version: None,
+        }
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Identifies exact product of selected card (NXP AN10833 procedure)
+    /// using ATQA, SAK, GET_VERSION, RATS/ATS and Ultralight C, Classic EV1
+    /// and MIFARE Plus auth probes.
+    ///
+    /// Some of the probes make card leave ACTIVE state, so card is
+    /// woken up and selected again before returning.
+    pub async fn picc_identify(&mut self, uid: &Uid) -> Result<CardInfo, PCDErrorCode> {
+        let sak = uid.sak & 0x7F;
+        if sak & 0x04 != 0 {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let info = if sak & 0x20 != 0 {
+            self.identify_iso14443_4(sak).await?
+        } else {
+            match sak {
+                0x00 => self.identify_ultralight(uid).await?,
+                0x08 | 0x18 => self.identify_classic(uid).await?,
+                0x09 => CardInfo::new(CardModel::MifareClassicMini, Some(320)),
+                0x10 | 0x11 if self.identify_plus_aes(uid).await? => CardInfo::new(
+                    CardModel::MifarePlus {
+                        version: PlusVersion::Unknown,
+                        security_level: SecurityLevel::Sl2,
+                    },
+                    Some(if sak == 0x10 { 2048 } else { 4096 }),
+                ),
+                0x01 => CardInfo::new(CardModel::Tnp3xxx, None),
+                _ => CardInfo::new(CardModel::Unknown, None),
+            }
+        };
+
+        let mut uid = uid.clone();
+        _ = self.picc_halta().await;
+        self.pcd_stop_crypto1().await?;
+        self.picc_wakeup_uid(&mut uid).await?;
+
+        Ok(info)
+    }
+
+    /// GET_VERSION (0x60) of Ultralight EV1 / NTAG21x.
+    /// Cards without this command NAK it and go to IDLE/HALT.
+    pub async fn picc_get_version(&mut self) -> Result<VersionInfo, PCDErrorCode> {
+        let mut buff = [0; 4];
+        buff[0] = PICCCommand::PICC_CMD_UL_GET_VERSION;
+        self.pcd_calc_crc_single_buf(&mut buff, 1, 1).await?;
+
+        let mut back = [0; 10];
+        let mut back_len = 10;
+        self.pcd_transceive_data(&buff, 3, &mut back, &mut back_len, &mut 0, 0, true)
+            .await?;
+
+        if back_len != 10 {
+            return Err(PCDErrorCode::Error);
+        }
+
+        // first byte is fixed header (0x00)
+        Ok(VersionInfo::from_bytes(&back[1..8]))
+    }
+
+    async fn identify_ultralight(&mut self, uid: &Uid) -> Result<CardInfo, PCDErrorCode> {
+        if let Ok(version) = self.picc_get_version().await {
+            return Ok(ultralight_info(version));
+        }
+
+        // NAK of GET_VERSION put card into IDLE
+        let mut uid = uid.clone();
+        self.picc_wakeup_uid(&mut uid).await?;
+
+        let mut buff = [0; 4];
+        buff[0] = PICCCommand::PICC_CMD_UL_AUTHENTICATE;
+        buff[1] = 0x00;
+        self.pcd_calc_crc_single_buf(&mut buff, 2, 2).await?;
+
+        // Ultralight C answers with 0xAF + ek(RndB)
+        let mut back = [0; 11];
+        let mut back_len = 11;
+        let res = self
+            .pcd_transceive_data(&buff, 4, &mut back, &mut back_len, &mut 0, 0, true)
+            .await;
+
+        if res.is_ok() && back_len == 11 && back[0] == 0xAF {
+            Ok(CardInfo::new(CardModel::MifareUltralightC, Some(192)))
+        } else {
+            Ok(CardInfo::new(CardModel::MifareUltralight, Some(64)))
+        }
+    }
+
+    /// SAK 0x08/0x18: MIFARE Classic 1K/4K (EV1 is recognized by its
+    /// originality signature key) or MIFARE Plus in SL1 (size by ATQA:
+    /// 0x0004/0x0044 - 2K, 0x0002/0x0042 - 4K)
+    async fn identify_classic(&mut self, uid: &Uid) -> Result<CardInfo, PCDErrorCode> {
+        let ev1 = self.identify_classic_ev1(uid).await?;
+        let plus = !ev1 && self.identify_plus_aes(uid).await?;
+        Ok(classic_info(uid.sak, &uid.atqa, ev1, plus))
+    }
+
+    /// First Authenticate to SL3 switch key, MIFARE Plus (SL1/SL2) answers
+    /// with ek(RndB), MIFARE Classic NAKs it. Authentication is not
+    /// finished, so nothing changes. Card is selected again first.
+    async fn identify_plus_aes(&mut self, uid: &Uid) -> Result<bool, PCDErrorCode> {
+        self.picc_reselect(uid).await?;
+
+        let mut buff = [0; 6];
+        buff[0] = PLUS_CMD_FIRST_AUTHENTICATE;
+        buff[1..3].copy_from_slice(&PLUS_LEVEL3_SWITCH_KEY);
+        buff[3] = 0x00; // no PCD capabilities
+        self.pcd_calc_crc_single_buf(&mut buff, 4, 4).await?;
+
+        // status + ek(RndB) + CRC
+        let mut back = [0; 19];
+        let mut back_len = 19;
+        let res = self
+            .pcd_transceive_data(&buff, 6, &mut back, &mut back_len, &mut 0, 0, true)
+            .await;
+
+        Ok(res.is_ok() && back_len == 19 && back[0] == PLUS_STATUS_OK)
+    }
+
+    /// Only Classic EV1 accepts its originality signature sector key
+    async fn identify_classic_ev1(&mut self, uid: &Uid) -> Result<bool, PCDErrorCode> {
+        let res = self
+            .pcd_authenticate(
+                KeyType::A,
+                CLASSIC_EV1_SIGNATURE_BLOCK,
+                &CLASSIC_EV1_SIGNATURE_KEY,
+                uid,
+            )
+            .await;
+
+        match res {
+            Ok(_) => Ok(true),
+            Err(PCDErrorCode::AuthenticationFailed) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Security level of MIFARE Plus card. SL1 card has the same SAK as
+    /// MIFARE Classic, so it's up to caller to know that card is Plus
+    /// (see `picc_identify`).
+    ///
+    /// SL0/SL3 card is probed through ISO-DEP, so it's woken up and selected
+    /// again before returning.
+    pub async fn picc_plus_security_level(
+        &mut self,
+        uid: &Uid,
+    ) -> Result<SecurityLevel, PCDErrorCode> {
+        match uid.sak & 0x7F {
+            0x08 | 0x18 | 0x28 | 0x38 => Ok(SecurityLevel::Sl1),
+            0x10 | 0x11 => Ok(SecurityLevel::Sl2),
+            0x20 => {
+                let mut iso = self.iso_dep_activate(None).await?;
+                let sl0 = self.identify_plus_sl0(&mut iso).await;
+                _ = self.iso_dep_deselect(&mut iso).await;
+
+                let mut uid = uid.clone();
+                self.picc_wakeup_uid(&mut uid).await?;
+
+                Ok(if sl0 {
+                    SecurityLevel::Sl0
+                } else {
+                    SecurityLevel::Sl3
+                })
+            }
+            _ => Err(PCDErrorCode::Invalid),
+        }
+    }
+
+    /// WritePerso to non-existent block 0x9090: SL0 card rejects only the
+    /// block number, in higher levels the command itself is not allowed.
+    /// Nothing gets written.
+    async fn identify_plus_sl0(&mut self, iso: &mut IsoDep) -> bool {
+        let mut cmd = [0; 3 + 16];
+        cmd[..3].copy_from_slice(&[PLUS_CMD_WRITE_PERSO, 0x90, 0x90]);
+
+        let mut resp = [0; 8];
+        matches!(
+            self.iso_dep_transceive(iso, &cmd, &mut resp).await,
+            Ok(len) if len >= 1 && resp[0] == PLUS_INVALID_BLOCK_NUMBER
+        )
+    }
+
+    async fn identify_iso14443_4(&mut self, sak: u8) -> Result<CardInfo, PCDErrorCode> {
+        let mut iso = match self.iso_dep_activate(None).await {
+            Ok(iso) => iso,
+            Err(PCDErrorCode::Timeout) => return Ok(CardInfo::new(CardModel::Iso14443_4, None)),
+            Err(e) => return Err(e),
+        };
+
+        // native GetVersion, first frame: 0xAF (additional frame) + 7 bytes
+        let mut resp = [0; 64];
+        let version = match self.iso_dep_transceive(&mut iso, &[0x60], &mut resp).await {
+            Ok(len) if len >= 8 && resp[0] == 0xAF => Some(VersionInfo::from_bytes(&resp[1..8])),
+            _ => None,
+        };
+
+        let plus = match version {
+            Some(version) if version.vendor == NXP_VENDOR_ID => version.product_type & 0x0F == 0x02,
+            _ => is_plus_historical(iso.ats.historical_bytes()),
+        };
+
+        let security_level = match sak {
+            0x20 if plus && self.identify_plus_sl0(&mut iso).await => SecurityLevel::Sl0,
+            0x20 => SecurityLevel::Sl3,
+            _ => SecurityLevel::Sl1,
+        };
+        _ = self.iso_dep_deselect(&mut iso).await;
+
+        Ok(match version {
+            Some(version) if version.vendor == NXP_VENDOR_ID => {
+                iso14443_4_version_info(version, security_level)
+            }
+            _ => CardInfo::new(
+                ats_model(sak, iso.ats.historical_bytes(), security_level),
+                None,
+            ),
+        })
+    }
+}
+
+/// Ultralight/NTAG by GET_VERSION response
+fn ultralight_info(version: VersionInfo) -> CardInfo {
+    let (model, memory_size) = match (version.product_type, version.storage_size) {
+        (0x03, 0x0B) => (CardModel::MifareUltralightEv1, Some(80)),
+        (0x03, 0x0E) => (CardModel::MifareUltralightEv1, Some(164)),
+        (0x03, _) => (CardModel::MifareUltralightEv1, None),
+        (0x04, 0x0F) => (CardModel::Ntag213, Some(180)),
+        (0x04, 0x11) => (CardModel::Ntag215, Some(540)),
+        (0x04, 0x13) => (CardModel::Ntag216, Some(924)),
+        (0x04, _) => (CardModel::Ntag, None),
+        _ => (CardModel::Unknown, None),
+    };
+
+    CardInfo {
+        model,
+        memory_size,
+        version: Some(version),
+    }
+}
+
+/// SAK 0x08/0x18 card, `plus` if it answered MIFARE Plus AES probe
+fn classic_info(sak: u8, atqa: &Atqa, ev1: bool, plus: bool) -> CardInfo {
+    if plus {
+        return CardInfo::new(
+            CardModel::MifarePlus {
+                version: PlusVersion::Unknown,
+                security_level: SecurityLevel::Sl1,
+            },
+            plus_memory_size(atqa),
+        );
+    }
+
+    match sak & 0x7F {
+        0x08 => CardInfo::new(CardModel::MifareClassic1K { ev1 }, Some(1024)),
+        _ => CardInfo::new(CardModel::MifareClassic4K { ev1 }, Some(4096)),
+    }
+}
+
+/// DESFire/Plus by native GetVersion response of NXP card
+fn iso14443_4_version_info(version: VersionInfo, security_level: SecurityLevel) -> CardInfo {
+    let model = match version.product_type & 0x0F {
+        0x01 => CardModel::MifareDesfire(match version.major >> 4 {
+            0 if version.major == 0 => DesfireGeneration::Original,
+            0 => DesfireGeneration::Ev1,
+            1 | 2 => DesfireGeneration::Ev2,
+            _ => DesfireGeneration::Ev3,
+        }),
+        0x08 => CardModel::MifareDesfire(DesfireGeneration::Light),
+        0x02 => CardModel::MifarePlus {
+            version: match version.major {
+                0x11 => PlusVersion::Ev1,
+                0x22 => PlusVersion::Ev2,
+                _ => PlusVersion::Unknown,
+            },
+            security_level,
+        },
+        _ => CardModel::SmartMx,
+    };
+
+    CardInfo {
+        model,
+        memory_size: Some(version.storage_bytes()),
+        version: Some(version),
+    }
+}
+
+/// ISO-DEP card without (NXP) GetVersion, by SAK and ATS historical bytes
+fn ats_model(sak: u8, historical: &[u8], security_level: SecurityLevel) -> CardModel {
+    // MIFARE Plus S/X don't support GET_VERSION, but can be recognized by
+    // their historical bytes (C1 05 2F 2F 0X ..)
+    if is_plus_historical(historical) {
+        CardModel::MifarePlus {
+            version: if historical[4] & 0x01 != 0 {
+                PlusVersion::X
+            } else {
+                PlusVersion::S
+            },
+            security_level,
+        }
+    } else if sak == 0x28 || sak == 0x38 || contains(historical, b"JCOP") {
+        CardModel::SmartMx
+    } else {
+        CardModel::Iso14443_4
+    }
+}
+
+fn plus_memory_size(atqa: &Atqa) -> Option<u32> {
+    match atqa.bit_frame_anticollision() {
+        0x04 => Some(2048),
+        0x02 => Some(4096),
+        _ => None,
+    }
+}
+
+fn is_plus_historical(historical: &[u8]) -> bool {
+    historical.len() >= 5 && historical[..2] == [0xC1, 0x05]
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GET_VERSION responses without the 0x00 header
+    const NTAG213: [u8; 7] = [0x04, 0x04, 0x02, 0x01, 0x00, 0x0F, 0x03];
+    const NTAG215: [u8; 7] = [0x04, 0x04, 0x02, 0x01, 0x00, 0x11, 0x03];
+    const NTAG216: [u8; 7] = [0x04, 0x04, 0x02, 0x01, 0x00, 0x13, 0x03];
+    const MF0UL11: [u8; 7] = [0x04, 0x03, 0x01, 0x01, 0x00, 0x0B, 0x03];
+    const MF0UL21: [u8; 7] = [0x04, 0x03, 0x01, 0x01, 0x00, 0x0E, 0x03];
+
+    // native GetVersion hardware info
+    const DESFIRE_D40: [u8; 7] = [0x04, 0x01, 0x01, 0x00, 0x02, 0x18, 0x05];
+    const DESFIRE_EV1_8K: [u8; 7] = [0x04, 0x01, 0x01, 0x01, 0x00, 0x1A, 0x05];
+    const DESFIRE_EV2_4K: [u8; 7] = [0x04, 0x01, 0x01, 0x12, 0x00, 0x18, 0x05];
+    const DESFIRE_EV3_4K: [u8; 7] = [0x04, 0x01, 0x01, 0x30, 0x00, 0x18, 0x05];
+    const DESFIRE_LIGHT: [u8; 7] = [0x04, 0x08, 0x01, 0x30, 0x00, 0x13, 0x05];
+    const PLUS_EV1_2K: [u8; 7] = [0x04, 0x02, 0x01, 0x11, 0x00, 0x16, 0x05];
+    const PLUS_EV2_4K: [u8; 7] = [0x04, 0x02, 0x01, 0x22, 0x00, 0x18, 0x05];
+
+    fn ultralight(bytes: &[u8; 7]) -> (CardModel, Option<u32>) {
+        let info = ultralight_info(VersionInfo::from_bytes(bytes));
+        assert_eq!(info.version.map(|v| v.to_bytes()), Some(*bytes));
+        (info.model, info.memory_size)
+    }
+
+    fn iso14443_4(bytes: &[u8; 7]) -> (CardModel, Option<u32>) {
+        let info = iso14443_4_version_info(VersionInfo::from_bytes(bytes), SecurityLevel::Sl3);
+        (info.model, info.memory_size)
+    }
+
+    #[test]
+    fn ultralight_versions() {
+        assert_eq!(ultralight(&NTAG213), (CardModel::Ntag213, Some(180)));
+        assert_eq!(ultralight(&NTAG215), (CardModel::Ntag215, Some(540)));
+        assert_eq!(ultralight(&NTAG216), (CardModel::Ntag216, Some(924)));
+        assert_eq!(
+            ultralight(&MF0UL11),
+            (CardModel::MifareUltralightEv1, Some(80))
+        );
+        assert_eq!(
+            ultralight(&MF0UL21),
+            (CardModel::MifareUltralightEv1, Some(164))
+        );
+
+        // NTAG210
+        let ntag210 = [0x04, 0x04, 0x01, 0x01, 0x00, 0x0B, 0x03];
+        assert_eq!(ultralight(&ntag210), (CardModel::Ntag, None));
+        assert_eq!(ultralight(&[0; 7]), (CardModel::Unknown, None));
+    }
+
+    #[test]
+    fn desfire_versions() {
+        use DesfireGeneration::*;
+
+        let cases = [
+            (DESFIRE_D40, Original, 4096),
+            (DESFIRE_EV1_8K, Ev1, 8192),
+            (DESFIRE_EV2_4K, Ev2, 4096),
+            (DESFIRE_EV3_4K, Ev3, 4096),
+            (DESFIRE_LIGHT, Light, 512),
+        ];
+
+        for (bytes, generation, size) in cases {
+            assert_eq!(
+                iso14443_4(&bytes),
+                (CardModel::MifareDesfire(generation), Some(size))
+            );
+        }
+    }
+
+    #[test]
+    fn plus_versions() {
+        let plus = |version| CardModel::MifarePlus {
+            version,
+            security_level: SecurityLevel::Sl3,
+        };
+
+        assert_eq!(
+            iso14443_4(&PLUS_EV1_2K),
+            (plus(PlusVersion::Ev1), Some(2048))
+        );
+        assert_eq!(
+            iso14443_4(&PLUS_EV2_4K),
+            (plus(PlusVersion::Ev2), Some(4096))
+        );
+
+        // S/X by historical bytes
+        let historical = [0xC1, 0x05, 0x2F, 0x2F, 0x01, 0xBC, 0xD6];
+        assert_eq!(
+            ats_model(0x20, &historical, SecurityLevel::Sl0),
+            CardModel::MifarePlus {
+                version: PlusVersion::X,
+                security_level: SecurityLevel::Sl0,
+            }
+        );
+        assert_eq!(
+            ats_model(0x20, &[0xC1, 0x05, 0x2F, 0x2F, 0x00], SecurityLevel::Sl3),
+            CardModel::MifarePlus {
+                version: PlusVersion::S,
+                security_level: SecurityLevel::Sl3,
+            }
+        );
+    }
+
+    #[test]
+    fn ats_models() {
+        let sl = SecurityLevel::Sl3;
+        assert_eq!(ats_model(0x28, &[], sl), CardModel::SmartMx);
+        assert_eq!(ats_model(0x20, b"\x80JCOP31", sl), CardModel::SmartMx);
+        assert_eq!(ats_model(0x20, &[0x80], sl), CardModel::Iso14443_4);
+    }
+
+    #[test]
+    fn classic_models() {
+        let atqa = Atqa::from_bytes([0x04, 0x00]);
+        assert_eq!(
+            classic_info(0x08, &atqa, true, false).model,
+            CardModel::MifareClassic1K { ev1: true }
+        );
+        assert_eq!(
+            classic_info(0x18, &Atqa::from_bytes([0x02, 0x00]), false, false),
+            CardInfo::new(CardModel::MifareClassic4K { ev1: false }, Some(4096))
+        );
+
+        // Plus in SL1, size by ATQA
+        let sl1 = CardModel::MifarePlus {
+            version: PlusVersion::Unknown,
+            security_level: SecurityLevel::Sl1,
+        };
+        assert_eq!(
+            classic_info(0x08, &Atqa::from_bytes([0x44, 0x00]), false, true),
+            CardInfo::new(sl1, Some(2048))
+        );
+        assert_eq!(
+            classic_info(0x18, &Atqa::from_bytes([0x42, 0x00]), false, true),
+            CardInfo::new(sl1, Some(4096))
+        );
+    }
+}
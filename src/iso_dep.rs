@@ -0,0 +1,472 @@
+use embedded_hal::digital::OutputPin;
+use heapless::Vec;
+
+use crate::{consts::PCDErrorCode, MFRC522};
+
+/// Max frame size reader can receive (FSDI = 5, size of MFRC522 FIFO)
+const FSD: usize = 64;
+const MAX_RETRIES: u8 = 2;
+
+const PCB_I_BLOCK: u8 = 0x02;
+const PCB_R_ACK: u8 = 0xA2;
+const PCB_R_NAK: u8 = 0xB2;
+const PCB_S_DESELECT: u8 = 0xC2;
+const PCB_S_WTX: u8 = 0xF2;
+
+const PCB_CHAINING: u8 = 0x10;
+const PCB_CID: u8 = 0x08;
+const PCB_NAD: u8 = 0x04;
+
+/// Frame size for FSCI (ISO 14443-4 Table 1)
+const FSC_TABLE: [u16; 13] = [16, 24, 32, 40, 48, 64, 96, 128, 256, 512, 1024, 2048, 4096];
+
+/// Answer To Select
+#[derive(Debug, Clone)]
+pub struct Ats {
+    raw: Vec<u8, FSD>,
+    historical_offset: usize,
+
+    /// Frame size for proximity card integer
+    pub fsci: u8,
+
+    /// Supported bit rates (TA(1)), `None` if not sent
+    pub ta: Option<u8>,
+
+    /// Frame waiting time integer
+    pub fwi: u8,
+
+    /// Start-up frame guard time integer
+    pub sfgi: u8,
+
+    pub cid_supported: bool,
+    pub nad_supported: bool,
+}
+
+impl Ats {
+    /// Parses ATS (without CRC), missing interface bytes get their default values
+    pub fn parse(bytes: &[u8]) -> Result<Self, PCDErrorCode> {
+        if bytes.is_empty() || bytes[0] as usize != bytes.len() {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let mut ats = Self {
+            raw: Vec::from_slice(bytes).map_err(|_| PCDErrorCode::NoRoom)?,
+            historical_offset: bytes.len(),
+            fsci: 2,
+            ta: None,
+            fwi: 4,
+            sfgi: 0,
+            cid_supported: true,
+            nad_supported: false,
+        };
+
+        if bytes.len() == 1 {
+            return Ok(ats);
+        }
+
+        let t0 = bytes[1];
+        ats.fsci = t0 & 0x0F;
+
+        let mut index = 2;
+        let mut next = || {
+            let byte = bytes.get(index).copied().ok_or(PCDErrorCode::Invalid);
+            index += 1;
+            byte
+        };
+
+        if t0 & 0x10 != 0 {
+            ats.ta = Some(next()?);
+        }
+
+        if t0 & 0x20 != 0 {
+            let tb = next()?;
+            ats.fwi = tb >> 4;
+            ats.sfgi = tb & 0x0F;
+        }
+
+        if t0 & 0x40 != 0 {
+            let tc = next()?;
+            ats.nad_supported = tc & 0x01 != 0;
+            ats.cid_supported = tc & 0x02 != 0;
+        }
+
+        ats.historical_offset = index;
+        Ok(ats)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn historical_bytes(&self) -> &[u8] {
+        &self.raw[self.historical_offset..]
+    }
+
+    /// Max frame size PICC can receive
+    pub fn fsc(&self) -> usize {
+        FSC_TABLE.get(self.fsci as usize).copied().unwrap_or(256) as usize
+    }
+
+    /// Frame waiting time (256 * 16 / fc * 2^FWI), FWI = 15 is RFU (treated as 4)
+    pub fn fwt_us(&self) -> u32 {
+        let fwi = if self.fwi == 15 { 4 } else { self.fwi };
+        (302 << fwi) + 1
+    }
+
+    /// Start-up frame guard time, which must pass before first block is sent
+    pub fn sfgt_us(&self) -> u32 {
+        if self.sfgi == 0 || self.sfgi == 15 {
+            return 0;
+        }
+
+        (302 << self.sfgi) + 1
+    }
+}
+
+/// ISO 14443-4 (ISO-DEP / T=CL) session with single activated card,
+/// created by [`MFRC522::iso_dep_activate`]
+#[derive(Debug, Clone)]
+pub struct IsoDep {
+    pub ats: Ats,
+
+    cid: Option<u8>,
+    nad: Option<u8>,
+    block_number: u8,
+}
+
+impl IsoDep {
+    pub fn cid(&self) -> Option<u8> {
+        self.cid
+    }
+
+    /// Node address sent with first block of every command, ignored if
+    /// card doesn't support NAD
+    pub fn set_nad(&mut self, nad: Option<u8>) {
+        self.nad = nad.filter(|_| self.ats.nad_supported);
+    }
+
+    /// Max size of whole frame (header + INF + CRC) that can be sent
+    fn frame_size(&self) -> usize {
+        self.ats.fsc().min(FSD)
+    }
+
+    fn header(&self, pcb: u8, with_nad: bool, out: &mut [u8]) -> usize {
+        let mut len = 1;
+        out[0] = pcb;
+
+        if let Some(cid) = self.cid {
+            out[0] |= PCB_CID;
+            out[len] = cid;
+            len += 1;
+        }
+
+        if let (true, Some(nad)) = (with_nad, self.nad) {
+            out[0] |= PCB_NAD;
+            out[len] = nad;
+            len += 1;
+        }
+
+        len
+    }
+
+    /// Prologue length of received block (PCB + CID + NAD)
+    fn received_header_len(&self, pcb: u8) -> usize {
+        1 + (pcb & PCB_CID != 0) as usize + (pcb & PCB_NAD != 0) as usize
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Sends RATS to selected card and starts ISO 14443-4 session.
+    /// `cid` is used only if card supports it.
+    pub async fn iso_dep_activate(&mut self, cid: Option<u8>) -> Result<IsoDep, PCDErrorCode> {
+        let mut ats = [0; FSD];
+        let len = self.picc_rats(cid.unwrap_or(0), &mut ats).await?;
+        let ats = Ats::parse(&ats[..len as usize])?;
+
+        let sfgt = ats.sfgt_us();
+        if sfgt > 0 {
+            self.sleep((sfgt as u64).div_ceil(1000)).await;
+        }
+
+        Ok(IsoDep {
+            cid: cid.filter(|_| ats.cid_supported),
+            nad: None,
+            block_number: 0,
+            ats,
+        })
+    }
+
+    /// Sends `send_data` as I-block(s) (chaining if it doesn't fit in a frame)
+    /// and receives whole (possibly chained) response into `back_data`.
+    ///
+    /// Returns length of received data.
+    pub async fn iso_dep_transceive(
+        &mut self,
+        iso: &mut IsoDep,
+        send_data: &[u8],
+        back_data: &mut [u8],
+    ) -> Result<usize, PCDErrorCode> {
+        let mut frame = [0; FSD];
+        let mut resp = [0; FSD];
+
+        // send chain
+        let mut offset = 0;
+        let mut resp_len;
+        loop {
+            let header_len = iso.header(PCB_I_BLOCK | iso.block_number, offset == 0, &mut frame);
+
+            let max_inf = iso.frame_size() - header_len - 2;
+            let chunk = (send_data.len() - offset).min(max_inf);
+            let chaining = offset + chunk < send_data.len();
+            if chaining {
+                frame[0] |= PCB_CHAINING;
+            }
+
+            frame[header_len..header_len + chunk]
+                .copy_from_slice(&send_data[offset..offset + chunk]);
+
+            resp_len = self
+                .iso_dep_send_i_block(iso, &frame[..header_len + chunk], &mut resp)
+                .await?;
+
+            let pcb = resp[0];
+            if chaining {
+                if pcb & 0xF6 != PCB_R_ACK {
+                    return Err(PCDErrorCode::Protocol);
+                }
+
+                iso.block_number ^= 1;
+                offset += chunk;
+            } else {
+                if pcb & 0xE2 != PCB_I_BLOCK {
+                    return Err(PCDErrorCode::Protocol);
+                }
+
+                break;
+            }
+        }
+
+        // receive chain
+        let mut received = 0;
+        loop {
+            let pcb = resp[0];
+            if pcb & 0x01 != iso.block_number {
+                return Err(PCDErrorCode::Protocol);
+            }
+            iso.block_number ^= 1;
+
+            let inf = &resp[iso.received_header_len(pcb)..resp_len];
+            if received + inf.len() > back_data.len() {
+                return Err(PCDErrorCode::NoRoom);
+            }
+
+            back_data[received..received + inf.len()].copy_from_slice(inf);
+            received += inf.len();
+
+            if pcb & PCB_CHAINING == 0 {
+                return Ok(received);
+            }
+
+            let header_len = iso.header(PCB_R_ACK | iso.block_number, false, &mut frame);
+            resp_len = self
+                .iso_dep_send_block(iso, &frame[..header_len], &mut resp, false)
+                .await?;
+
+            if resp[0] & 0xE2 != PCB_I_BLOCK {
+                return Err(PCDErrorCode::Protocol);
+            }
+        }
+    }
+
+    /// Sends S(DESELECT), card goes into HALT state
+    pub async fn iso_dep_deselect(&mut self, iso: &mut IsoDep) -> Result<(), PCDErrorCode> {
+        let mut frame = [0; 2];
+        let header_len = iso.header(PCB_S_DESELECT, false, &mut frame);
+
+        let mut resp = [0; FSD];
+        self.iso_dep_send_block(iso, &frame[..header_len], &mut resp, false)
+            .await?;
+
+        if resp[0] & 0xF7 != PCB_S_DESELECT {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        Ok(())
+    }
+
+    /// Sends I-block, retransmits it if card acknowledges previous block
+    /// instead (our block got lost).
+    async fn iso_dep_send_i_block(
+        &mut self,
+        iso: &mut IsoDep,
+        frame: &[u8],
+        resp: &mut [u8; FSD],
+    ) -> Result<usize, PCDErrorCode> {
+        for _ in 0..=MAX_RETRIES {
+            let len = self.iso_dep_send_block(iso, frame, resp, true).await?;
+
+            let pcb = resp[0];
+            if pcb & 0xF6 == PCB_R_ACK && pcb & 0x01 != iso.block_number {
+                continue;
+            }
+
+            return Ok(len);
+        }
+
+        Err(PCDErrorCode::Protocol)
+    }
+
+    /// Sends single block and handles transmission errors and S(WTX) requests.
+    /// On error R(NAK) is sent (`nak_on_error`), otherwise block is retransmitted.
+    async fn iso_dep_send_block(
+        &mut self,
+        iso: &IsoDep,
+        frame: &[u8],
+        resp: &mut [u8; FSD],
+        nak_on_error: bool,
+    ) -> Result<usize, PCDErrorCode> {
+        let mut buff = [0; FSD];
+        let mut len = frame.len();
+        buff[..len].copy_from_slice(frame);
+
+        let mut fwt = iso.ats.fwt_us();
+        let mut retries = 0;
+        loop {
+            match self.iso_dep_exchange(&mut buff, len, resp, fwt).await {
+                Ok(resp_len) => {
+                    let pcb = resp[0];
+                    if pcb & 0xF7 != PCB_S_WTX {
+                        return Ok(resp_len);
+                    }
+
+                    // S(WTX) - answer with the same WTXM and wait longer
+                    let header_len = iso.received_header_len(pcb);
+                    if resp_len <= header_len {
+                        return Err(PCDErrorCode::Protocol);
+                    }
+
+                    let wtxm = resp[header_len] & 0x3F;
+                    if wtxm == 0 || wtxm > 59 {
+                        return Err(PCDErrorCode::Protocol);
+                    }
+
+                    len = iso.header(PCB_S_WTX, false, &mut buff);
+                    buff[len] = wtxm;
+                    len += 1;
+
+                    fwt = (iso.ats.fwt_us() * wtxm as u32).min(4_949_000);
+                }
+                Err(PCDErrorCode::Timeout | PCDErrorCode::CrcWrong | PCDErrorCode::Error)
+                    if retries < MAX_RETRIES =>
+                {
+                    retries += 1;
+                    fwt = iso.ats.fwt_us();
+
+                    if nak_on_error {
+                        len = iso.header(PCB_R_NAK | iso.block_number, false, &mut buff);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Adds CRC, sends frame with given frame waiting time and checks
+    /// CRC of response. Returns response length without CRC.
+    async fn iso_dep_exchange(
+        &mut self,
+        buff: &mut [u8; FSD],
+        len: usize,
+        resp: &mut [u8; FSD],
+        fwt_us: u32,
+    ) -> Result<usize, PCDErrorCode> {
+        if len + 2 > FSD {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        self.pcd_calc_crc_single_buf(buff, len as u8, len).await?;
+        self.pcd_set_timeout(fwt_us).await?;
+
+        let mut resp_len = FSD as u8;
+        let res = self
+            .pcd_transceive_data(
+                &buff[..len + 2],
+                len as u8 + 2,
+                resp,
+                &mut resp_len,
+                &mut 0,
+                0,
+                true,
+            )
+            .await;
+
+        self.pcd_reset_timeout().await?;
+        res?;
+
+        if resp_len < 3 {
+            return Err(PCDErrorCode::Error);
+        }
+
+        Ok(resp_len as usize - 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ats_desfire() {
+        let ats = Ats::parse(&[0x06, 0x75, 0x77, 0x81, 0x02, 0x80]).unwrap();
+        assert_eq!(ats.fsci, 5);
+        assert_eq!(ats.fsc(), 64);
+        assert_eq!(ats.ta, Some(0x77));
+        assert_eq!((ats.fwi, ats.sfgi), (8, 1));
+        assert_eq!(ats.fwt_us(), (302 << 8) + 1);
+        assert_eq!(ats.sfgt_us(), (302 << 1) + 1);
+        assert!(ats.cid_supported);
+        assert!(!ats.nad_supported);
+        assert_eq!(ats.historical_bytes(), &[0x80]);
+    }
+
+    #[test]
+    fn ats_defaults() {
+        let ats = Ats::parse(&[0x01]).unwrap();
+        assert_eq!(ats.fsc(), 32);
+        assert_eq!(ats.ta, None);
+        assert_eq!((ats.fwi, ats.sfgi), (4, 0));
+        assert_eq!(ats.sfgt_us(), 0);
+        assert!(ats.cid_supported);
+        assert!(ats.historical_bytes().is_empty());
+
+        // only TC sent, historical bytes follow
+        let ats = Ats::parse(&[0x05, 0x48, 0x01, 0xC1, 0x05]).unwrap();
+        assert_eq!(ats.fsc(), 256);
+        assert_eq!(ats.ta, None);
+        assert_eq!(ats.fwi, 4);
+        assert!(!ats.cid_supported);
+        assert!(ats.nad_supported);
+        assert_eq!(ats.historical_bytes(), &[0xC1, 0x05]);
+    }
+
+    #[test]
+    fn ats_invalid() {
+        assert_eq!(Ats::parse(&[]).err(), Some(PCDErrorCode::Invalid));
+
+        // TL doesn't match length
+        assert_eq!(
+            Ats::parse(&[0x05, 0x75, 0x77]).err(),
+            Some(PCDErrorCode::Invalid)
+        );
+
+        // T0 announces TA, TB and TC, only TA is present
+        assert_eq!(
+            Ats::parse(&[0x03, 0x75, 0x77]).err(),
+            Some(PCDErrorCode::Invalid)
+        );
+    }
+}
@@ -1,14 +1,39 @@
 #![no_std]
 
-use consts::{PCDErrorCode, Uid, UidSize};
+use consts::{Atqa, PCDErrorCode, Uid};
 use embedded_hal::digital::OutputPin;
 use esp_hal::gpio::Flex;
 
+pub mod access;
+pub mod apdu;
 pub mod consts;
+#[cfg(any(feature = "desfire", feature = "mifare-plus"))]
+mod crypto;
 pub mod debug;
+#[cfg(feature = "desfire")]
+pub mod desfire;
+pub mod dictionary;
+pub mod dump;
+pub mod dump_format;
+pub mod emv;
+pub mod geometry;
+pub mod identify;
+pub mod iso_dep;
+pub mod magic;
 pub mod mifare;
+pub mod ndef;
 pub mod pcd;
 pub mod picc;
+#[cfg(feature = "mifare-plus")]
+pub mod plus;
+pub mod raw;
+pub mod restore;
+pub mod tlv;
+pub mod tracker;
+pub mod uid;
+
+/// Software timeout of PICC communication (slightly longer than 25ms timer set in `pcd_init`)
+const DEFAULT_TIMEOUT_US: u64 = 36_000;
 
 pub struct MFRC522<S, C>
 where
@@ -18,6 +43,8 @@ where
     spi: S,
     cs: C,
     read_buff: [u8; 1],
+    last_atqa: Atqa,
+    timeout_us: u64,
 
     get_current_time: fn() -> u64,
 }
@@ -33,6 +60,8 @@ where
             spi,
             cs,
             read_buff: [0],
+            last_atqa: Atqa { bytes: [0; 2] },
+            timeout_us: DEFAULT_TIMEOUT_US,
             get_current_time,
         }
     }
@@ -43,6 +72,8 @@ where
             spi,
             cs,
             read_buff: [0],
+            last_atqa: Atqa { bytes: [0; 2] },
+            timeout_us: DEFAULT_TIMEOUT_US,
 
             get_current_time: || embassy_time::Instant::now().as_micros(),
         }
@@ -59,14 +90,31 @@ where
         embassy_time::Timer::after_millis(time_ms).await;
     }
 
-    pub async fn get_card(&mut self, size: UidSize) -> Result<Uid, PCDErrorCode> {
+    /// Selects the card that answered the last REQA/WUPA and reads its UID.
+    ///
+    /// UID length is taken from the cascade bit in SAK (authoritative), ATQA
+    /// uid size bits are only checked against it.
+    pub async fn get_card(&mut self) -> Result<Uid, PCDErrorCode> {
         let mut uid = Uid {
-            size: size.to_byte(),
+            size: 0,
             sak: 0,
             uid_bytes: [0; 10],
+            atqa: self.last_atqa,
         };
 
         self.picc_select(&mut uid, 0).await?;
+
+        match uid.atqa.uid_size() {
+            Some(expected) if expected.to_byte() != uid.size => {
+                log::warn!(
+                    "ATQA uid size ({}) doesn't match selected uid size ({})",
+                    expected.to_byte(),
+                    uid.size
+                );
+            }
+            _ => {}
+        }
+
         Ok(uid)
     }
 
@@ -162,4 +210,4 @@ pub fn tif<T>(expr: bool, true_val: T, false_val: T) -> T {
     } else {
         false_val
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,404 @@
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    consts::{Atqa, PCDErrorCode, PICCCommand, PICCType, Uid},
+    geometry::ClassicSize,
+    mifare::{KeyType, MifareKey},
+    raw::{RawFrameConfig, RawResponse},
+    MFRC522,
+};
+
+/// Transport configuration trailer (key A/B FF..FF, access bits FF 07 80 69)
+pub const DEFAULT_SECTOR_TRAILER: [u8; 16] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, 0x80, 0x69, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+const GEN1A_UNLOCK_1: u8 = 0x40; // sent as 7 bit frame
+const GEN1A_UNLOCK_2: u8 = 0x43;
+
+/// Block Check Character of 4 byte uid (XOR of all bytes)
+pub fn calculate_bcc(uid_bytes: &[u8]) -> u8 {
+    uid_bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// Builds MIFARE Classic manufacturer block (block 0) from `uid` (and its
+/// `sak`/`atqa`), rest of the block is filled with `manufacturer` data.
+/// Uid without SAK and ATQA (e.g. parsed by `Uid::from_hex`) is refused,
+/// set them to values of emulated card first.
+///
+/// 4 byte uid: `UID0..3 BCC SAK ATQA0 ATQA1 manufacturer[8]`
+/// 7 byte uid: `UID0..6 SAK ATQA0 ATQA1 manufacturer[6]`
+pub fn build_manufacturer_block(uid: &Uid, manufacturer: &[u8]) -> Result<[u8; 16], PCDErrorCode> {
+    if uid.sak == 0 && uid.atqa.as_u16() == 0 {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    let mut block = [0; 16];
+    let offset = match uid.size {
+        4 => {
+            block[..4].copy_from_slice(&uid.uid_bytes[..4]);
+            block[4] = calculate_bcc(&uid.uid_bytes[..4]);
+            5
+        }
+        7 => {
+            block[..7].copy_from_slice(&uid.uid_bytes[..7]);
+            7
+        }
+        _ => return Err(PCDErrorCode::Invalid),
+    };
+
+    block[offset] = uid.sak;
+    block[offset + 1..offset + 3].copy_from_slice(&uid.atqa.bytes);
+
+    let len = manufacturer.len().min(16 - (offset + 3));
+    block[offset + 3..offset + 3 + len].copy_from_slice(&manufacturer[..len]);
+
+    Ok(block)
+}
+
+/// Refuses uids that would make card unusable/unselectable (all 0x00/0xFF,
+/// cascade tag as first byte, unsupported length)
+pub fn validate_block0_uid(uid: &Uid) -> Result<(), PCDErrorCode> {
+    if uid.size != 4 && uid.size != 7 {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    let bytes = &uid.uid_bytes[..uid.size as usize];
+    if bytes.iter().all(|&b| b == 0x00) || bytes.iter().all(|&b| b == 0xFF) {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    if bytes[0] == PICCCommand::PICC_CMD_CT {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    Ok(())
+}
+
+/// Checks raw manufacturer block (e.g. from dump) before it's written to magic
+/// card: BCC of 4 byte uid, uid itself (see [`validate_block0_uid`]) and SAK/ATQA.
+/// Returns uid (with SAK/ATQA) stored in block and remaining manufacturer data.
+pub fn validate_manufacturer_block(
+    block: &[u8; 16],
+    uid_size: u8,
+) -> Result<(Uid, &[u8]), PCDErrorCode> {
+    let offset = match uid_size {
+        4 if calculate_bcc(&block[..4]) != block[4] => return Err(PCDErrorCode::Invalid),
+        4 => 5,
+        7 => 7,
+        _ => return Err(PCDErrorCode::Invalid),
+    };
+
+    let mut uid = Uid::from_bytes(&block[..uid_size as usize])?;
+    uid.sak = block[offset];
+    uid.atqa = Atqa::from_bytes([block[offset + 1], block[offset + 2]]);
+
+    validate_block0_uid(&uid)?;
+    if uid.sak == 0 && uid.atqa.as_u16() == 0 {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    Ok((uid, &block[offset + 3..]))
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Sends Gen1a backdoor sequence (HLTA, 7 bit 0x40, 0x43).
+    /// After that card accepts reads/writes of every block without auth.
+    pub async fn magic_gen1a_unlock(&mut self) -> Result<(), PCDErrorCode> {
+        self.pcd_stop_crypto1().await?;
+        _ = self.picc_halta().await;
+
+        let mut buff = [0; 1];
+        let config = RawFrameConfig {
+            tx_last_bits: 7,
+            ..Default::default()
+        };
+
+        let res = self
+            .pcd_transceive_raw(&config, &[GEN1A_UNLOCK_1], &mut buff)
+            .await?;
+        if !is_ack(&res, &buff) {
+            return Err(PCDErrorCode::MifareNack);
+        }
+
+        let res = self
+            .pcd_transceive_raw(&RawFrameConfig::default(), &[GEN1A_UNLOCK_2], &mut buff)
+            .await?;
+        if !is_ack(&res, &buff) {
+            return Err(PCDErrorCode::MifareNack);
+        }
+
+        Ok(())
+    }
+
+    /// Checks if card in the field answers Gen1a backdoor commands.
+    /// Card is halted afterwards, so it has to be woken up (WUPA) again.
+    pub async fn magic_gen1a_detect(&mut self) -> Result<bool, PCDErrorCode> {
+        let res = match self.magic_gen1a_unlock().await {
+            Ok(_) => Ok(true),
+            Err(PCDErrorCode::Timeout | PCDErrorCode::MifareNack | PCDErrorCode::Error) => {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        };
+
+        _ = self.picc_halta().await;
+        res
+    }
+
+    /// Unlocks Gen1a card and writes block 0 built from `uid` (uid, BCC, SAK, ATQA)
+    pub async fn magic_gen1a_write_block0(
+        &mut self,
+        uid: &Uid,
+        manufacturer: &[u8],
+    ) -> Result<(), PCDErrorCode> {
+        validate_block0_uid(uid)?;
+        if uid.size != 4 {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let block = build_manufacturer_block(uid, manufacturer)?;
+        self.magic_gen1a_unlock().await?;
+        self.mifare_write(0, &block, 16).await
+    }
+
+    /// Unlocks Gen1a card and writes it to transport state: block 0 from `uid`,
+    /// zeroed data blocks and default trailers (see [`DEFAULT_SECTOR_TRAILER`]).
+    pub async fn magic_gen1a_wipe(
+        &mut self,
+        uid: &Uid,
+        picc_type: PICCType,
+    ) -> Result<(), PCDErrorCode> {
+        let size = ClassicSize::from_picc_type(&picc_type).ok_or(PCDErrorCode::Invalid)?;
+
+        validate_block0_uid(uid)?;
+        if uid.size != 4 {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let block0 = build_manufacturer_block(uid, &[])?;
+        self.magic_gen1a_unlock().await?;
+
+        for block_addr in size.blocks() {
+            let block = if block_addr.is_manufacturer() {
+                block0
+            } else if block_addr.is_trailer() {
+                DEFAULT_SECTOR_TRAILER
+            } else {
+                [0; 16]
+            };
+
+            self.mifare_write(block_addr, &block, 16).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes block 0 of direct-write magic card (Gen2/CUID, also FUID which
+    /// locks itself after first write). Block is written after normal
+    /// authentication with `key` using current `card` uid.
+    ///
+    /// Returns `Ok(true)` if card was re-selected with `new_uid` afterwards,
+    /// `Ok(false)` if card refused the write or still reports old uid.
+    /// Card is left halted.
+    pub async fn magic_gen2_write_block0(
+        &mut self,
+        card: &Uid,
+        key_type: KeyType,
+        key: &MifareKey,
+        new_uid: &Uid,
+        manufacturer: &[u8],
+    ) -> Result<bool, PCDErrorCode> {
+        validate_block0_uid(new_uid)?;
+        if new_uid.size != card.size {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let block = build_manufacturer_block(new_uid, manufacturer)?;
+
+        self.pcd_authenticate(key_type, 0, key, card).await?;
+        let res = self.mifare_write(0, &block, 16).await;
+
+        _ = self.picc_halta().await;
+        self.pcd_stop_crypto1().await?;
+
+        match res {
+            Ok(_) => {}
+            Err(PCDErrorCode::MifareNack) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        self.picc_wakeup_a().await?;
+        let selected = self.get_card().await?;
+        _ = self.picc_halta().await;
+
+        Ok(selected == *new_uid)
+    }
+}
+
+/// 4 bit MIFARE ACK
+fn is_ack(res: &RawResponse, buff: &[u8]) -> bool {
+    res.len == 1 && res.rx_last_bits == 4 && buff[0] & 0x0F == 0x0A
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{AccessConditions, SectorTrailer};
+
+    fn card_uid(bytes: &[u8], sak: u8, atqa: [u8; 2]) -> Uid {
+        let mut uid = Uid::from_bytes(bytes).unwrap();
+        uid.sak = sak;
+        uid.atqa = Atqa::from_bytes(atqa);
+        uid
+    }
+
+    #[test]
+    fn bcc() {
+        assert_eq!(calculate_bcc(&[0xDE, 0xAD, 0xBE, 0xEF]), 0x22);
+        assert_eq!(calculate_bcc(&[0x01, 0x02, 0x04, 0x08]), 0x0F);
+        assert_eq!(calculate_bcc(&[]), 0x00);
+    }
+
+    #[test]
+    fn manufacturer_block_4_byte() {
+        let uid = card_uid(&[0xDE, 0xAD, 0xBE, 0xEF], 0x08, [0x04, 0x00]);
+        let block = build_manufacturer_block(&uid, &[0x62, 0x63, 0x64]).unwrap();
+        assert_eq!(
+            block,
+            [
+                0xDE, 0xAD, 0xBE, 0xEF, 0x22, 0x08, 0x04, 0x00, 0x62, 0x63, 0x64, 0x00, 0x00, 0x00,
+                0x00, 0x00
+            ]
+        );
+
+        let (parsed, manufacturer) = validate_manufacturer_block(&block, 4).unwrap();
+        assert_eq!(parsed, uid);
+        assert_eq!((parsed.sak, parsed.atqa), (uid.sak, uid.atqa));
+        assert_eq!(manufacturer.len(), 8);
+        assert_eq!(&manufacturer[..3], &[0x62, 0x63, 0x64]);
+    }
+
+    #[test]
+    fn manufacturer_block_7_byte() {
+        let uid = card_uid(
+            &[0x04, 0xA2, 0x3B, 0x1A, 0x5C, 0x80, 0x00],
+            0x08,
+            [0x44, 0x00],
+        );
+
+        // manufacturer data is cut to fit
+        let block = build_manufacturer_block(&uid, &[0xAA; 10]).unwrap();
+        assert_eq!(&block[..7], uid.as_bytes());
+        assert_eq!(&block[7..10], &[0x08, 0x44, 0x00]);
+        assert_eq!(&block[10..], &[0xAA; 6]);
+
+        let (parsed, manufacturer) = validate_manufacturer_block(&block, 7).unwrap();
+        assert_eq!(parsed, uid);
+        assert_eq!(manufacturer, &[0xAA; 6]);
+
+        // block doesn't fit uid size of card
+        assert!(validate_manufacturer_block(&block, 4).is_err());
+        assert!(validate_manufacturer_block(&block, 10).is_err());
+    }
+
+    #[test]
+    fn manufacturer_block_wrong_bcc() {
+        let uid = card_uid(&[0xDE, 0xAD, 0xBE, 0xEF], 0x08, [0x04, 0x00]);
+        let mut block = build_manufacturer_block(&uid, &[]).unwrap();
+        block[4] ^= 0x01;
+
+        assert_eq!(
+            validate_manufacturer_block(&block, 4).err(),
+            Some(PCDErrorCode::Invalid)
+        );
+    }
+
+    #[test]
+    fn manufacturer_block_without_sak_atqa() {
+        let uid = card_uid(&[0xDE, 0xAD, 0xBE, 0xEF], 0x00, [0x00, 0x00]);
+        assert_eq!(
+            build_manufacturer_block(&uid, &[]),
+            Err(PCDErrorCode::Invalid)
+        );
+
+        // block with zeroed SAK/ATQA, but correct BCC
+        let mut block = [0; 16];
+        block[..4].copy_from_slice(uid.as_bytes());
+        block[4] = calculate_bcc(uid.as_bytes());
+        assert_eq!(
+            validate_manufacturer_block(&block, 4).err(),
+            Some(PCDErrorCode::Invalid)
+        );
+
+        // Ultralight SAK with ATQA is fine
+        let uid = Uid {
+            sak: 0x00,
+            atqa: Atqa::from_bytes([0x44, 0x00]),
+            ..uid
+        };
+        assert!(build_manufacturer_block(&uid, &[]).is_ok());
+    }
+
+    #[test]
+    fn unusable_uids() {
+        let refused: [&[u8]; 4] = [
+            &[0x00; 4],
+            &[0xFF; 7],
+            &[0x88, 0x01, 0x02, 0x03],
+            &[0x88, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+        ];
+
+        for bytes in refused {
+            let uid = card_uid(bytes, 0x08, [0x04, 0x00]);
+            assert_eq!(validate_block0_uid(&uid), Err(PCDErrorCode::Invalid));
+
+            let block = build_manufacturer_block(&uid, &[]).unwrap();
+            assert!(validate_manufacturer_block(&block, uid.size).is_err());
+        }
+
+        let uid = card_uid(&[0x00; 10], 0x08, [0x04, 0x00]);
+        assert_eq!(validate_block0_uid(&uid), Err(PCDErrorCode::Invalid));
+        assert_eq!(
+            build_manufacturer_block(&uid, &[]),
+            Err(PCDErrorCode::Invalid)
+        );
+
+        // cascade tag is allowed in other positions
+        let uid = card_uid(&[0x01, 0x88, 0x00, 0xFF], 0x08, [0x04, 0x00]);
+        assert_eq!(validate_block0_uid(&uid), Ok(()));
+    }
+
+    #[test]
+    fn default_trailer() {
+        let trailer = SectorTrailer::new(
+            MifareKey::DEFAULT,
+            AccessConditions::TRANSPORT,
+            MifareKey::DEFAULT,
+        );
+        assert_eq!(DEFAULT_SECTOR_TRAILER, trailer.to_bytes());
+    }
+
+    #[test]
+    fn gen1a_ack() {
+        let ack = RawResponse {
+            len: 1,
+            rx_last_bits: 4,
+        };
+        assert!(is_ack(&ack, &[0x0A]));
+        assert!(is_ack(&ack, &[0xFA]));
+        assert!(!is_ack(&ack, &[0x04]));
+
+        // full byte or longer answer isn't ACK
+        let full = RawResponse {
+            len: 1,
+            rx_last_bits: 0,
+        };
+        assert!(!is_ack(&full, &[0x0A]));
+        assert!(!is_ack(&RawResponse { len: 2, ..ack }, &[0x0A, 0x00]));
+    }
+}
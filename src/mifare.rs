@@ -1,10 +1,171 @@
 use embedded_hal::digital::OutputPin;
 
 use crate::{
-    consts::{PCDErrorCode, PICCCommand},
+    access::{Access, AccessConditions, DataOperation, SectorTrailer},
+    consts::{PCDErrorCode, PICCCommand, Uid},
+    geometry::{BlockAddr, Sector},
     MFRC522,
 };
 
+/// MIFARE Classic sector key (Crypto1, 6 bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MifareKey(pub [u8; 6]);
+
+impl MifareKey {
+    /// Factory default key
+    pub const DEFAULT: Self = Self([0xFF; 6]);
+
+    /// Key A of MIFARE Application Directory sectors
+    pub const MAD: Self = Self([0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5]);
+
+    /// Key A of NFC Forum (NDEF) sectors
+    pub const NDEF: Self = Self([0xD3, 0xF7, 0xD3, 0xF7, 0xD3, 0xF7]);
+
+    pub fn as_bytes(&self) -> &[u8; 6] {
+        &self.0
+    }
+}
+
+impl From<[u8; 6]> for MifareKey {
+    fn from(bytes: [u8; 6]) -> Self {
+        Self(bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyType {
+    A,
+    B,
+}
+
+impl KeyType {
+    /// PICC authentication command for this key
+    pub fn command(&self) -> u8 {
+        match self {
+            KeyType::A => PICCCommand::PICC_CMD_MF_AUTH_KEY_A,
+            KeyType::B => PICCCommand::PICC_CMD_MF_AUTH_KEY_B,
+        }
+    }
+}
+
+/// MIFARE Classic value block: signed value stored 3 times (as is, inverted,
+/// as is) and address byte 4 times (addr, !addr, addr, !addr)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValueBlock {
+    pub value: i32,
+
+    /// Free for user, usually address of block (used for backup management)
+    pub addr: u8,
+}
+
+impl ValueBlock {
+    pub fn new(value: i32, addr: u8) -> Self {
+        Self { value, addr }
+    }
+
+    /// Parses 16 byte block, fails with `IntegrityError` if copies of value
+    /// or address don't match
+    pub fn parse(block: &[u8]) -> Result<Self, PCDErrorCode> {
+        let block: &[u8; 16] = block
+            .get(..16)
+            .and_then(|b| b.try_into().ok())
+            .ok_or(PCDErrorCode::Invalid)?;
+
+        let value = ValueBlock {
+            value: i32::from_le_bytes([block[0], block[1], block[2], block[3]]),
+            addr: block[12],
+        };
+
+        if value.to_bytes() != *block {
+            return Err(PCDErrorCode::IntegrityError);
+        }
+
+        Ok(value)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let value = self.value.to_le_bytes();
+
+        let mut block = [0; 16];
+        block[..4].copy_from_slice(&value);
+        block[4..8].copy_from_slice(&(!self.value).to_le_bytes());
+        block[8..12].copy_from_slice(&value);
+        block[12..].copy_from_slice(&[self.addr, !self.addr, self.addr, !self.addr]);
+        block
+    }
+}
+
+/// Checks done by [`MFRC522::mifare_write_sector_trailer`]
+#[derive(Debug, Clone, Copy)]
+pub struct TrailerWriteOptions<'a> {
+    /// Allow access bits that can never be changed again and keys that
+    /// can't be changed with any usable key
+    pub allow_irreversible: bool,
+
+    /// Key used to authenticate again after the write
+    pub verify_with: KeyType,
+
+    /// Operations on data blocks that have to stay possible with new keys
+    pub required: &'a [(KeyType, DataOperation)],
+}
+
+impl Default for TrailerWriteOptions<'_> {
+    fn default() -> Self {
+        Self {
+            allow_irreversible: false,
+            verify_with: KeyType::A,
+            required: &[],
+        }
+    }
+}
+
+impl TrailerWriteOptions<'_> {
+    /// Checks raw trailer block (including inverted copies of access bits)
+    /// and returns it decoded
+    pub(crate) fn check(
+        &self,
+        sector: Sector,
+        block: &[u8; 16],
+    ) -> Result<SectorTrailer, PCDErrorCode> {
+        let trailer = SectorTrailer::parse(block)?;
+        let access = &trailer.access;
+        let permissions = access.trailer();
+
+        // readable Key B is just data, card refuses to authenticate with it
+        let usable = |key_type| key_type == KeyType::A || !permissions.key_b_readable();
+        let keys_writable = [KeyType::A, KeyType::B].into_iter().any(|key_type| {
+            usable(key_type)
+                && (permissions.key_a_write.allows(key_type)
+                    || permissions.key_b_write.allows(key_type))
+        });
+
+        let irreversible = permissions.access_bits_write == Access::Never || !keys_writable;
+        if irreversible && !self.allow_irreversible {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        if !usable(self.verify_with) {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let mut groups = sector.data_blocks().map(|block| block.access_group());
+        let allowed = groups.all(|group| {
+            self.required.iter().all(|&(key_type, operation)| {
+                usable(key_type) && access.data(group).get(operation).allows(key_type)
+            })
+        });
+
+        if !allowed {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        Ok(trailer)
+    }
+}
+
 impl<S, C> MFRC522<S, C>
 where
     S: embedded_hal::spi::SpiDevice,
@@ -12,7 +173,7 @@ where
 {
     pub async fn mifare_read(
         &mut self,
-        block_addr: u8,
+        block_addr: impl Into<BlockAddr>,
         buff: &mut [u8],
         buff_size: &mut u8,
     ) -> Result<(), PCDErrorCode> {
@@ -21,7 +182,7 @@ where
         }
 
         buff[0] = PICCCommand::PICC_CMD_MF_READ;
-        buff[1] = block_addr;
+        buff[1] = block_addr.into().0;
 
         let mut tmp_buff = [0; 2];
         tmp_buff.copy_from_slice(&buff[..2]);
@@ -36,7 +197,7 @@ where
 
     pub async fn mifare_write(
         &mut self,
-        block_addr: u8,
+        block_addr: impl Into<BlockAddr>,
         buff: &[u8],
         buff_size: u8,
     ) -> Result<(), PCDErrorCode> {
@@ -44,7 +205,7 @@ where
             return Err(PCDErrorCode::Invalid);
         }
 
-        let cmd_buff = [PICCCommand::PICC_CMD_MF_WRITE, block_addr];
+        let cmd_buff = [PICCCommand::PICC_CMD_MF_WRITE, block_addr.into().0];
         self.pcd_mifare_transceive(&cmd_buff, 2, false).await?;
         self.pcd_mifare_transceive(buff, buff_size, false).await?;
 
@@ -70,8 +231,11 @@ where
         Ok(())
     }
 
-    pub async fn mifare_transfer(&mut self, block_addr: u8) -> Result<(), PCDErrorCode> {
-        let cmd_buff = [PICCCommand::PICC_CMD_MF_TRANSFER, block_addr];
+    pub async fn mifare_transfer(
+        &mut self,
+        block_addr: impl Into<BlockAddr>,
+    ) -> Result<(), PCDErrorCode> {
+        let cmd_buff = [PICCCommand::PICC_CMD_MF_TRANSFER, block_addr.into().0];
         self.pcd_mifare_transceive(&cmd_buff, 2, false).await?;
 
         Ok(())
@@ -80,10 +244,10 @@ where
     pub async fn mifare_two_step_helper(
         &mut self,
         cmd: u8,
-        block_addr: u8,
+        block_addr: impl Into<BlockAddr>,
         data: u32,
     ) -> Result<(), PCDErrorCode> {
-        let cmd_buff = [cmd, block_addr];
+        let cmd_buff = [cmd, block_addr.into().0];
         self.pcd_mifare_transceive(&cmd_buff, 2, false).await?;
         self.pcd_mifare_transceive(&data.to_le_bytes(), 4, false)
             .await?;
@@ -91,67 +255,102 @@ where
         Ok(())
     }
 
+    /// Subtracts `delta` (signed like [`ValueBlock::value`]) to value block and
+    /// keeps result in transfer buffer (see `mifare_transfer`)
     pub async fn mifare_decrement(
         &mut self,
-        block_addr: u8,
-        delta: u32,
+        block_addr: impl Into<BlockAddr>,
+        delta: i32,
     ) -> Result<(), PCDErrorCode> {
-        self.mifare_two_step_helper(PICCCommand::PICC_CMD_MF_DECREMENT, block_addr, delta)
+        self.mifare_two_step_helper(PICCCommand::PICC_CMD_MF_DECREMENT, block_addr, delta as u32)
             .await
     }
 
+    /// Adds `delta` (signed like [`ValueBlock::value`]) to value block and
+    /// keeps result in transfer buffer (see `mifare_transfer`)
     pub async fn mifare_increment(
         &mut self,
-        block_addr: u8,
-        delta: u32,
+        block_addr: impl Into<BlockAddr>,
+        delta: i32,
     ) -> Result<(), PCDErrorCode> {
-        self.mifare_two_step_helper(PICCCommand::PICC_CMD_MF_INCREMENT, block_addr, delta)
+        self.mifare_two_step_helper(PICCCommand::PICC_CMD_MF_INCREMENT, block_addr, delta as u32)
             .await
     }
 
-    pub async fn mifare_restore(&mut self, block_addr: u8) -> Result<(), PCDErrorCode> {
+    pub async fn mifare_restore(
+        &mut self,
+        block_addr: impl Into<BlockAddr>,
+    ) -> Result<(), PCDErrorCode> {
         self.mifare_two_step_helper(PICCCommand::PICC_CMD_MF_RESTORE, block_addr, 0)
             .await
     }
 
-    pub async fn mifare_get_value(&mut self, block_addr: u8) -> Result<u32, PCDErrorCode> {
+    /// Reads value block, fails with `IntegrityError` if its copies don't match
+    pub async fn mifare_get_value(
+        &mut self,
+        block_addr: impl Into<BlockAddr>,
+    ) -> Result<i32, PCDErrorCode> {
         let mut buff = [0; 18];
         let mut size = 18;
 
         self.mifare_read(block_addr, &mut buff, &mut size).await?;
-        Ok(((buff[3] as u32) << 24)
-            | ((buff[2] as u32) << 16)
-            | ((buff[1] as u32) << 8)
-            | (buff[0] as u32))
+        Ok(ValueBlock::parse(&buff[..16])?.value)
     }
 
+    /// Formats block as value block (address byte is set to `block_addr`)
     pub async fn mifare_set_value(
         &mut self,
-        block_addr: u8,
-        value: u32,
+        block_addr: impl Into<BlockAddr>,
+        value: i32,
     ) -> Result<(), PCDErrorCode> {
-        let mut buff = [0; 18];
+        let block_addr = block_addr.into();
+        let block = ValueBlock::new(value, block_addr.0);
 
-        buff[0] = (value & 0xFF) as u8;
-        buff[8] = (value & 0xFF) as u8;
-        buff[1] = (value & 0xFF00) as u8 >> 8;
-        buff[9] = (value & 0xFF00) as u8 >> 8;
-        buff[2] = (value & 0xFF0000) as u8 >> 16;
-        buff[10] = (value & 0xFF0000) as u8 >> 16;
-        buff[3] = (value & 0xFF000000) as u8 >> 24;
-        buff[11] = (value & 0xFF000000) as u8 >> 24;
+        self.mifare_write(block_addr, &block.to_bytes(), 16).await
+    }
 
-        buff[4] = !buff[0];
-        buff[5] = !buff[1];
-        buff[6] = !buff[2];
-        buff[7] = !buff[3];
+    /// Writes sector trailer after checking that it won't lock the sector
+    /// (see [`TrailerWriteOptions`]), nothing is written if check fails.
+    /// Sector has to be authenticated with key allowed to write the trailer.
+    ///
+    /// Afterwards card is selected again and authenticated with new key
+    /// (`AuthenticationFailed` means trailer was written, but new key doesn't
+    /// work), readable access bits are also compared. Card is left
+    /// authenticated to the sector.
+    pub async fn mifare_write_sector_trailer(
+        &mut self,
+        uid: &Uid,
+        sector: Sector,
+        trailer: &SectorTrailer,
+        options: &TrailerWriteOptions<'_>,
+    ) -> Result<(), PCDErrorCode> {
+        let bytes = trailer.to_bytes();
+        options.check(sector, &bytes)?;
 
-        buff[12] = block_addr;
-        buff[14] = block_addr;
-        buff[13] = !block_addr;
-        buff[15] = !block_addr;
+        self.mifare_write(sector.trailer(), &bytes, 16).await?;
 
-        self.mifare_write(block_addr, &buff, 16).await
+        _ = self.picc_halta().await;
+        self.pcd_stop_crypto1().await?;
+
+        let mut uid = uid.clone();
+        self.picc_wakeup_uid(&mut uid).await?;
+
+        let key_type = options.verify_with;
+        self.pcd_authenticate(key_type, sector.trailer(), trailer.key(key_type), &uid)
+            .await?;
+
+        if trailer.access.trailer().access_bits_read.allows(key_type) {
+            let mut buff = [0; 18];
+            let mut size = 18;
+            self.mifare_read(sector.trailer(), &mut buff, &mut size)
+                .await?;
+
+            if buff[6..10] != bytes[6..10] {
+                return Err(PCDErrorCode::IntegrityError);
+            }
+        }
+
+        Ok(())
     }
 
     pub async fn mifare_calculate_access_bits(
@@ -162,14 +361,121 @@ where
         g2: u8,
         g3: u8,
     ) -> Result<(), PCDErrorCode> {
-        let c1 = ((g3 & 4) << 1) | ((g2 & 4) << 0) | ((g1 & 4) >> 1) | ((g0 & 4) >> 2);
-        let c2 = ((g3 & 2) << 2) | ((g2 & 2) << 1) | ((g1 & 2) << 0) | ((g0 & 2) >> 1);
-        let c3 = ((g3 & 1) << 3) | ((g2 & 1) << 2) | ((g1 & 1) << 1) | ((g0 & 1) << 0);
-
-        buff[0] = (!c2 & 0xF) << 4 | (!c1 & 0xF);
-        buff[1] = c1 << 4 | (!c3 & 0xF);
-        buff[2] = c3 << 4 | c2;
+        let bytes = AccessConditions::new([g0, g1, g2, g3]).to_bytes();
+        buff[..3].copy_from_slice(&bytes);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailer(groups: [u8; 4]) -> [u8; 16] {
+        SectorTrailer::new(
+            MifareKey([0x11; 6]),
+            AccessConditions::new(groups),
+            MifareKey([0x22; 6]),
+        )
+        .to_bytes()
+    }
+
+    #[test]
+    fn trailer_check_transport() {
+        let sector = Sector::new(1).unwrap();
+        let options = TrailerWriteOptions::default();
+
+        let checked = options.check(sector, &trailer([0, 0, 0, 0b001])).unwrap();
+        assert_eq!(checked.access, AccessConditions::TRANSPORT);
+        assert_eq!(checked.key_a, MifareKey([0x11; 6]));
+    }
+
+    #[test]
+    fn trailer_check_corrupted_access_bits() {
+        let sector = Sector::new(1).unwrap();
+        let mut block = trailer([0, 0, 0, 0b001]);
+        block[6] ^= 0x01;
+
+        let options = TrailerWriteOptions {
+            allow_irreversible: true,
+            ..Default::default()
+        };
+        assert_eq!(options.check(sector, &block), Err(PCDErrorCode::Invalid));
+    }
+
+    #[test]
+    fn trailer_check_irreversible() {
+        let sector = Sector::new(1).unwrap();
+        let allowed = TrailerWriteOptions {
+            allow_irreversible: true,
+            verify_with: KeyType::B,
+            ..Default::default()
+        };
+        let options = TrailerWriteOptions {
+            allow_irreversible: false,
+            ..allowed
+        };
+
+        // access bits can't be changed
+        let block = trailer([0, 0, 0, 0b100]);
+        assert_eq!(options.check(sector, &block), Err(PCDErrorCode::Invalid));
+        assert!(allowed.check(sector, &block).is_ok());
+
+        // access bits can be changed, keys can't
+        let block = trailer([0, 0, 0, 0b101]);
+        assert_eq!(options.check(sector, &block), Err(PCDErrorCode::Invalid));
+        assert!(allowed.check(sector, &block).is_ok());
+
+        // keys and access bits writable with Key B
+        assert!(options.check(sector, &trailer([0, 0, 0, 0b011])).is_ok());
+    }
+
+    #[test]
+    fn trailer_check_readable_key_b() {
+        let sector = Sector::new(1).unwrap();
+        let options = TrailerWriteOptions {
+            verify_with: KeyType::B,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            options.check(sector, &trailer([0, 0, 0, 0b001])),
+            Err(PCDErrorCode::Invalid)
+        );
+    }
+
+    #[test]
+    fn value_block_negative() {
+        let block = [
+            0x9C, 0xFF, 0xFF, 0xFF, 0x63, 0x00, 0x00, 0x00, 0x9C, 0xFF, 0xFF, 0xFF, 0x05, 0xFA,
+            0x05, 0xFA,
+        ];
+
+        let value = ValueBlock::new(-100, 5);
+        assert_eq!(value.to_bytes(), block);
+        assert_eq!(ValueBlock::parse(&block), Ok(value));
+
+        for value in [i32::MIN, -1, 0, 1, i32::MAX] {
+            let value = ValueBlock::new(value, 0xA5);
+            assert_eq!(ValueBlock::parse(&value.to_bytes()), Ok(value));
+        }
+    }
+
+    #[test]
+    fn value_block_corrupted() {
+        let block = ValueBlock::new(1000, 4).to_bytes();
+
+        // every copy of value and address is checked
+        for i in 0..16 {
+            let mut corrupted = block;
+            corrupted[i] ^= 0x01;
+            assert_eq!(
+                ValueBlock::parse(&corrupted),
+                Err(PCDErrorCode::IntegrityError)
+            );
+        }
+
+        assert_eq!(ValueBlock::parse(&block[..15]), Err(PCDErrorCode::Invalid));
+    }
+}
@@ -0,0 +1,388 @@
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    apdu::Command,
+    consts::PCDErrorCode,
+    iso_dep::IsoDep,
+    tlv::{Tag, TlvIter},
+    MFRC522,
+};
+
+/// NDEF Tag Application (version 2.0+)
+pub const NDEF_APPLICATION_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+pub const CC_FILE_ID: u16 = 0xE103;
+
+const INS_SELECT: u8 = 0xA4;
+const INS_READ_BINARY: u8 = 0xB0;
+const INS_UPDATE_BINARY: u8 = 0xD6;
+
+const TLV_NDEF_FILE_CONTROL: u32 = 0x04;
+const TLV_EXTENDED_NDEF_FILE_CONTROL: u32 = 0x06;
+
+/// Access condition byte value meaning free access
+pub const ACCESS_GRANTED: u8 = 0x00;
+
+/// READ/UPDATE BINARY with P1-P2 offset can address only 15 bits
+const MAX_OFFSET: usize = 0x7FFF;
+
+/// Max data read/written by single APDU
+const MAX_CHUNK: usize = 255;
+
+/// Capability Container file of Type 4 Tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityContainer {
+    pub cc_len: u16,
+    pub mapping_version: u8,
+
+    /// Max data that can be read with single READ BINARY
+    pub max_le: u16,
+
+    /// Max data that can be sent with single UPDATE BINARY
+    pub max_lc: u16,
+
+    pub ndef_file_id: u16,
+
+    /// Max size of NDEF file (including NLEN field)
+    pub max_ndef_size: u32,
+    pub read_access: u8,
+    pub write_access: u8,
+
+    /// NDEF file uses 4 byte ENLEN instead of 2 byte NLEN (mapping 3.0)
+    pub extended: bool,
+}
+
+impl CapabilityContainer {
+    pub fn parse(bytes: &[u8]) -> Result<Self, PCDErrorCode> {
+        if bytes.len() < 15 {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        let tlv = TlvIter::new(&bytes[7..])
+            .next()
+            .and_then(|tlv| tlv.ok())
+            .ok_or(PCDErrorCode::Protocol)?;
+
+        let extended = match (tlv.tag, tlv.value.len()) {
+            (Tag(TLV_NDEF_FILE_CONTROL), 6) => false,
+            (Tag(TLV_EXTENDED_NDEF_FILE_CONTROL), 8) => true,
+            _ => return Err(PCDErrorCode::Protocol),
+        };
+        let value = tlv.value;
+
+        let (max_ndef_size, access) = if extended {
+            (
+                u32::from_be_bytes([value[2], value[3], value[4], value[5]]),
+                &value[6..8],
+            )
+        } else {
+            (
+                u16::from_be_bytes([value[2], value[3]]) as u32,
+                &value[4..6],
+            )
+        };
+
+        Ok(Self {
+            cc_len: u16::from_be_bytes([bytes[0], bytes[1]]),
+            mapping_version: bytes[2],
+            max_le: u16::from_be_bytes([bytes[3], bytes[4]]),
+            max_lc: u16::from_be_bytes([bytes[5], bytes[6]]),
+            ndef_file_id: u16::from_be_bytes([value[0], value[1]]),
+            max_ndef_size,
+            read_access: access[0],
+            write_access: access[1],
+            extended,
+        })
+    }
+
+    pub fn can_read(&self) -> bool {
+        self.read_access == ACCESS_GRANTED
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.write_access == ACCESS_GRANTED
+    }
+
+    /// Size of NLEN/ENLEN field at the start of NDEF file
+    pub fn length_field_size(&self) -> usize {
+        if self.extended {
+            4
+        } else {
+            2
+        }
+    }
+
+    /// Max NDEF message length that fits into NDEF file
+    pub fn max_message_len(&self) -> usize {
+        (self.max_ndef_size as usize).saturating_sub(self.length_field_size())
+    }
+
+    fn read_chunk(&self) -> usize {
+        (self.max_le as usize).clamp(1, MAX_CHUNK)
+    }
+
+    fn write_chunk(&self) -> usize {
+        (self.max_lc as usize).clamp(1, MAX_CHUNK)
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Selects NDEF Tag Application and reads its Capability Container
+    pub async fn ndef_select(
+        &mut self,
+        iso: &mut IsoDep,
+    ) -> Result<CapabilityContainer, PCDErrorCode> {
+        let mut resp = [0; 64];
+        let cmd = Command::new(0x00, INS_SELECT, 0x04, 0x00)
+            .with_data(&NDEF_APPLICATION_AID)
+            .with_le(0);
+        self.transmit_apdu(iso, &cmd, &mut resp)
+            .await?
+            .into_result()?;
+
+        self.ndef_select_file(iso, CC_FILE_ID).await?;
+
+        let mut cc = [0; 17];
+        self.ndef_read_binary(iso, 0, &mut cc[..15]).await?;
+
+        // extended NDEF file control TLV is 2 bytes longer
+        if cc[7] as u32 == TLV_EXTENDED_NDEF_FILE_CONTROL {
+            self.ndef_read_binary(iso, 15, &mut cc[15..]).await?;
+            return CapabilityContainer::parse(&cc);
+        }
+
+        CapabilityContainer::parse(&cc[..15])
+    }
+
+    /// Reads NDEF message into `buff`, returns its length
+    pub async fn ndef_read(
+        &mut self,
+        iso: &mut IsoDep,
+        cc: &CapabilityContainer,
+        buff: &mut [u8],
+    ) -> Result<usize, PCDErrorCode> {
+        if !cc.can_read() {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        self.ndef_select_file(iso, cc.ndef_file_id).await?;
+
+        let field_size = cc.length_field_size();
+        let mut len = [0; 4];
+        self.ndef_read_binary(iso, 0, &mut len[..field_size])
+            .await?;
+
+        let len = if cc.extended {
+            u32::from_be_bytes(len) as usize
+        } else {
+            u16::from_be_bytes([len[0], len[1]]) as usize
+        };
+
+        if len > cc.max_message_len() {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        if len > buff.len() {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(cc.read_chunk());
+            self.ndef_read_binary(iso, field_size + done, &mut buff[done..done + chunk])
+                .await?;
+
+            done += chunk;
+        }
+
+        Ok(len)
+    }
+
+    /// Writes NDEF message. Length field is zeroed first and set only after
+    /// whole message is written, so interrupted write leaves empty (valid) tag.
+    pub async fn ndef_write(
+        &mut self,
+        iso: &mut IsoDep,
+        cc: &CapabilityContainer,
+        message: &[u8],
+    ) -> Result<(), PCDErrorCode> {
+        if !cc.can_write() {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        if message.len() > cc.max_message_len() {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        self.ndef_select_file(iso, cc.ndef_file_id).await?;
+
+        let field_size = cc.length_field_size();
+        self.ndef_update_binary(iso, 0, &[0; 4][..field_size])
+            .await?;
+
+        for (i, chunk) in message.chunks(cc.write_chunk()).enumerate() {
+            let offset = field_size + i * cc.write_chunk();
+            self.ndef_update_binary(iso, offset, chunk).await?;
+        }
+
+        let len = (message.len() as u32).to_be_bytes();
+        self.ndef_update_binary(iso, 0, &len[4 - field_size..])
+            .await
+    }
+
+    async fn ndef_select_file(
+        &mut self,
+        iso: &mut IsoDep,
+        file_id: u16,
+    ) -> Result<(), PCDErrorCode> {
+        let mut resp = [0; 2];
+        let file_id = file_id.to_be_bytes();
+        let cmd = Command::new(0x00, INS_SELECT, 0x00, 0x0C).with_data(&file_id);
+        self.transmit_apdu(iso, &cmd, &mut resp)
+            .await?
+            .into_result()?;
+
+        Ok(())
+    }
+
+    /// Reads exactly `buff.len()` bytes from selected file
+    async fn ndef_read_binary(
+        &mut self,
+        iso: &mut IsoDep,
+        offset: usize,
+        buff: &mut [u8],
+    ) -> Result<(), PCDErrorCode> {
+        if offset + buff.len() > MAX_OFFSET + 1 {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        let [p1, p2] = (offset as u16).to_be_bytes();
+        let cmd = Command::new(0x00, INS_READ_BINARY, p1, p2).with_le(buff.len() as u32);
+
+        let mut resp = [0; MAX_CHUNK + 2];
+        let data = self
+            .transmit_apdu(iso, &cmd, &mut resp)
+            .await?
+            .into_result()?;
+
+        if data.len() != buff.len() {
+            return Err(PCDErrorCode::Protocol);
+        }
+
+        buff.copy_from_slice(data);
+        Ok(())
+    }
+
+    async fn ndef_update_binary(
+        &mut self,
+        iso: &mut IsoDep,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), PCDErrorCode> {
+        if offset + data.len() > MAX_OFFSET + 1 {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        let [p1, p2] = (offset as u16).to_be_bytes();
+        let cmd = Command::new(0x00, INS_UPDATE_BINARY, p1, p2).with_data(data);
+
+        let mut resp = [0; 2];
+        self.transmit_apdu(iso, &cmd, &mut resp)
+            .await?
+            .into_result()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mapping 2.0, MLe 0x003B, MLc 0x0034, NDEF file E104 of 2048 bytes
+    const CC: [u8; 15] = [
+        0x00, 0x0F, 0x20, 0x00, 0x3B, 0x00, 0x34, 0x04, 0x06, 0xE1, 0x04, 0x08, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn parse_cc() {
+        let cc = CapabilityContainer::parse(&CC).unwrap();
+        assert_eq!(
+            cc,
+            CapabilityContainer {
+                cc_len: 15,
+                mapping_version: 0x20,
+                max_le: 0x3B,
+                max_lc: 0x34,
+                ndef_file_id: 0xE104,
+                max_ndef_size: 2048,
+                read_access: ACCESS_GRANTED,
+                write_access: ACCESS_GRANTED,
+                extended: false,
+            }
+        );
+        assert!(cc.can_read() && cc.can_write());
+        assert_eq!(cc.length_field_size(), 2);
+    }
+
+    #[test]
+    fn parse_cc_access() {
+        // read-only tag, proprietary read access
+        let mut bytes = CC;
+        bytes[13] = 0x80;
+        bytes[14] = 0xFF;
+
+        let cc = CapabilityContainer::parse(&bytes).unwrap();
+        assert_eq!((cc.read_access, cc.write_access), (0x80, 0xFF));
+        assert!(!cc.can_read());
+        assert!(!cc.can_write());
+    }
+
+    #[test]
+    fn parse_cc_extended() {
+        let bytes = [
+            0x00, 0x11, 0x30, 0x00, 0xFF, 0x00, 0xFF, 0x06, 0x08, 0xE1, 0x04, 0x00, 0x01, 0x00,
+            0x00, 0x00, 0xFF,
+        ];
+
+        let cc = CapabilityContainer::parse(&bytes).unwrap();
+        assert!(cc.extended);
+        assert_eq!(cc.max_ndef_size, 0x10000);
+        assert_eq!((cc.read_access, cc.write_access), (0x00, 0xFF));
+        assert_eq!(cc.length_field_size(), 4);
+
+        // extended TLV cut to 15 bytes
+        assert_eq!(
+            CapabilityContainer::parse(&bytes[..15]),
+            Err(PCDErrorCode::Protocol)
+        );
+    }
+
+    #[test]
+    fn parse_cc_invalid() {
+        assert_eq!(CapabilityContainer::parse(&[]), Err(PCDErrorCode::Protocol));
+        assert_eq!(
+            CapabilityContainer::parse(&CC[..14]),
+            Err(PCDErrorCode::Protocol)
+        );
+
+        // T other than NDEF file control TLV
+        let mut bytes = CC;
+        bytes[7] = 0x05;
+        assert_eq!(
+            CapabilityContainer::parse(&bytes),
+            Err(PCDErrorCode::Protocol)
+        );
+
+        // L doesn't match T
+        let mut bytes = CC;
+        bytes[8] = 0x05;
+        assert_eq!(
+            CapabilityContainer::parse(&bytes),
+            Err(PCDErrorCode::Protocol)
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{
     consts::{PCDCommand, PCDErrorCode, PCDRegister, PCDVersion, Uid},
-    MFRC522,
+    geometry::BlockAddr,
+    mifare::{KeyType, MifareKey},
+    DEFAULT_TIMEOUT_US, MFRC522,
 };
 use embedded_hal::digital::OutputPin;
 use heapless::String;
@@ -138,43 +140,97 @@ where
         Err(PCDErrorCode::Timeout)
     }
 
+    /// Sets timeout of PICC response (timer and software timeout).
+    /// Used for ISO 14443-4 frame waiting time, which can be up to ~5s.
+    pub async fn pcd_set_timeout(&mut self, timeout_us: u32) -> Result<(), PCDErrorCode> {
+        // timer frequency: 13.56MHz / (2 * prescaler + 1), reload is 16 bit
+        let cycles = timeout_us as u64 * 1356 / 100;
+        let prescaler = ((cycles / 0xFFFF).saturating_sub(1) / 2 + 1).clamp(0xA9, 0xFFF);
+        let reload = (cycles / (2 * prescaler + 1)).clamp(1, 0xFFFF);
+
+        self.write_reg(PCDRegister::TModeReg, 0x80 | (prescaler >> 8) as u8)
+            .await?;
+        self.write_reg(PCDRegister::TPrescalerReg, prescaler as u8)
+            .await?;
+        self.write_reg(PCDRegister::TReloadRegH, (reload >> 8) as u8)
+            .await?;
+        self.write_reg(PCDRegister::TReloadRegL, reload as u8)
+            .await?;
+
+        self.timeout_us = timeout_us as u64 + 11_000;
+        Ok(())
+    }
+
+    /// Restores timeout set in `pcd_init` (25ms)
+    pub async fn pcd_reset_timeout(&mut self) -> Result<(), PCDErrorCode> {
+        self.write_reg(PCDRegister::TModeReg, 0x80).await?;
+        self.write_reg(PCDRegister::TPrescalerReg, 0xA9).await?;
+        self.write_reg(PCDRegister::TReloadRegH, 0x03).await?;
+        self.write_reg(PCDRegister::TReloadRegL, 0xE8).await?;
+
+        self.timeout_us = DEFAULT_TIMEOUT_US;
+        Ok(())
+    }
+
     pub async fn pcd_stop_crypto1(&mut self) -> Result<(), PCDErrorCode> {
         self.pcd_clear_register_bit_mask(PCDRegister::Status2Reg, 0x08)
             .await
     }
 
-    /// Key - 6 bytes
+    /// Authenticates sector of `block_addr` (MIFARE Classic Crypto1).
+    ///
+    /// Card doesn't answer when key is wrong, so after timeout it's woken up
+    /// and selected again: if it responds, `AuthenticationFailed` is returned
+    /// (and next key can be tried right away), otherwise the original error.
     pub async fn pcd_authenticate(
         &mut self,
-        cmd: u8,
-        block_addr: u8,
-        key: &[u8],
+        key_type: KeyType,
+        block_addr: impl Into<BlockAddr>,
+        key: &MifareKey,
         uid: &Uid,
     ) -> Result<(), PCDErrorCode> {
-        if key.len() != 6 && key.len() != 0xA {
-            return Err(PCDErrorCode::Invalid);
-        }
-
         let wait_irq = 0x10;
         let mut send_data = [0; 12];
-        send_data[0] = cmd;
-        send_data[1] = block_addr;
-        send_data[2..8].copy_from_slice(&key);
+        send_data[0] = key_type.command();
+        send_data[1] = block_addr.into().0;
+        send_data[2..8].copy_from_slice(key.as_bytes());
         send_data[8..12]
             .copy_from_slice(&uid.uid_bytes[(uid.size as usize - 4)..(uid.size as usize)]);
 
-        self.pcd_communicate_with_picc(
-            PCDCommand::MFAuthent,
-            wait_irq,
-            &send_data,
-            12,
-            &mut [],
-            &mut 0,
-            &mut 0,
-            0,
-            false,
-        )
-        .await
+        let res = self
+            .pcd_communicate_with_picc(
+                PCDCommand::MFAuthent,
+                wait_irq,
+                &send_data,
+                12,
+                &mut [],
+                &mut 0,
+                &mut 0,
+                0,
+                false,
+            )
+            .await;
+
+        match res {
+            Ok(()) => {
+                let status = self.read_reg(PCDRegister::Status2Reg).await?;
+                if status & 0x08 == 0 {
+                    return Err(PCDErrorCode::AuthenticationFailed);
+                }
+
+                Ok(())
+            }
+            Err(PCDErrorCode::Timeout | PCDErrorCode::Error) => {
+                self.pcd_stop_crypto1().await?;
+
+                let mut uid = uid.clone();
+                match self.picc_wakeup_uid(&mut uid).await {
+                    Ok(()) => Err(PCDErrorCode::AuthenticationFailed),
+                    Err(_) => res,
+                }
+            }
+            Err(e) => Err(e),
+        }
     }
 
     pub async fn pcd_mifare_transceive(
@@ -330,7 +386,7 @@ where
                 break;
             }
 
-            if n & 0x01 != 0 || (self.get_current_time)() - start_time >= 36_000 {
+            if n & 0x01 != 0 || (self.get_current_time)() - start_time >= self.timeout_us {
                 return Err(PCDErrorCode::Timeout);
             }
         }
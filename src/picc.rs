@@ -1,5 +1,5 @@
 use crate::{
-    consts::{PCDErrorCode, PCDRegister, PICCCommand, Uid},
+    consts::{Atqa, PCDErrorCode, PCDRegister, PICCCommand, Uid},
     tif, MFRC522,
 };
 use embedded_hal::digital::OutputPin;
@@ -9,18 +9,12 @@ where
     S: embedded_hal::spi::SpiDevice,
     C: OutputPin,
 {
-    pub async fn picc_is_new_card_present(&mut self) -> Result<(), PCDErrorCode> {
-        let mut buffer_atqa = [0; 2];
-        let mut buffer_size = 2;
-
+    pub async fn picc_is_new_card_present(&mut self) -> Result<Atqa, PCDErrorCode> {
         self.write_reg(PCDRegister::TxModeReg, 0x00).await?;
         self.write_reg(PCDRegister::RxModeReg, 0x00).await?;
         self.write_reg(PCDRegister::ModWidthReg, 0x26).await?;
 
-        self.picc_request_a(&mut buffer_atqa, &mut buffer_size)
-            .await?;
-
-        Ok(())
+        self.picc_request_a().await
     }
 
     pub async fn picc_halta(&mut self) -> Result<(), PCDErrorCode> {
@@ -41,6 +35,38 @@ where
         }
     }
 
+    /// Request for Answer To Select, switches selected card into ISO 14443-4
+    /// mode. FSDI is always 5 (64 bytes - size of MFRC522 FIFO).
+    ///
+    /// Returns ATS length (without CRC), ATS is written into `ats`.
+    pub async fn picc_rats(&mut self, cid: u8, ats: &mut [u8]) -> Result<u8, PCDErrorCode> {
+        if cid > 14 {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let mut buff = [0; 4];
+        buff[0] = PICCCommand::PICC_CMD_RATS;
+        buff[1] = 0x50 | cid;
+        self.pcd_calc_crc_single_buf(&mut buff, 2, 2).await?;
+
+        let mut back = [0; 64];
+        let mut back_len = 64;
+        self.pcd_transceive_data(&buff, 4, &mut back, &mut back_len, &mut 0, 0, true)
+            .await?;
+
+        let len = back_len.saturating_sub(2);
+        if len == 0 || back[0] != len {
+            return Err(PCDErrorCode::Error);
+        }
+
+        if ats.len() < len as usize {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        ats[..len as usize].copy_from_slice(&back[..len as usize]);
+        Ok(len)
+    }
+
     pub async fn picc_select(&mut self, uid: &mut Uid, valid_bits: u8) -> Result<(), PCDErrorCode> {
         let mut uid_complete = false;
         let mut use_casdcade_tag;
@@ -220,25 +246,112 @@ where
             }
         }
 
+        // every cascade level adds 3 uid bytes (4 on the last one)
+        uid.size = 3 * cascade_level + 1;
         Ok(())
     }
 
-    pub async fn picc_wakeup_a(
-        &mut self,
-        buffer_atqa: &mut [u8],
-        buffer_size: &mut u8,
-    ) -> Result<(), PCDErrorCode> {
-        self.picc_reqa_or_wupa(PICCCommand::PICC_CMD_WUPA, buffer_atqa, buffer_size)
-            .await
+    /// Selects card with already known `uid` (full SELECT on every cascade level,
+    /// without anticollision). Updates `uid.sak`.
+    ///
+    /// Card must be in READY state (after REQA/WUPA).
+    pub async fn picc_select_uid(&mut self, uid: &mut Uid) -> Result<(), PCDErrorCode> {
+        let levels = match uid.size {
+            4 => 1,
+            7 => 2,
+            10 => 3,
+            _ => return Err(PCDErrorCode::Invalid),
+        };
+
+        self.pcd_clear_register_bit_mask(PCDRegister::CollReg, 0x80)
+            .await?;
+
+        for level in 1..=levels {
+            let mut buff = [0; 9];
+            buff[0] = match level {
+                1 => PICCCommand::PICC_CMD_SEL_CL1,
+                2 => PICCCommand::PICC_CMD_SEL_CL2,
+                _ => PICCCommand::PICC_CMD_SEL_CL3,
+            };
+            buff[1] = 0x70;
+
+            let uid_index = 3 * (level - 1);
+            let last_level = level == levels;
+            if last_level {
+                buff[2..6].copy_from_slice(&uid.uid_bytes[uid_index..uid_index + 4]);
+            } else {
+                buff[2] = PICCCommand::PICC_CMD_CT;
+                buff[3..6].copy_from_slice(&uid.uid_bytes[uid_index..uid_index + 3]);
+            }
+
+            buff[6] = buff[2] ^ buff[3] ^ buff[4] ^ buff[5];
+            self.pcd_calc_crc_single_buf(&mut buff, 7, 7).await?;
+
+            let mut sak_buff = [0; 3];
+            let mut sak_len = 3;
+            self.pcd_transceive_data(&buff, 9, &mut sak_buff, &mut sak_len, &mut 0, 0, true)
+                .await?;
+
+            if sak_len != 3 {
+                return Err(PCDErrorCode::Error);
+            }
+
+            // cascade bit must be set on every level except the last one
+            let uid_not_complete = sak_buff[0] & 0x04 != 0;
+            if uid_not_complete == last_level {
+                return Err(PCDErrorCode::Error);
+            }
+
+            if last_level {
+                uid.sak = sak_buff[0];
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn picc_request_a(
-        &mut self,
-        buffer_atqa: &mut [u8],
-        buffer_size: &mut u8,
-    ) -> Result<(), PCDErrorCode> {
-        self.picc_reqa_or_wupa(PICCCommand::PICC_CMD_REQA, buffer_atqa, buffer_size)
-            .await
+    /// Wakes up (WUPA) and selects card with known `uid`, also works for halted
+    /// cards. Other cards in the field go back to IDLE/HALT on SELECT.
+    pub async fn picc_wakeup_uid(&mut self, uid: &mut Uid) -> Result<(), PCDErrorCode> {
+        uid.atqa = self.picc_wakeup_a().await?;
+        self.picc_select_uid(uid).await
+    }
+
+    /// Halts card and selects it again, drops MIFARE authentication
+    pub(crate) async fn picc_reselect(&mut self, uid: &Uid) -> Result<(), PCDErrorCode> {
+        _ = self.picc_halta().await;
+        self.pcd_stop_crypto1().await?;
+
+        let mut uid = uid.clone();
+        self.picc_wakeup_uid(&mut uid).await
+    }
+
+    pub async fn picc_wakeup_a(&mut self) -> Result<Atqa, PCDErrorCode> {
+        let mut buffer_atqa = [0; 2];
+        let mut buffer_size = 2;
+
+        self.picc_reqa_or_wupa(
+            PICCCommand::PICC_CMD_WUPA,
+            &mut buffer_atqa,
+            &mut buffer_size,
+        )
+        .await?;
+
+        Ok(Atqa::from_bytes(buffer_atqa))
+    }
+
+    pub async fn picc_request_a(&mut self) -> Result<Atqa, PCDErrorCode> {
+        let mut buffer_atqa = [0; 2];
+        let mut buffer_size = 2;
+
+        self.picc_reqa_or_wupa(
+            PICCCommand::PICC_CMD_REQA,
+            &mut buffer_atqa,
+            &mut buffer_size,
+        )
+        .await?;
+
+        Ok(Atqa::from_bytes(buffer_atqa))
     }
 
     pub async fn picc_reqa_or_wupa(
@@ -270,6 +383,7 @@ where
             return Err(PCDErrorCode::Error);
         }
 
+        self.last_atqa = Atqa::from_bytes([buffer_atqa[0], buffer_atqa[1]]);
         Ok(())
     }
 }
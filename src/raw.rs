@@ -0,0 +1,278 @@
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    consts::{PCDCommand, PCDErrorCode, PCDRegister},
+    MFRC522,
+};
+
+/// Framing of a raw frame, see [`MFRC522::pcd_transceive_raw`]
+#[derive(Debug, Clone, Copy)]
+pub struct RawFrameConfig {
+    /// Append CRC_A to transmitted data (TxModeReg TxCRCEn)
+    pub tx_crc: bool,
+
+    /// Check CRC_A of received data (RxModeReg RxCRCEn)
+    pub rx_crc: bool,
+
+    /// Generate/check parity bits (MfRxReg ParityDisable cleared).
+    /// If disabled, parity bits must be put into data by hand
+    /// (see [`encode_parity`] / [`decode_parity`]).
+    pub parity: bool,
+
+    /// Number of valid bits in last transmitted byte (0 = whole byte)
+    pub tx_last_bits: u8,
+
+    /// Bit position of first received bit in first byte
+    pub rx_align: u8,
+}
+
+impl Default for RawFrameConfig {
+    fn default() -> Self {
+        Self {
+            tx_crc: false,
+            rx_crc: false,
+            parity: true,
+            tx_last_bits: 0,
+            rx_align: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawResponse {
+    /// Number of received bytes (including last partial one)
+    pub len: u8,
+
+    /// Number of valid bits in last received byte (0 = whole byte)
+    pub rx_last_bits: u8,
+}
+
+impl RawResponse {
+    pub fn bit_len(&self) -> usize {
+        match self.rx_last_bits {
+            0 => self.len as usize * 8,
+            bits => (self.len as usize).saturating_sub(1) * 8 + bits as usize,
+        }
+    }
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Sends arbitrary bit-length frame with given CRC/parity settings.
+    /// Registers changed here are restored afterwards (also on error).
+    pub async fn pcd_transceive_raw(
+        &mut self,
+        config: &RawFrameConfig,
+        send_data: &[u8],
+        back_data: &mut [u8],
+    ) -> Result<RawResponse, PCDErrorCode> {
+        if send_data.len() > 64 || config.tx_last_bits > 7 || config.rx_align > 7 {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let tx_mode = self.read_reg(PCDRegister::TxModeReg).await?;
+        let rx_mode = self.read_reg(PCDRegister::RxModeReg).await?;
+        let mf_rx = self.read_reg(PCDRegister::MfRxReg).await?;
+
+        self.write_reg(
+            PCDRegister::TxModeReg,
+            (tx_mode & !0x80) | if config.tx_crc { 0x80 } else { 0 },
+        )
+        .await?;
+        self.write_reg(
+            PCDRegister::RxModeReg,
+            (rx_mode & !0x80) | if config.rx_crc { 0x80 } else { 0 },
+        )
+        .await?;
+        self.write_reg(
+            PCDRegister::MfRxReg,
+            (mf_rx & !0x10) | if config.parity { 0 } else { 0x10 },
+        )
+        .await?;
+
+        let res = self
+            .pcd_transceive_raw_inner(config, send_data, back_data)
+            .await;
+
+        self.write_reg(PCDRegister::TxModeReg, tx_mode).await?;
+        self.write_reg(PCDRegister::RxModeReg, rx_mode).await?;
+        self.write_reg(PCDRegister::MfRxReg, mf_rx).await?;
+
+        res
+    }
+
+    async fn pcd_transceive_raw_inner(
+        &mut self,
+        config: &RawFrameConfig,
+        send_data: &[u8],
+        back_data: &mut [u8],
+    ) -> Result<RawResponse, PCDErrorCode> {
+        let mut back_len = back_data.len().min(64) as u8;
+        let mut valid_bits = config.tx_last_bits;
+
+        self.pcd_communicate_with_picc(
+            PCDCommand::Transceive,
+            0x30,
+            send_data,
+            send_data.len() as u8,
+            back_data,
+            &mut back_len,
+            &mut valid_bits,
+            config.rx_align,
+            false,
+        )
+        .await?;
+
+        if config.rx_crc && self.read_reg(PCDRegister::ErrorReg).await? & 0x04 != 0 {
+            return Err(PCDErrorCode::CrcWrong);
+        }
+
+        let rx_last_bits = self.read_reg(PCDRegister::ControlReg).await? & 0x07;
+        Ok(RawResponse {
+            len: back_len,
+            rx_last_bits,
+        })
+    }
+}
+
+/// Odd parity bit of a byte (as used by ISO 14443-A)
+pub fn odd_parity(byte: u8) -> u8 {
+    (byte.count_ones() as u8 & 1) ^ 1
+}
+
+/// Interleaves parity bit after every byte (LSB first, as sent on air),
+/// so it can be sent with parity generation disabled.
+///
+/// `parity` - one bit per byte (0/1), odd parity is calculated if `None`.
+/// Returns number of bytes used in `out` and valid bits in the last one
+/// (to be used as `tx_last_bits`).
+pub fn encode_parity(
+    data: &[u8],
+    parity: Option<&[u8]>,
+    out: &mut [u8],
+) -> Result<(usize, u8), PCDErrorCode> {
+    let bit_len = data.len() * 9;
+    let out_len = bit_len.div_ceil(8);
+    if out.len() < out_len || parity.is_some_and(|p| p.len() < data.len()) {
+        return Err(PCDErrorCode::NoRoom);
+    }
+
+    out[..out_len].fill(0);
+    let mut bit = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        let parity_bit = match parity {
+            Some(p) => p[i] & 1,
+            None => odd_parity(byte),
+        };
+
+        let word = byte as u16 | ((parity_bit as u16) << 8);
+        for b in 0..9 {
+            if word & (1 << b) != 0 {
+                out[bit / 8] |= 1 << (bit % 8);
+            }
+            bit += 1;
+        }
+    }
+
+    Ok((out_len, (bit_len % 8) as u8))
+}
+
+/// Splits frame received with parity check disabled into data bytes and
+/// parity bits (one per byte in `parity`). Returns number of decoded bytes.
+pub fn decode_parity(
+    raw: &[u8],
+    bit_len: usize,
+    data: &mut [u8],
+    parity: &mut [u8],
+) -> Result<usize, PCDErrorCode> {
+    let bytes = bit_len / 9;
+    if raw.len() * 8 < bit_len {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    if data.len() < bytes || parity.len() < bytes {
+        return Err(PCDErrorCode::NoRoom);
+    }
+
+    for i in 0..bytes {
+        let mut word = 0u16;
+        for b in 0..9 {
+            let bit = i * 9 + b;
+            if raw[bit / 8] & (1 << (bit % 8)) != 0 {
+                word |= 1 << b;
+            }
+        }
+
+        data[i] = word as u8;
+        parity[i] = (word >> 8) as u8;
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_parity_select() {
+        // SELECT CL1 with anticollision: 0x93 (parity 1), 0x20 (parity 0)
+        let mut out = [0; 3];
+        assert_eq!(encode_parity(&[0x93, 0x20], None, &mut out), Ok((3, 2)));
+        assert_eq!(out, [0x93, 0x41, 0x00]);
+
+        let mut small = [0; 2];
+        assert_eq!(
+            encode_parity(&[0x93, 0x20], None, &mut small),
+            Err(PCDErrorCode::NoRoom)
+        );
+    }
+
+    #[test]
+    fn parity_round_trip() {
+        let data = [0x00, 0xFF, 0x93, 0x20, 0x5A, 0xA5, 0x01, 0x80];
+        let forced = [0, 1, 1, 0, 1, 0, 0, 1];
+
+        // every length up to 8 bytes, all but the last are not byte aligned
+        for len in 1..=data.len() {
+            for parity in [None, Some(&forced[..len])] {
+                let mut raw = [0; 9];
+                let (raw_len, last_bits) = encode_parity(&data[..len], parity, &mut raw).unwrap();
+                assert_eq!(raw_len, (len * 9).div_ceil(8));
+                assert_eq!(last_bits as usize, (len * 9) % 8);
+
+                let mut decoded = [0; 8];
+                let mut decoded_parity = [0; 8];
+                let bytes =
+                    decode_parity(&raw[..raw_len], len * 9, &mut decoded, &mut decoded_parity);
+                assert_eq!(bytes, Ok(len));
+                assert_eq!(decoded[..len], data[..len]);
+
+                for i in 0..len {
+                    let expected = parity.map_or(odd_parity(data[i]), |p| p[i]);
+                    assert_eq!(decoded_parity[i], expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_parity_short_frame() {
+        let mut data = [0; 2];
+        let mut parity = [0; 2];
+        assert_eq!(
+            decode_parity(&[0x93, 0x41], 18, &mut data, &mut parity),
+            Err(PCDErrorCode::Invalid)
+        );
+
+        // incomplete byte at the end is ignored
+        assert_eq!(
+            decode_parity(&[0x93, 0x41, 0x00], 17, &mut data, &mut parity),
+            Ok(1)
+        );
+        assert_eq!((data[0], parity[0]), (0x93, 1));
+    }
+}
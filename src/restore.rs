@@ -0,0 +1,656 @@
+use core::ops::Range;
+
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    access::AccessConditions,
+    consts::{PCDErrorCode, PICCType, Uid},
+    dictionary::KeyMap,
+    dump::ClassicDump,
+    geometry::{BlockAddr, ClassicSize, Sector},
+    magic::validate_manufacturer_block,
+    mifare::{KeyType, MifareKey, TrailerWriteOptions},
+    MFRC522,
+};
+
+/// How blocks are written to card
+#[derive(Debug, Clone, Copy)]
+pub enum RestoreMode<'a> {
+    /// Normal authentication with current keys of card (sector trailers of
+    /// dump bring new keys)
+    Keys(&'a KeyMap),
+
+    /// Gen1a backdoor, no keys are needed
+    Gen1a,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RestoreOptions<'a> {
+    pub mode: RestoreMode<'a>,
+
+    /// Only authenticates and checks permissions, nothing is written
+    pub dry_run: bool,
+
+    /// Write manufacturer block too (magic cards only). Block is checked by
+    /// [`validate_manufacturer_block`] and written by `magic_gen1a_write_block0`
+    /// or `magic_gen2_write_block0`, rest of the card is written with new uid.
+    pub write_block0: bool,
+
+    /// Write sector trailers (after all data blocks). Trailers are written
+    /// as they are in dump, trailers with unknown keys are skipped.
+    pub write_trailers: bool,
+
+    /// Allow trailers with access bits or keys that can never be changed
+    /// again
+    pub allow_irreversible: bool,
+}
+
+impl<'a> RestoreOptions<'a> {
+    pub fn new(mode: RestoreMode<'a>) -> Self {
+        Self {
+            mode,
+            dry_run: false,
+            write_block0: false,
+            write_trailers: true,
+            allow_irreversible: false,
+        }
+    }
+
+    fn trailer_options(&self) -> TrailerWriteOptions<'static> {
+        TrailerWriteOptions {
+            allow_irreversible: self.allow_irreversible,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    Manufacturer,
+    Trailer,
+
+    /// Block wasn't read into dump
+    NotInDump,
+
+    /// No key of sector is known
+    NoKey,
+
+    /// Current access conditions don't allow write with known keys
+    NotWritable,
+
+    /// Trailer in dump doesn't contain real keys (see [`ClassicDump::key_known`])
+    KeyUnknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreStatus {
+    /// Not processed (restore was aborted)
+    Pending,
+
+    /// Written and verified by read-back
+    Written,
+
+    /// Would be written (dry run)
+    DryRun,
+    Skipped(SkipReason),
+    Failed(PCDErrorCode),
+}
+
+/// Result of every block of restored card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub size: ClassicSize,
+    blocks: [RestoreStatus; 256],
+}
+
+impl RestoreReport {
+    pub fn new(size: ClassicSize) -> Self {
+        Self {
+            size,
+            blocks: [RestoreStatus::Pending; 256],
+        }
+    }
+
+    pub fn status(&self, block: BlockAddr) -> RestoreStatus {
+        self.blocks[block.0 as usize]
+    }
+
+    /// Statuses of card blocks (`size.block_count()` of them)
+    pub fn blocks(&self) -> &[RestoreStatus] {
+        &self.blocks[..self.size.block_count() as usize]
+    }
+
+    /// Written blocks (blocks that would be written in dry run)
+    pub fn written(&self) -> usize {
+        self.count(|status| matches!(status, RestoreStatus::Written | RestoreStatus::DryRun))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|status| matches!(status, RestoreStatus::Skipped(_)))
+    }
+
+    /// Failed and not processed blocks
+    pub fn failed(&self) -> usize {
+        self.count(|status| matches!(status, RestoreStatus::Failed(_) | RestoreStatus::Pending))
+    }
+
+    fn count(&self, f: impl Fn(&RestoreStatus) -> bool) -> usize {
+        self.blocks().iter().filter(|status| f(status)).count()
+    }
+}
+
+/// Blocks in order they are restored: data blocks first, sector trailers last
+/// (new keys would lock out the rest of sector)
+fn restore_order(size: ClassicSize) -> impl Iterator<Item = BlockAddr> {
+    [false, true].into_iter().flat_map(move |trailers| {
+        size.blocks()
+            .filter(move |block| block.is_trailer() == trailers)
+    })
+}
+
+/// Blocks skipped because of `options` or content of dump (before card is used)
+fn skip_reason(
+    dump: &ClassicDump,
+    block: BlockAddr,
+    options: &RestoreOptions<'_>,
+) -> Option<SkipReason> {
+    if block.is_manufacturer() && !options.write_block0 {
+        return Some(SkipReason::Manufacturer);
+    }
+
+    if block.is_trailer() && !options.write_trailers {
+        return Some(SkipReason::Trailer);
+    }
+
+    if !dump.block(block).is_read() {
+        return Some(SkipReason::NotInDump);
+    }
+
+    let sector = block.sector();
+    if block.is_trailer()
+        && !(dump.key_known(sector, KeyType::A) && dump.key_known(sector, KeyType::B))
+    {
+        return Some(SkipReason::KeyUnknown);
+    }
+
+    None
+}
+
+/// Known key allowed to write `block` by `access` currently on card (Key A
+/// preferred, readable Key B can't be used). If access conditions couldn't
+/// be read, any known key is used and card decides.
+fn writable_key(
+    block: BlockAddr,
+    access: Option<&AccessConditions>,
+    key_map: &KeyMap,
+) -> Result<KeyType, SkipReason> {
+    let sector = block.sector();
+    let (any_key, _) = key_map.any(sector).ok_or(SkipReason::NoKey)?;
+    let Some(access) = access else {
+        return Ok(any_key);
+    };
+
+    let allowed = if block.is_trailer() {
+        access.trailer().access_bits_write
+    } else {
+        access.data(block.access_group()).write
+    };
+
+    let key_b_usable = !access.trailer().key_b_readable();
+    [KeyType::A, KeyType::B]
+        .into_iter()
+        .find(|&key_type| {
+            key_map.get(sector, key_type).is_some()
+                && (key_type == KeyType::A || key_b_usable)
+                && allowed.allows(key_type)
+        })
+        .ok_or(SkipReason::NotWritable)
+}
+
+/// State of sector being restored with keys
+#[derive(Default)]
+struct SectorState {
+    /// Access conditions currently on card, `None` if they couldn't be read
+    access: Option<Option<AccessConditions>>,
+    authenticated: Option<KeyType>,
+}
+
+impl<S, C> MFRC522<S, C>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    /// Writes MIFARE Classic `dump` to card: all data blocks first, sector
+    /// trailers last. Every written block is read back and compared (only
+    /// access bits and GPB of trailers, keys can't be read).
+    ///
+    /// Result of every block is stored in `report`, `Err` is returned only
+    /// if card stops responding (remaining blocks stay `Pending`).
+    /// Card is selected again before returning (with uid from block 0 of
+    /// dump if it was written).
+    pub async fn mifare_restore_dump(
+        &mut self,
+        uid: &Uid,
+        dump: &ClassicDump,
+        options: &RestoreOptions<'_>,
+        report: &mut RestoreReport,
+    ) -> Result<(), PCDErrorCode> {
+        let card_size = ClassicSize::from_picc_type(&PICCType::from_sak(uid.sak));
+        if card_size.is_some_and(|size| size.block_count() < dump.size.block_count()) {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        *report = RestoreReport::new(dump.size);
+
+        match options.mode {
+            RestoreMode::Gen1a if options.dry_run => {
+                if !self.magic_gen1a_detect().await? {
+                    return Err(PCDErrorCode::MifareNack);
+                }
+            }
+            RestoreMode::Gen1a => self.magic_gen1a_unlock().await?,
+            RestoreMode::Keys(_) => {}
+        }
+
+        // block 0 changes uid used for authentication
+        let mut uid = uid.clone();
+        let mut state = SectorState::default();
+        let mut sector = None;
+        for block in restore_order(dump.size) {
+            if sector != Some(block.sector()) {
+                sector = Some(block.sector());
+                state = SectorState::default();
+            }
+
+            report.blocks[block.0 as usize] = self
+                .restore_block(&mut uid, dump, block, options, &mut state)
+                .await?;
+        }
+
+        self.picc_reselect(&uid).await
+    }
+
+    async fn restore_block(
+        &mut self,
+        uid: &mut Uid,
+        dump: &ClassicDump,
+        block: BlockAddr,
+        options: &RestoreOptions<'_>,
+        state: &mut SectorState,
+    ) -> Result<RestoreStatus, PCDErrorCode> {
+        if let Some(reason) = skip_reason(dump, block, options) {
+            return Ok(RestoreStatus::Skipped(reason));
+        }
+
+        let data = dump.block(block);
+        let sector = block.sector();
+
+        let trailer = if block.is_trailer() {
+            match options.trailer_options().check(sector, &data.data) {
+                Ok(trailer) => Some(trailer),
+                Err(e) => return Ok(RestoreStatus::Failed(e)),
+            }
+        } else {
+            None
+        };
+
+        // corrupted block 0 could make magic card unselectable
+        let block0 = if block.is_manufacturer() {
+            match validate_manufacturer_block(&data.data, uid.size) {
+                Ok(block0) => Some(block0),
+                Err(e) => return Ok(RestoreStatus::Failed(e)),
+            }
+        } else {
+            None
+        };
+
+        // keys can't be read back
+        let compared = match trailer {
+            Some(_) => 6..10,
+            None => 0..16,
+        };
+
+        let key_map = match options.mode {
+            RestoreMode::Gen1a if options.dry_run => return Ok(RestoreStatus::DryRun),
+            RestoreMode::Gen1a => {
+                let res = match &block0 {
+                    Some((new_uid, manufacturer)) => {
+                        self.restore_gen1a_block0(uid, &data.data, new_uid, manufacturer)
+                            .await
+                    }
+                    None => self.restore_write(block, &data.data, compared).await,
+                };
+
+                return match res {
+                    Ok(()) => Ok(RestoreStatus::Written),
+                    Err(e) => {
+                        // failed command ends backdoor mode
+                        self.magic_gen1a_unlock().await?;
+                        Ok(RestoreStatus::Failed(e))
+                    }
+                };
+            }
+            RestoreMode::Keys(key_map) => key_map,
+        };
+
+        let access = self.restore_access(uid, sector, key_map, state).await?;
+        let key_type = match writable_key(block, access.as_ref(), key_map) {
+            Ok(key_type) => key_type,
+            Err(reason) => return Ok(RestoreStatus::Skipped(reason)),
+        };
+
+        let key = key_map.get(sector, key_type).ok_or(PCDErrorCode::Invalid)?;
+        if state.authenticated != Some(key_type) {
+            match self
+                .pcd_authenticate(key_type, sector.trailer(), key, uid)
+                .await
+            {
+                Ok(()) => state.authenticated = Some(key_type),
+                Err(e @ PCDErrorCode::AuthenticationFailed) => {
+                    state.authenticated = None;
+                    return Ok(RestoreStatus::Failed(e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if options.dry_run {
+            return Ok(RestoreStatus::DryRun);
+        }
+
+        let res = match (&trailer, &block0) {
+            (Some(trailer), _) => {
+                let trailer_options = options.trailer_options();
+                let res = self
+                    .mifare_write_sector_trailer(uid, sector, trailer, &trailer_options)
+                    .await;
+
+                // authenticated with new key
+                state.authenticated = Some(trailer_options.verify_with);
+                res
+            }
+            (None, Some((new_uid, manufacturer))) => {
+                state.authenticated = None;
+                self.restore_gen2_block0(uid, key_type, key, new_uid, manufacturer)
+                    .await
+            }
+            (None, None) => self.restore_write(block, &data.data, compared).await,
+        };
+
+        match res {
+            Ok(()) => Ok(RestoreStatus::Written),
+            Err(e) => {
+                // NAK puts card into IDLE
+                self.picc_reselect(uid).await?;
+                state.authenticated = None;
+                Ok(RestoreStatus::Failed(e))
+            }
+        }
+    }
+
+    /// Access conditions currently on card, read once per sector
+    async fn restore_access(
+        &mut self,
+        uid: &Uid,
+        sector: Sector,
+        key_map: &KeyMap,
+        state: &mut SectorState,
+    ) -> Result<Option<AccessConditions>, PCDErrorCode> {
+        if let Some(access) = state.access {
+            return Ok(access);
+        }
+
+        let access = self
+            .restore_read_access(uid, sector, key_map, state)
+            .await?;
+        state.access = Some(access);
+        Ok(access)
+    }
+
+    /// Access conditions currently on card (access bits are readable by
+    /// Key A in every configuration)
+    async fn restore_read_access(
+        &mut self,
+        uid: &Uid,
+        sector: Sector,
+        key_map: &KeyMap,
+        state: &mut SectorState,
+    ) -> Result<Option<AccessConditions>, PCDErrorCode> {
+        let Some((key_type, key)) = key_map.any(sector) else {
+            return Ok(None);
+        };
+
+        match self
+            .pcd_authenticate(key_type, sector.trailer(), key, uid)
+            .await
+        {
+            Ok(()) => state.authenticated = Some(key_type),
+            Err(PCDErrorCode::AuthenticationFailed) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let mut buff = [0; 18];
+        let mut byte_count = 18;
+        if self
+            .mifare_read(sector.trailer(), &mut buff, &mut byte_count)
+            .await
+            .is_err()
+        {
+            self.picc_reselect(uid).await?;
+            state.authenticated = None;
+            return Ok(None);
+        }
+
+        Ok(AccessConditions::parse(&buff[6..9]).ok())
+    }
+
+    /// Writes block 0 of unlocked Gen1a card (and unlocks it again), `uid`
+    /// is updated if it's written
+    async fn restore_gen1a_block0(
+        &mut self,
+        uid: &mut Uid,
+        data: &[u8; 16],
+        new_uid: &Uid,
+        manufacturer: &[u8],
+    ) -> Result<(), PCDErrorCode> {
+        self.magic_gen1a_write_block0(new_uid, manufacturer).await?;
+        *uid = new_uid.clone();
+
+        self.restore_verify(BlockAddr(0), data, 0..16).await
+    }
+
+    /// Writes block 0 of direct-write card, which is selected again with
+    /// `new_uid` (`uid` is updated) if it answers with it
+    async fn restore_gen2_block0(
+        &mut self,
+        uid: &mut Uid,
+        key_type: KeyType,
+        key: &MifareKey,
+        new_uid: &Uid,
+        manufacturer: &[u8],
+    ) -> Result<(), PCDErrorCode> {
+        // block 0 is authenticated again from fresh selection
+        self.picc_reselect(uid).await?;
+
+        let written = self
+            .magic_gen2_write_block0(uid, key_type, key, new_uid, manufacturer)
+            .await?;
+        if !written {
+            return Err(PCDErrorCode::MifareNack);
+        }
+
+        *uid = new_uid.clone();
+        self.picc_reselect(uid).await
+    }
+
+    /// Writes block and compares `compared` bytes read back
+    async fn restore_write(
+        &mut self,
+        block: BlockAddr,
+        data: &[u8; 16],
+        compared: Range<usize>,
+    ) -> Result<(), PCDErrorCode> {
+        self.mifare_write(block, data, 16).await?;
+        self.restore_verify(block, data, compared).await
+    }
+
+    /// Compares `compared` bytes of block read back
+    async fn restore_verify(
+        &mut self,
+        block: BlockAddr,
+        data: &[u8; 16],
+        compared: Range<usize>,
+    ) -> Result<(), PCDErrorCode> {
+        let mut buff = [0; 18];
+        let mut byte_count = 18;
+        self.mifare_read(block, &mut buff, &mut byte_count).await?;
+
+        if buff[compared.clone()] != data[compared] {
+            return Err(PCDErrorCode::IntegrityError);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::ReadStatus;
+
+    fn sector(index: u8) -> Sector {
+        Sector::new(index).unwrap()
+    }
+
+    fn key_map(key_a: bool, key_b: bool) -> KeyMap {
+        let mut key_map = KeyMap::default();
+        key_map.set(sector(0), KeyType::A, key_a.then_some(MifareKey::DEFAULT));
+        key_map.set(sector(0), KeyType::B, key_b.then_some(MifareKey::DEFAULT));
+        key_map
+    }
+
+    #[test]
+    fn order_trailers_last() {
+        for size in [
+            ClassicSize::Mini,
+            ClassicSize::Classic2K,
+            ClassicSize::Classic1K,
+            ClassicSize::Classic4K,
+        ] {
+            let mut seen = [false; 256];
+            let mut trailers = false;
+
+            for block in restore_order(size) {
+                trailers |= block.is_trailer();
+                assert_eq!(block.is_trailer(), trailers, "{block:?} after trailer");
+
+                assert!(!seen[block.0 as usize]);
+                seen[block.0 as usize] = true;
+            }
+
+            assert_eq!(
+                seen.iter().filter(|&&seen| seen).count(),
+                size.block_count() as usize
+            );
+        }
+
+        // large sectors of 4K card
+        let trailers = restore_order(ClassicSize::Classic4K).filter(|block| block.is_trailer());
+        assert_eq!(trailers.last(), Some(BlockAddr(255)));
+    }
+
+    #[test]
+    fn skip_rules() {
+        let mut dump = ClassicDump::new(ClassicSize::Classic1K);
+        for block in [0, 1, 3] {
+            dump.block_mut(BlockAddr(block)).status = ReadStatus::Ok;
+        }
+
+        for dry_run in [false, true] {
+            let mut options = RestoreOptions::new(RestoreMode::Gen1a);
+            options.dry_run = dry_run;
+
+            let skip = |options: &RestoreOptions, dump: &ClassicDump, block| {
+                skip_reason(dump, BlockAddr(block), options)
+            };
+
+            assert_eq!(skip(&options, &dump, 0), Some(SkipReason::Manufacturer));
+            assert_eq!(skip(&options, &dump, 1), None);
+            assert_eq!(skip(&options, &dump, 2), Some(SkipReason::NotInDump));
+
+            // trailer with zeros in place of unreadable keys
+            assert_eq!(skip(&options, &dump, 3), Some(SkipReason::KeyUnknown));
+            let mut dump = dump.clone();
+            dump.set_key_known(sector(0), KeyType::A, true);
+            assert_eq!(skip(&options, &dump, 3), Some(SkipReason::KeyUnknown));
+            dump.set_key_known(sector(0), KeyType::B, true);
+            assert_eq!(skip(&options, &dump, 3), None);
+
+            options.write_block0 = true;
+            options.write_trailers = false;
+            assert_eq!(skip(&options, &dump, 0), None);
+            assert_eq!(skip(&options, &dump, 3), Some(SkipReason::Trailer));
+            assert_eq!(skip(&options, &dump, 7), Some(SkipReason::Trailer));
+        }
+    }
+
+    #[test]
+    fn key_choice_transport() {
+        let access = AccessConditions::TRANSPORT;
+        let key = |block, key_map: &KeyMap| writable_key(BlockAddr(block), Some(&access), key_map);
+
+        assert_eq!(key(1, &key_map(true, true)), Ok(KeyType::A));
+        assert_eq!(key(3, &key_map(true, true)), Ok(KeyType::A));
+
+        // Key B is readable, so it can't be used
+        assert_eq!(key(1, &key_map(false, true)), Err(SkipReason::NotWritable));
+        assert_eq!(key(1, &key_map(false, false)), Err(SkipReason::NoKey));
+    }
+
+    #[test]
+    fn key_choice_key_b() {
+        // block 0 group writable by Key B, keys and access bits by Key B
+        let access = AccessConditions::new([0b100, 0b000, 0b111, 0b011]);
+        let key = |block, key_map: &KeyMap| writable_key(BlockAddr(block), Some(&access), key_map);
+
+        assert_eq!(key(0, &key_map(true, true)), Ok(KeyType::B));
+        assert_eq!(key(1, &key_map(true, true)), Ok(KeyType::A));
+        assert_eq!(key(2, &key_map(true, true)), Err(SkipReason::NotWritable));
+        assert_eq!(key(3, &key_map(true, true)), Ok(KeyType::B));
+        assert_eq!(key(0, &key_map(true, false)), Err(SkipReason::NotWritable));
+        assert_eq!(key(3, &key_map(true, false)), Err(SkipReason::NotWritable));
+    }
+
+    #[test]
+    fn key_choice_unknown_access() {
+        let key = |key_map: &KeyMap| writable_key(BlockAddr(1), None, key_map);
+
+        assert_eq!(key(&key_map(true, true)), Ok(KeyType::A));
+        assert_eq!(key(&key_map(false, true)), Ok(KeyType::B));
+        assert_eq!(key(&key_map(false, false)), Err(SkipReason::NoKey));
+    }
+
+    #[test]
+    fn report_counts() {
+        let mut report = RestoreReport::new(ClassicSize::Classic1K);
+        assert_eq!(report.blocks().len(), 64);
+        assert_eq!(
+            (report.written(), report.skipped(), report.failed()),
+            (0, 0, 64)
+        );
+
+        report.blocks[0] = RestoreStatus::Skipped(SkipReason::Manufacturer);
+        report.blocks[1] = RestoreStatus::Written;
+        report.blocks[2] = RestoreStatus::DryRun;
+        report.blocks[3] = RestoreStatus::Skipped(SkipReason::KeyUnknown);
+        report.blocks[4] = RestoreStatus::Failed(PCDErrorCode::IntegrityError);
+
+        // blocks outside of card aren't counted
+        report.blocks[100] = RestoreStatus::Written;
+
+        assert_eq!(report.written(), 2);
+        assert_eq!(report.skipped(), 2);
+        assert_eq!(report.failed(), 60);
+        assert_eq!(report.status(BlockAddr(2)), RestoreStatus::DryRun);
+    }
+}
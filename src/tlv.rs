@@ -0,0 +1,417 @@
+use crate::consts::PCDErrorCode;
+
+/// BER-TLV tag, multi-byte tags are stored big-endian (e.g. `0x9F38`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tag(pub u32);
+
+impl Tag {
+    /// Number of bytes of encoded tag
+    pub fn encoded_len(&self) -> usize {
+        (4 - self.0.leading_zeros() as usize / 8).max(1)
+    }
+
+    fn first_byte(&self) -> u8 {
+        (self.0 >> ((self.encoded_len() - 1) * 8)) as u8
+    }
+
+    /// Class bits (b8..b7): 0 - universal, 1 - application,
+    /// 2 - context-specific, 3 - private
+    pub fn class(&self) -> u8 {
+        self.first_byte() >> 6
+    }
+
+    pub fn is_constructed(&self) -> bool {
+        self.first_byte() & 0x20 != 0
+    }
+
+    /// Parses tag from start of `data`, returns tag and its length
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), PCDErrorCode> {
+        let first = *data.first().ok_or(PCDErrorCode::Invalid)?;
+        let mut tag = first as u32;
+        let mut len = 1;
+
+        if first & 0x1F == 0x1F {
+            loop {
+                let byte = *data.get(len).ok_or(PCDErrorCode::Invalid)?;
+                tag = (tag << 8) | byte as u32;
+                len += 1;
+
+                if byte & 0x80 == 0 {
+                    break;
+                }
+
+                if len == 4 {
+                    return Err(PCDErrorCode::Invalid);
+                }
+            }
+        }
+
+        Ok((Self(tag), len))
+    }
+
+    /// Writes encoded tag into `out`, returns its length
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, PCDErrorCode> {
+        let len = self.encoded_len();
+        if out.len() < len {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        out[..len].copy_from_slice(&self.0.to_be_bytes()[4 - len..]);
+        Ok(len)
+    }
+}
+
+/// Parses definite length (short or long form up to 4 bytes), returns
+/// length value and number of bytes it took
+pub fn parse_length(data: &[u8]) -> Result<(usize, usize), PCDErrorCode> {
+    let first = *data.first().ok_or(PCDErrorCode::Invalid)?;
+    if first & 0x80 == 0 {
+        return Ok((first as usize, 1));
+    }
+
+    // 0x80 is indefinite form, not used in smart card data
+    let count = (first & 0x7F) as usize;
+    if count == 0 || count > 4 || data.len() < 1 + count {
+        return Err(PCDErrorCode::Invalid);
+    }
+
+    let len = data[1..1 + count]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+    Ok((len, 1 + count))
+}
+
+/// Number of bytes needed to encode `len`
+pub fn length_size(len: usize) -> usize {
+    match len {
+        0..=0x7F => 1,
+        0x80..=0xFF => 2,
+        0x100..=0xFFFF => 3,
+        0x1_0000..=0xFF_FFFF => 4,
+        _ => 5,
+    }
+}
+
+/// Writes encoded length into `out`, returns number of bytes written
+pub fn encode_length(len: usize, out: &mut [u8]) -> Result<usize, PCDErrorCode> {
+    let size = length_size(len);
+    if out.len() < size {
+        return Err(PCDErrorCode::NoRoom);
+    }
+
+    if size == 1 {
+        out[0] = len as u8;
+    } else {
+        out[0] = 0x80 | (size - 1) as u8;
+        out[1..size].copy_from_slice(&(len as u32).to_be_bytes()[5 - size..]);
+    }
+
+    Ok(size)
+}
+
+/// Single data object, `value` borrows parsed buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tlv<'a> {
+    pub tag: Tag,
+    pub value: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    /// Iterator over nested data objects (empty for primitive objects)
+    pub fn children(&self) -> TlvIter<'a> {
+        match self.tag.is_constructed() {
+            true => TlvIter::new(self.value),
+            false => TlvIter::new(&[]),
+        }
+    }
+
+    /// Finds first data object with `tag` nested (at any depth) in this object
+    pub fn find(&self, tag: Tag) -> Option<Tlv<'a>> {
+        find(self.children().as_bytes(), tag)
+    }
+}
+
+/// Iterator over BER-TLV data objects on one level. Padding bytes (0x00 /
+/// 0xFF) between objects are skipped. After malformed object returns one
+/// error and then stops.
+#[derive(Debug, Clone)]
+pub struct TlvIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TlvIter<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Remaining (not parsed) bytes
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn parse_next(&mut self) -> Result<Tlv<'a>, PCDErrorCode> {
+        let (tag, tag_len) = Tag::parse(self.data)?;
+        let (len, len_len) = parse_length(&self.data[tag_len..])?;
+
+        let start = tag_len + len_len;
+        if self.data.len() - start < len {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let value = &self.data[start..start + len];
+        self.data = &self.data[start + len..];
+        Ok(Tlv { tag, value })
+    }
+}
+
+impl<'a> Iterator for TlvIter<'a> {
+    type Item = Result<Tlv<'a>, PCDErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let padding = self
+            .data
+            .iter()
+            .take_while(|&&b| b == 0x00 || b == 0xFF)
+            .count();
+        self.data = &self.data[padding..];
+
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let res = self.parse_next();
+        if res.is_err() {
+            self.data = &[];
+        }
+
+        Some(res)
+    }
+}
+
+/// Finds first data object with `tag` in `data` (depth-first, descending
+/// into constructed objects). Malformed data ends the search.
+pub fn find(data: &[u8], tag: Tag) -> Option<Tlv<'_>> {
+    for tlv in TlvIter::new(data) {
+        let tlv = tlv.ok()?;
+        if tlv.tag == tag {
+            return Some(tlv);
+        }
+
+        if tlv.tag.is_constructed() {
+            if let Some(found) = find(tlv.value, tag) {
+                return Some(found);
+            }
+        }
+    }
+
+    None
+}
+
+/// BER-TLV encoder writing into caller provided buffer
+#[derive(Debug)]
+pub struct TlvWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+/// Opened constructed object, see [`TlvWriter::begin`]
+#[derive(Debug)]
+#[must_use]
+pub struct Constructed {
+    /// Position of length byte
+    len_pos: usize,
+}
+
+impl<'a> TlvWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// Encoded data written so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.pos]
+    }
+
+    /// Writes primitive (or already encoded constructed) object
+    pub fn write(&mut self, tag: Tag, value: &[u8]) -> Result<(), PCDErrorCode> {
+        let needed = tag.encoded_len() + length_size(value.len()) + value.len();
+        if self.buf.len() - self.pos < needed {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        self.pos += tag.encode(&mut self.buf[self.pos..])?;
+        self.pos += encode_length(value.len(), &mut self.buf[self.pos..])?;
+        self.buf[self.pos..self.pos + value.len()].copy_from_slice(value);
+        self.pos += value.len();
+
+        Ok(())
+    }
+
+    /// Starts constructed object, everything written until matching
+    /// [`TlvWriter::end`] is nested inside it
+    pub fn begin(&mut self, tag: Tag) -> Result<Constructed, PCDErrorCode> {
+        if !tag.is_constructed() {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        if self.buf.len() - self.pos < tag.encoded_len() + 1 {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        self.pos += tag.encode(&mut self.buf[self.pos..])?;
+        let len_pos = self.pos;
+
+        // single length byte is reserved, content is moved if it's longer
+        self.buf[self.pos] = 0;
+        self.pos += 1;
+
+        Ok(Constructed { len_pos })
+    }
+
+    /// Finishes constructed object (objects have to be ended in reverse order)
+    pub fn end(&mut self, constructed: Constructed) -> Result<(), PCDErrorCode> {
+        let content_start = constructed.len_pos + 1;
+        let content_len = self.pos - content_start;
+        let extra = length_size(content_len) - 1;
+
+        if self.buf.len() - self.pos < extra {
+            return Err(PCDErrorCode::NoRoom);
+        }
+
+        self.buf
+            .copy_within(content_start..self.pos, content_start + extra);
+        encode_length(content_len, &mut self.buf[constructed.len_pos..])?;
+        self.pos += extra;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_parse_encode() {
+        let cases: [(&[u8], u32); 4] = [
+            (&[0x5A], 0x5A),
+            (&[0x9F, 0x38], 0x9F38),
+            (&[0xBF, 0x0C], 0xBF0C),
+            (&[0x9F, 0x81, 0x01], 0x9F8101),
+        ];
+
+        for (bytes, value) in cases {
+            assert_eq!(Tag::parse(bytes), Ok((Tag(value), bytes.len())));
+
+            let mut out = [0; 4];
+            assert_eq!(Tag(value).encode(&mut out), Ok(bytes.len()));
+            assert_eq!(&out[..bytes.len()], bytes);
+        }
+
+        assert!(Tag(0xBF0C).is_constructed());
+        assert!(!Tag(0x9F38).is_constructed());
+        assert_eq!(Tag(0x9F38).class(), 2);
+
+        // missing subsequent byte, longer than 4 bytes (doesn't fit u32)
+        assert_eq!(Tag::parse(&[0x9F]), Err(PCDErrorCode::Invalid));
+        assert_eq!(
+            Tag::parse(&[0x9F, 0x81, 0x81, 0x81, 0x01]),
+            Err(PCDErrorCode::Invalid)
+        );
+    }
+
+    #[test]
+    fn length_parse_encode() {
+        let cases: [(&[u8], usize); 4] = [
+            (&[0x7F], 0x7F),
+            (&[0x81, 0x80], 0x80),
+            (&[0x82, 0x01, 0x00], 0x100),
+            (&[0x83, 0x01, 0x00, 0x00], 0x1_0000),
+        ];
+
+        for (bytes, len) in cases {
+            assert_eq!(parse_length(bytes), Ok((len, bytes.len())));
+            assert_eq!(length_size(len), bytes.len());
+
+            let mut out = [0; 5];
+            assert_eq!(encode_length(len, &mut out), Ok(bytes.len()));
+            assert_eq!(&out[..bytes.len()], bytes);
+        }
+
+        // indefinite form, truncated long form
+        assert_eq!(parse_length(&[0x80]), Err(PCDErrorCode::Invalid));
+        assert_eq!(parse_length(&[0x82, 0x01]), Err(PCDErrorCode::Invalid));
+    }
+
+    #[test]
+    fn iter_padding_and_errors() {
+        let data = [0x00, 0x5A, 0x02, 0x12, 0x34, 0xFF, 0xFF, 0x50, 0x05, 0x41];
+        let mut iter = TlvIter::new(&data);
+
+        let tlv = iter.next().unwrap().unwrap();
+        assert_eq!(tlv.tag, Tag(0x5A));
+        assert_eq!(tlv.value, &[0x12, 0x34]);
+
+        // value is longer than remaining data
+        assert_eq!(iter.next(), Some(Err(PCDErrorCode::Invalid)));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn writer_long_lengths() {
+        let short = [0x11; 200];
+        let long = [0x22; 300];
+
+        let mut buf = [0; 1024];
+        let mut writer = TlvWriter::new(&mut buf);
+        let template = writer.begin(Tag(0x70)).unwrap();
+        writer.write(Tag(0x5A), &short).unwrap();
+
+        let nested = writer.begin(Tag(0xBF0C)).unwrap();
+        writer.write(Tag(0x9F10), &long).unwrap();
+        writer.end(nested).unwrap();
+        writer.end(template).unwrap();
+
+        // 0x5A: 1 + 2 + 200, 0x9F10: 2 + 3 + 300, 0xBF0C: 2 + 3 + 305
+        let bytes = writer.as_bytes();
+        assert_eq!(&bytes[..4], &[0x70, 0x82, 0x02, 0x01]);
+        assert_eq!(bytes.len(), 4 + 513);
+
+        let template = TlvIter::new(bytes).next().unwrap().unwrap();
+        assert_eq!(template.tag, Tag(0x70));
+
+        let mut children = template.children();
+        let pan = children.next().unwrap().unwrap();
+        assert_eq!((pan.tag, pan.value), (Tag(0x5A), &short[..]));
+
+        let nested = children.next().unwrap().unwrap();
+        assert_eq!(nested.tag, Tag(0xBF0C));
+        assert_eq!(nested.value.len(), 305);
+        assert!(children.next().is_none());
+
+        assert_eq!(template.find(Tag(0x9F10)).unwrap().value, &long[..]);
+    }
+
+    #[test]
+    fn writer_no_room() {
+        // content over 127 bytes needs one more length byte on `end`
+        let mut buf = [0; 205];
+        let mut writer = TlvWriter::new(&mut buf);
+        let template = writer.begin(Tag(0x70)).unwrap();
+        writer.write(Tag(0x5A), &[0; 200]).unwrap();
+        assert_eq!(writer.end(template), Err(PCDErrorCode::NoRoom));
+
+        let mut buf = [0; 4];
+        let mut writer = TlvWriter::new(&mut buf);
+        assert_eq!(writer.write(Tag(0x5A), &[0; 3]), Err(PCDErrorCode::NoRoom));
+        assert_eq!(writer.begin(Tag(0x5A)).err(), Some(PCDErrorCode::Invalid));
+    }
+}
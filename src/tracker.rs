@@ -0,0 +1,305 @@
+#[cfg(feature = "embassy-sync")]
+use embassy_sync::{blocking_mutex::raw::RawMutex, channel::Sender};
+use embedded_hal::digital::OutputPin;
+
+use crate::{
+    consts::{PCDErrorCode, Uid},
+    MFRC522,
+};
+
+#[derive(Debug, Clone)]
+pub enum CardEvent {
+    /// New card was detected (after `debounce` consecutive reads)
+    CardArrived(Uid),
+
+    /// Tracked card still answers (every poll, only if
+    /// [`CardTrackerConfig::report_still_present`] is set)
+    CardStillPresent,
+
+    /// Tracked card didn't answer for `removal_timeout_ms`
+    CardRemoved(Uid),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CardTrackerConfig {
+    /// How many consecutive reads of the same uid are needed to report arrival
+    pub debounce: u8,
+
+    /// How long card can be silent before it's reported as removed
+    pub removal_timeout_ms: u64,
+
+    /// Sleep between polls in [`CardTracker::next_event`]
+    pub poll_interval_ms: u64,
+
+    /// Emit `CardStillPresent` on every poll that sees tracked card,
+    /// otherwise only arrival and removal are reported
+    pub report_still_present: bool,
+}
+
+impl Default for CardTrackerConfig {
+    fn default() -> Self {
+        Self {
+            debounce: 2,
+            removal_timeout_ms: 200,
+            poll_interval_ms: 20,
+            report_still_present: false,
+        }
+    }
+}
+
+/// Card presence tracker, emits arrival/removal events instead of
+/// re-detecting the same card on every poll.
+///
+/// Card is halted after every poll, so it's woken up again using WUPA
+/// (which also means that cards halted by application code are reported too).
+pub struct CardTracker {
+    config: CardTrackerConfig,
+
+    present: Option<Uid>,
+    last_seen: u64,
+
+    candidate: Option<Uid>,
+    candidate_reads: u8,
+}
+
+impl CardTracker {
+    pub fn new(config: CardTrackerConfig) -> Self {
+        Self {
+            config,
+            present: None,
+            last_seen: 0,
+            candidate: None,
+            candidate_reads: 0,
+        }
+    }
+
+    /// Currently tracked card
+    pub fn current(&self) -> Option<&Uid> {
+        self.present.as_ref()
+    }
+
+    /// Forget tracked card without emitting `CardRemoved`
+    pub fn reset(&mut self) {
+        self.present = None;
+        self.candidate = None;
+        self.candidate_reads = 0;
+    }
+
+    /// Waits (polling every `poll_interval_ms`) until next event
+    pub async fn next_event<S, C>(&mut self, mfrc522: &mut MFRC522<S, C>) -> CardEvent
+    where
+        S: embedded_hal::spi::SpiDevice,
+        C: OutputPin,
+    {
+        loop {
+            if let Some(event) = self.poll(mfrc522).await {
+                return event;
+            }
+
+            mfrc522.sleep(self.config.poll_interval_ms).await;
+        }
+    }
+
+    /// Forwards every event to `sender` (waits if channel is full), meant to be
+    /// run in a separate task owning the reader. With `report_still_present`
+    /// the channel receives event on every poll while card is present.
+    #[cfg(feature = "embassy-sync")]
+    pub async fn run<S, C, M, const N: usize>(
+        &mut self,
+        mfrc522: &mut MFRC522<S, C>,
+        sender: Sender<'_, M, CardEvent, N>,
+    ) -> !
+    where
+        S: embedded_hal::spi::SpiDevice,
+        C: OutputPin,
+        M: RawMutex,
+    {
+        loop {
+            let event = self.next_event(mfrc522).await;
+            sender.send(event).await;
+        }
+    }
+
+    /// Single presence check, returns `None` if nothing changed yet
+    /// (card still debouncing or not yet timed out).
+    pub async fn poll<S, C>(&mut self, mfrc522: &mut MFRC522<S, C>) -> Option<CardEvent>
+    where
+        S: embedded_hal::spi::SpiDevice,
+        C: OutputPin,
+    {
+        let card = probe_card(mfrc522).await.ok();
+        let now = (mfrc522.get_current_time)();
+
+        self.update(card, now)
+    }
+
+    /// State transition by result of single probe (`now` in microseconds)
+    fn update(&mut self, card: Option<Uid>, now: u64) -> Option<CardEvent> {
+        if let Some(present) = &self.present {
+            if card.as_ref() == Some(present) {
+                self.last_seen = now;
+                return self
+                    .config
+                    .report_still_present
+                    .then_some(CardEvent::CardStillPresent);
+            }
+
+            if now.saturating_sub(self.last_seen) >= self.config.removal_timeout_ms * 1_000 {
+                return self.present.take().map(CardEvent::CardRemoved);
+            }
+
+            return None;
+        }
+
+        let Some(uid) = card else {
+            self.candidate = None;
+            self.candidate_reads = 0;
+            return None;
+        };
+
+        match &self.candidate {
+            Some(candidate) if *candidate == uid => {
+                self.candidate_reads = self.candidate_reads.saturating_add(1);
+            }
+            _ => {
+                self.candidate_reads = 1;
+            }
+        }
+        self.candidate = Some(uid);
+
+        if self.candidate_reads >= self.config.debounce {
+            self.candidate_reads = 0;
+            self.last_seen = now;
+            self.present = self.candidate.take();
+
+            return self.present.clone().map(CardEvent::CardArrived);
+        }
+
+        None
+    }
+}
+
+impl Default for CardTracker {
+    fn default() -> Self {
+        Self::new(CardTrackerConfig::default())
+    }
+}
+
+/// WUPA (not REQA), because card is left halted after every probe
+async fn probe_card<S, C>(mfrc522: &mut MFRC522<S, C>) -> Result<Uid, PCDErrorCode>
+where
+    S: embedded_hal::spi::SpiDevice,
+    C: OutputPin,
+{
+    mfrc522.pcd_stop_crypto1().await?;
+    mfrc522.picc_wakeup_a().await?;
+
+    let uid = mfrc522.get_card().await?;
+    _ = mfrc522.picc_halta().await;
+
+    Ok(uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000;
+
+    fn uid(last: u8) -> Uid {
+        Uid::from_bytes(&[0x04, 0x11, 0x22, last]).unwrap()
+    }
+
+    fn arrived(tracker: &mut CardTracker, card: Uid, now: u64) {
+        assert!(tracker.update(Some(card.clone()), now).is_none());
+        assert!(matches!(
+            tracker.update(Some(card.clone()), now + 20 * MS),
+            Some(CardEvent::CardArrived(uid)) if uid == card
+        ));
+    }
+
+    #[test]
+    fn debounce() {
+        let mut tracker = CardTracker::new(CardTrackerConfig {
+            debounce: 3,
+            ..Default::default()
+        });
+
+        // interrupted reads start over
+        assert!(tracker.update(Some(uid(1)), 0).is_none());
+        assert!(tracker.update(Some(uid(1)), 20 * MS).is_none());
+        assert!(tracker.update(None, 40 * MS).is_none());
+        assert!(tracker.update(Some(uid(1)), 60 * MS).is_none());
+        assert!(tracker.update(Some(uid(2)), 80 * MS).is_none());
+        assert!(tracker.update(Some(uid(2)), 100 * MS).is_none());
+        assert!(matches!(
+            tracker.update(Some(uid(2)), 120 * MS),
+            Some(CardEvent::CardArrived(card)) if card == uid(2)
+        ));
+        assert_eq!(tracker.current(), Some(&uid(2)));
+    }
+
+    #[test]
+    fn no_still_present_by_default() {
+        let mut tracker = CardTracker::default();
+        arrived(&mut tracker, uid(1), 0);
+
+        for i in 2..10 {
+            assert!(tracker.update(Some(uid(1)), i * 20 * MS).is_none());
+        }
+
+        let mut tracker = CardTracker::new(CardTrackerConfig {
+            report_still_present: true,
+            ..Default::default()
+        });
+        arrived(&mut tracker, uid(1), 0);
+        assert!(matches!(
+            tracker.update(Some(uid(1)), 40 * MS),
+            Some(CardEvent::CardStillPresent)
+        ));
+    }
+
+    #[test]
+    fn removal_after_timeout() {
+        let mut tracker = CardTracker::default();
+        arrived(&mut tracker, uid(1), 0);
+
+        // missed polls shorter than timeout are tolerated
+        assert!(tracker.update(None, 100 * MS).is_none());
+        assert!(tracker.update(Some(uid(1)), 150 * MS).is_none());
+        assert!(tracker.update(None, 300 * MS).is_none());
+        assert!(matches!(
+            tracker.update(None, 350 * MS),
+            Some(CardEvent::CardRemoved(card)) if card == uid(1)
+        ));
+        assert_eq!(tracker.current(), None);
+        assert!(tracker.update(None, 400 * MS).is_none());
+    }
+
+    #[test]
+    fn card_swap() {
+        let mut tracker = CardTracker::default();
+        arrived(&mut tracker, uid(1), 0);
+
+        // other card doesn't keep tracked one present
+        assert!(tracker.update(Some(uid(2)), 100 * MS).is_none());
+        assert!(matches!(
+            tracker.update(Some(uid(2)), 220 * MS),
+            Some(CardEvent::CardRemoved(card)) if card == uid(1)
+        ));
+
+        arrived(&mut tracker, uid(2), 240 * MS);
+        assert_eq!(tracker.current(), Some(&uid(2)));
+    }
+
+    #[test]
+    fn reset_forgets_card() {
+        let mut tracker = CardTracker::default();
+        arrived(&mut tracker, uid(1), 0);
+        tracker.reset();
+
+        assert_eq!(tracker.current(), None);
+        assert!(tracker.update(None, 1_000 * MS).is_none());
+        arrived(&mut tracker, uid(1), 1_000 * MS);
+    }
+}
@@ -0,0 +1,255 @@
+use core::{
+    fmt::{self, Write},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use heapless::String;
+
+use crate::consts::{Atqa, PCDErrorCode, Uid, UidSize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// First received byte is the most significant one (as printed on most cards)
+    BigEndian,
+
+    /// First received byte is the least significant one ("reversed" uid)
+    LittleEndian,
+}
+
+/// Decimal formats used by access-control readers.
+/// All of them except `Decimal` require 4 byte uid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecimalFormat {
+    /// Whole uid as decimal number, without padding
+    Decimal(ByteOrder),
+
+    /// 10 digit decimal of reversed uid (what most USB readers "type")
+    TenDigit,
+
+    /// 8 hex digits -> 10 decimal digits (big endian)
+    EightH10D,
+
+    /// Last 6 hex digits -> 8 decimal digits
+    SixH8D,
+
+    /// Wiegand 26 style "FFF,CCCCC" (facility code, card number)
+    Wiegand26,
+}
+
+impl Uid {
+    /// Uid without SAK/ATQA info (for example parsed from user input)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PCDErrorCode> {
+        if UidSize::from_byte(bytes.len() as u8).is_none() {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        let mut uid_bytes = [0; 10];
+        uid_bytes[..bytes.len()].copy_from_slice(bytes);
+
+        Ok(Self {
+            size: bytes.len() as u8,
+            uid_bytes,
+            sak: 0,
+            atqa: Atqa::default(),
+        })
+    }
+
+    /// Parses hex string, bytes can be separated using `:`, `-` or spaces
+    /// (e.g. `04:A2:3B:1A:5C:80:00` or `DEADBEEF`)
+    pub fn from_hex(hex: &str) -> Result<Self, PCDErrorCode> {
+        let mut bytes = [0; 10];
+        let mut len = 0;
+        let mut high: Option<u8> = None;
+
+        for c in hex.chars() {
+            if matches!(c, ':' | '-' | ' ') {
+                if high.is_some() {
+                    return Err(PCDErrorCode::Invalid);
+                }
+
+                continue;
+            }
+
+            let nibble = c.to_digit(16).ok_or(PCDErrorCode::Invalid)? as u8;
+            match high.take() {
+                Some(h) => {
+                    if len >= bytes.len() {
+                        return Err(PCDErrorCode::Invalid);
+                    }
+
+                    bytes[len] = (h << 4) | nibble;
+                    len += 1;
+                }
+                None => high = Some(nibble),
+            }
+        }
+
+        if high.is_some() {
+            return Err(PCDErrorCode::Invalid);
+        }
+
+        Self::from_bytes(&bytes[..len])
+    }
+
+    /// Uid bytes in received order
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.uid_bytes[..(self.size as usize).min(self.uid_bytes.len())]
+    }
+
+    /// Little endian number (kept for compatibility, see [`Uid::to_number`])
+    pub fn get_number(&self) -> u128 {
+        self.to_number(ByteOrder::LittleEndian)
+    }
+
+    pub fn to_number(&self, order: ByteOrder) -> u128 {
+        let bytes = self.as_bytes();
+        match order {
+            ByteOrder::BigEndian => bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u128),
+            ByteOrder::LittleEndian => bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u128),
+        }
+    }
+
+    /// 4 byte uid as u32, `None` for longer uids
+    pub fn to_u32(&self, order: ByteOrder) -> Option<u32> {
+        if self.size != 4 {
+            return None;
+        }
+
+        Some(self.to_number(order) as u32)
+    }
+
+    pub fn to_decimal(&self, format: DecimalFormat) -> Result<String<40>, PCDErrorCode> {
+        let mut out = String::new();
+
+        // only 4 byte uids have reader formats
+        let be = || {
+            self.to_u32(ByteOrder::BigEndian)
+                .ok_or(PCDErrorCode::Invalid)
+        };
+        match format {
+            DecimalFormat::Decimal(order) => _ = write!(out, "{}", self.to_number(order)),
+            DecimalFormat::TenDigit => _ = write!(out, "{:010}", be()?.swap_bytes()),
+            DecimalFormat::EightH10D => _ = write!(out, "{:010}", be()?),
+            DecimalFormat::SixH8D => _ = write!(out, "{:08}", be()? & 0xFFFFFF),
+            DecimalFormat::Wiegand26 => {
+                let be = be()?;
+                _ = write!(out, "{:03},{:05}", (be >> 16) & 0xFF, be & 0xFFFF)
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Uids are equal when their bytes are equal (SAK and ATQA are ignored)
+impl PartialEq for Uid {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Uid {}
+
+impl Hash for Uid {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+/// Hex bytes separated with `:` (e.g. `04:A2:3B:1A:5C:80:00`),
+/// alternate flag (`{:#}`) prints them without separators.
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.as_bytes().iter().enumerate() {
+            if i != 0 && !f.alternate() {
+                f.write_char(':')?;
+            }
+
+            write!(f, "{b:02X}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Uid {
+    type Err = PCDErrorCode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_separators() {
+        let uid: Uid = "DE:AD:BE:EF".parse().unwrap();
+        assert_eq!(uid.as_bytes(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        assert_eq!(Uid::from_hex("de-ad-be-ef").unwrap(), uid);
+        assert_eq!(Uid::from_hex("DE AD BE EF").unwrap(), uid);
+        assert_eq!(Uid::from_hex("DEADBEEF").unwrap(), uid);
+    }
+
+    #[test]
+    fn from_hex_invalid() {
+        // odd nibble count, also split by separator
+        assert_eq!(Uid::from_hex("DEADBEE"), Err(PCDErrorCode::Invalid));
+        assert_eq!(Uid::from_hex("D:EADBEEF"), Err(PCDErrorCode::Invalid));
+        assert_eq!(Uid::from_hex("DEADBEXF"), Err(PCDErrorCode::Invalid));
+
+        // only 4, 7 and 10 byte uids exist
+        assert_eq!(Uid::from_hex("DEADBE"), Err(PCDErrorCode::Invalid));
+        assert_eq!(Uid::from_hex(""), Err(PCDErrorCode::Invalid));
+        assert_eq!(
+            Uid::from_hex("00112233445566778899AA"),
+            Err(PCDErrorCode::Invalid)
+        );
+    }
+
+    #[test]
+    fn ten_byte_uid() {
+        let uid = Uid::from_hex("00:11:22:33:44:55:66:77:88:99").unwrap();
+        assert_eq!(uid.size, 10);
+        assert_eq!(uid.to_u32(ByteOrder::BigEndian), None);
+        assert_eq!(uid.to_number(ByteOrder::BigEndian), 0x00112233445566778899);
+
+        let mut text: String<32> = String::new();
+        write!(text, "{uid}").unwrap();
+        assert_eq!(text, "00:11:22:33:44:55:66:77:88:99");
+
+        text.clear();
+        write!(text, "{uid:#}").unwrap();
+        assert_eq!(text, "00112233445566778899");
+        assert_eq!(text.parse::<Uid>().unwrap(), uid);
+    }
+
+    #[test]
+    fn to_decimal() {
+        let uid = Uid::from_hex("DEADBEEF").unwrap();
+        let decimal = |format| uid.to_decimal(format).unwrap();
+
+        assert_eq!(
+            decimal(DecimalFormat::Decimal(ByteOrder::BigEndian)),
+            "3735928559"
+        );
+        assert_eq!(
+            decimal(DecimalFormat::Decimal(ByteOrder::LittleEndian)),
+            "4022250974"
+        );
+        assert_eq!(decimal(DecimalFormat::TenDigit), "4022250974");
+        assert_eq!(decimal(DecimalFormat::EightH10D), "3735928559");
+        assert_eq!(decimal(DecimalFormat::SixH8D), "11386607");
+        assert_eq!(decimal(DecimalFormat::Wiegand26), "173,48879");
+
+        let uid = Uid::from_hex("04A23B1A5C8000").unwrap();
+        assert_eq!(
+            uid.to_decimal(DecimalFormat::TenDigit),
+            Err(PCDErrorCode::Invalid)
+        );
+    }
+}